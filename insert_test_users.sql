@@ -1,20 +1,23 @@
 INSERT INTO
-    users (username, password, email_address)
+    users (username, password, password_scheme, role, email_address)
 VALUES
     (
         'user1',
         -- test
         '7b3d979ca8330a94fa7e9e1b466d8b99e0bcdea1ec90596c0dcc8d7ef6b4300c',
+        'sha256',
+        'admin',
         'email@domain.com'
     );
 
 INSERT INTO
-    users (username, password, first_name, last_name)
+    users (username, password, password_scheme, first_name, last_name)
 VALUES
     (
         'user2',
         -- quiz
         'b63c3b5c53c87c9fd8c279976d924b152fe39d2a79c80035aae1673f02d26439',
+        'sha256',
         'My',
         'User'
     );
\ No newline at end of file
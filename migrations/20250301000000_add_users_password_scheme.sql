@@ -0,0 +1,2 @@
+-- Existing passwords are unsalted SHA-256 digests and get upgraded to Argon2id on the next login
+ALTER TABLE users ADD COLUMN password_scheme TEXT NOT NULL DEFAULT 'sha256';
@@ -0,0 +1,3 @@
+-- Kept apart from adding the column, since CockroachDB cannot change a column in the same
+-- transaction that adds it
+ALTER TABLE users ALTER COLUMN password_scheme SET DEFAULT 'argon2id';
@@ -0,0 +1,3 @@
+-- Make sure somebody can still manage users and categories once roles are enforced. Kept apart
+-- from adding the column, since CockroachDB cannot write a column in the transaction that adds it.
+UPDATE users SET role = 'admin' WHERE id = (SELECT MIN(id) FROM users);
@@ -0,0 +1,11 @@
+CREATE TABLE ticket_events (
+    id BIGSERIAL PRIMARY KEY,
+    ticket_id BIGINT REFERENCES tickets (id) NOT NULL,
+    actor_id BIGINT REFERENCES users (id) NOT NULL,
+    field TEXT NOT NULL,
+    old_value TEXT,
+    new_value TEXT,
+    changed TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX ticket_events_ticket_id_idx ON ticket_events (ticket_id);
@@ -0,0 +1,2 @@
+ALTER TABLE tickets ADD COLUMN search_vector TSVECTOR NOT NULL DEFAULT ''::tsvector;
+ALTER TABLE comments ADD COLUMN search_vector TSVECTOR NOT NULL DEFAULT ''::tsvector;
@@ -0,0 +1,6 @@
+-- The application strips HTML before indexing with the same pattern as service::tickets::HTML_TAG_PATTERN
+UPDATE tickets SET search_vector =
+    setweight(to_tsvector('english', subject), 'A') ||
+    setweight(to_tsvector('english', regexp_replace(coalesce(description, ''), '</?[^>]+(>|$)|&nbsp;', ' ', 'g')), 'B');
+UPDATE comments SET search_vector =
+    to_tsvector('english', regexp_replace(content, '</?[^>]+(>|$)|&nbsp;', ' ', 'g'));
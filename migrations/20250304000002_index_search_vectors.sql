@@ -0,0 +1,4 @@
+-- Indexed once the vectors are filled in, in a transaction of its own like the backfill, since
+-- CockroachDB does not mix schema changes with writes to the same columns
+CREATE INDEX tickets_search_vector_idx ON tickets USING GIN (search_vector);
+CREATE INDEX comments_search_vector_idx ON comments USING GIN (search_vector);
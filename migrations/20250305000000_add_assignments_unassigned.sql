@@ -0,0 +1,2 @@
+ALTER TABLE assignments ADD COLUMN unassigned TIMESTAMP;
+ALTER TABLE assignments ADD COLUMN unassigner_id BIGINT REFERENCES users (id);
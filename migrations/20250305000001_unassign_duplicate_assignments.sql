@@ -0,0 +1,9 @@
+-- Assignment updates used to overwrite every row of a ticket with the same assignee,
+-- so only keep the oldest of any duplicates as the current assignment.
+UPDATE assignments SET unassigned = CURRENT_TIMESTAMP, unassigner_id = assigner_id
+WHERE EXISTS (
+    SELECT 1 FROM assignments AS older
+    WHERE older.ticket_id = assignments.ticket_id
+    AND older.assignee_id = assignments.assignee_id
+    AND older.id < assignments.id
+);
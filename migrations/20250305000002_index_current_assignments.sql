@@ -0,0 +1,3 @@
+-- Only unique once the duplicates are unassigned, which CockroachDB needs to have committed first
+CREATE UNIQUE INDEX assignments_current_ticket_id_assignee_id_idx
+ON assignments (ticket_id, assignee_id) WHERE unassigned IS NULL;
@@ -0,0 +1,45 @@
+CREATE TABLE statuses (
+    name TEXT PRIMARY KEY,
+    open BOOLEAN DEFAULT TRUE NOT NULL,
+    position INTEGER DEFAULT 0 NOT NULL
+);
+
+CREATE TABLE priorities (
+    name TEXT PRIMARY KEY,
+    position INTEGER DEFAULT 0 NOT NULL
+);
+
+-- Transitions without a category make up the default workflow, which is used by every category
+-- that has no transitions of its own. Transitions without a from_status are the statuses new
+-- tickets can start in.
+CREATE TABLE status_transitions (
+    id BIGSERIAL PRIMARY KEY,
+    category_id BIGINT REFERENCES categories (id),
+    from_status TEXT REFERENCES statuses (name),
+    to_status TEXT REFERENCES statuses (name) NOT NULL
+);
+
+CREATE INDEX status_transitions_category_id_idx ON status_transitions (category_id);
+
+INSERT INTO statuses (name, open, position) VALUES
+    ('Backlog', TRUE, 0),
+    ('In Progress', TRUE, 1),
+    ('Not Deployed Yet', TRUE, 2),
+    ('Done', FALSE, 3),
+    ('Closed', FALSE, 4);
+
+INSERT INTO statuses (name, position)
+SELECT DISTINCT status, 5 FROM tickets WHERE status NOT IN (SELECT name FROM statuses);
+
+INSERT INTO priorities (name, position) VALUES ('Low', 0), ('Medium', 1), ('High', 2);
+
+INSERT INTO priorities (name, position)
+SELECT DISTINCT priority, 3 FROM tickets WHERE priority NOT IN (SELECT name FROM priorities);
+
+-- Tickets could move between any statuses before, so keep allowing that by default
+INSERT INTO status_transitions (from_status, to_status)
+SELECT NULL, name FROM statuses
+UNION ALL
+SELECT from_statuses.name, to_statuses.name
+FROM statuses AS from_statuses CROSS JOIN statuses AS to_statuses
+WHERE from_statuses.name <> to_statuses.name;
@@ -0,0 +1,4 @@
+-- Added once the statuses and priorities of existing tickets are committed, since CockroachDB
+-- validates foreign keys against them outside of the transaction that creates them
+ALTER TABLE tickets ADD CONSTRAINT tickets_status_fkey FOREIGN KEY (status) REFERENCES statuses (name);
+ALTER TABLE tickets ADD CONSTRAINT tickets_priority_fkey FOREIGN KEY (priority) REFERENCES priorities (name);
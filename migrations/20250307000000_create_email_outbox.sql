@@ -0,0 +1,14 @@
+CREATE TABLE email_outbox (
+    id BIGSERIAL PRIMARY KEY,
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    body TEXT NOT NULL,
+    status TEXT DEFAULT 'pending' NOT NULL,
+    attempts INTEGER DEFAULT 0 NOT NULL,
+    last_error TEXT,
+    next_attempt TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    created TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    sent TIMESTAMP
+);
+
+CREATE INDEX email_outbox_status_next_attempt_idx ON email_outbox (status, next_attempt);
@@ -0,0 +1,23 @@
+CREATE TABLE ticket_watchers (
+    ticket_id BIGINT REFERENCES tickets (id) NOT NULL,
+    user_id BIGINT REFERENCES users (id) NOT NULL,
+    created TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (ticket_id, user_id)
+);
+
+CREATE INDEX ticket_watchers_user_id_idx ON ticket_watchers (user_id);
+
+INSERT INTO ticket_watchers (ticket_id, user_id)
+SELECT id, reporter_id FROM tickets
+UNION
+SELECT ticket_id, assignee_id FROM assignments WHERE unassigned IS NULL
+UNION
+SELECT ticket_id, commenter_id FROM comments;
+
+-- Events without a row are emailed
+CREATE TABLE notification_preferences (
+    user_id BIGINT REFERENCES users (id) NOT NULL,
+    event TEXT NOT NULL,
+    email BOOLEAN NOT NULL,
+    PRIMARY KEY (user_id, event)
+);
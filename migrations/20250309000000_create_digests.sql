@@ -0,0 +1,16 @@
+-- Users without a row get every notification as its own email
+CREATE TABLE digest_settings (
+    user_id BIGINT PRIMARY KEY REFERENCES users (id),
+    frequency TEXT NOT NULL,
+    last_sent TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE TABLE digest_items (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT REFERENCES users (id) NOT NULL,
+    ticket_id BIGINT REFERENCES tickets (id) NOT NULL,
+    body TEXT NOT NULL,
+    created TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX digest_items_user_id_idx ON digest_items (user_id);
@@ -0,0 +1,4 @@
+-- Plain-text alternatives of the HTML bodies, missing for emails queued before they existed
+ALTER TABLE email_outbox ADD COLUMN text_body TEXT;
+
+ALTER TABLE digest_items ADD COLUMN text_body TEXT;
@@ -0,0 +1,15 @@
+ALTER TABLE comments ADD COLUMN edited TIMESTAMP;
+
+-- Deleted comments are kept, but only shown as a placeholder
+ALTER TABLE comments ADD COLUMN deleted TIMESTAMP;
+
+-- The content a comment had before each edit
+CREATE TABLE comment_revisions (
+    id BIGSERIAL PRIMARY KEY,
+    comment_id BIGINT REFERENCES comments (id) NOT NULL,
+    content TEXT NOT NULL,
+    editor_id BIGINT REFERENCES users (id) NOT NULL,
+    revised TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX comment_revisions_comment_id_idx ON comment_revisions (comment_id);
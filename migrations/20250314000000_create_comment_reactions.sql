@@ -0,0 +1,10 @@
+-- Comments without a parent are top-level, the others are replies in its thread
+ALTER TABLE comments ADD COLUMN parent_comment_id BIGINT REFERENCES comments (id);
+
+CREATE TABLE comment_reactions (
+    comment_id BIGINT REFERENCES comments (id) NOT NULL,
+    user_id BIGINT REFERENCES users (id) NOT NULL,
+    emoji TEXT NOT NULL,
+    created TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (comment_id, user_id, emoji)
+);
@@ -0,0 +1,2 @@
+-- Separate from the column it indexes, which CockroachDB has to commit before indexing it
+CREATE INDEX comments_parent_comment_id_idx ON comments (parent_comment_id);
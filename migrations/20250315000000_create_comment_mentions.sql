@@ -0,0 +1,9 @@
+-- The users a comment mentions as @username in its current content
+CREATE TABLE comment_mentions (
+    comment_id BIGINT REFERENCES comments (id) NOT NULL,
+    user_id BIGINT REFERENCES users (id) NOT NULL,
+    mentioned TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (comment_id, user_id)
+);
+
+CREATE INDEX comment_mentions_user_id_idx ON comment_mentions (user_id, mentioned);
@@ -0,0 +1,10 @@
+-- Descriptions and comments are written as 'html' or 'markdown'. The source is what was written,
+-- description and content are the sanitized HTML it renders to.
+ALTER TABLE tickets ADD COLUMN content_format TEXT NOT NULL DEFAULT 'html';
+ALTER TABLE tickets ADD COLUMN description_source TEXT;
+
+ALTER TABLE comments ADD COLUMN content_format TEXT NOT NULL DEFAULT 'html';
+ALTER TABLE comments ADD COLUMN content_source TEXT;
+
+ALTER TABLE comment_revisions ADD COLUMN content_format TEXT NOT NULL DEFAULT 'html';
+ALTER TABLE comment_revisions ADD COLUMN content_source TEXT;
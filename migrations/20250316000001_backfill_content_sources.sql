@@ -0,0 +1,4 @@
+-- Everything written so far was HTML, so it is its own source
+UPDATE tickets SET description_source = description;
+UPDATE comments SET content_source = content;
+UPDATE comment_revisions SET content_source = content;
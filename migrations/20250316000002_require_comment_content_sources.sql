@@ -0,0 +1,3 @@
+-- Only possible once the backfilled sources are committed on CockroachDB
+ALTER TABLE comments ALTER COLUMN content_source SET NOT NULL;
+ALTER TABLE comment_revisions ALTER COLUMN content_source SET NOT NULL;
@@ -0,0 +1,16 @@
+-- Files uploaded to a ticket, or to one of its comments. The content is a blob in the image
+-- storage under storage_key, checksum is its SHA-256 in hex.
+CREATE TABLE attachments (
+    id BIGSERIAL PRIMARY KEY,
+    ticket_id BIGINT REFERENCES tickets (id) NOT NULL,
+    comment_id BIGINT REFERENCES comments (id),
+    owner_id BIGINT REFERENCES users (id) NOT NULL,
+    file_name TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    checksum TEXT NOT NULL,
+    storage_key TEXT UNIQUE NOT NULL,
+    uploaded TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX attachments_ticket_id_idx ON attachments (ticket_id);
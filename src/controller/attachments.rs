@@ -0,0 +1,146 @@
+use super::TiraError;
+use crate::models::success::{AlteredResourceResponse, StandardResponse};
+use crate::models::{CreateAttachment, Session, User};
+use crate::service::{self, attachments, permissions, ServiceError};
+use crate::TiraState;
+use anyhow::Result;
+use axum::extract::{Multipart, Path, State};
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS};
+use axum::http::HeaderValue;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use std::slice;
+
+/// Returns the `Content-Disposition` that makes browsers download an attachment under its name.
+///
+/// Characters that cannot be in the header are replaced.
+fn content_disposition(file_name: &str) -> String {
+    let file_name: String = file_name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    format!("attachment; filename=\"{}\"", file_name)
+}
+
+/// Endpoint for uploading a file to a ticket.
+///
+/// Requires authentication.
+///
+/// The request is `multipart/form-data` with the file in the `file` field, and optionally the
+/// id of a comment on the ticket in the `comment_id` field.
+///
+/// **POST /tickets/<ticket_id>/attachments**
+pub async fn create_attachment_endpoint(
+    State(state): State<TiraState>,
+    Extension(session): Extension<Session>,
+    Path(ticket_id): Path<i64>,
+    mut multipart: Multipart,
+) -> Result<Response, TiraError> {
+    let mut file = None;
+    let mut comment_id = None;
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| ServiceError::BadRequest(e.body_text()))?
+    {
+        match field.name() {
+            Some("file") => {
+                let file_name = field.file_name().unwrap_or_default().to_string();
+                let content_type = field
+                    .content_type()
+                    .unwrap_or(attachments::DEFAULT_CONTENT_TYPE)
+                    .to_string();
+                let data = field
+                    .bytes()
+                    .await
+                    .map_err(|e| ServiceError::BadRequest(e.body_text()))?;
+                file = Some((file_name, content_type, data.to_vec()));
+            }
+            Some("comment_id") => {
+                let text = field
+                    .text()
+                    .await
+                    .map_err(|e| ServiceError::BadRequest(e.body_text()))?;
+                let id = text.trim().parse().map_err(|_| {
+                    ServiceError::invalid_field("comment_id", "Comment id must be a number")
+                })?;
+                comment_id = Some(id);
+            }
+            _ => {}
+        }
+    }
+    let Some((file_name, content_type, data)) = file else {
+        return Err(ServiceError::invalid_field("file", "File is required").into());
+    };
+
+    let attachment = CreateAttachment {
+        comment_id,
+        file_name,
+        content_type,
+        data,
+    };
+    let id = attachments::create_attachment(&state, attachment, ticket_id, session.user_id).await?;
+
+    let message = "Successfully uploaded attachment!".to_string();
+    let response = AlteredResourceResponse { message, id };
+    Ok(Json(response).into_response())
+}
+
+/// Endpoint for retrieving the attachments of a ticket, oldest first.
+///
+/// **GET /tickets/<ticket_id>/attachments**
+pub async fn get_attachments_by_ticket_id_endpoint(
+    State(state): State<TiraState>,
+    Path(ticket_id): Path<i64>,
+) -> Result<Response, TiraError> {
+    let ticket = service::tickets::get_ticket_by_id(&state, ticket_id).await?;
+    let batch =
+        service::tickets::load_ticket_batch(&state, slice::from_ref(&ticket), Vec::new()).await?;
+    let attachments = batch.attachment_responses(ticket_id)?;
+    Ok(Json(attachments).into_response())
+}
+
+/// Endpoint for downloading an attachment of a ticket.
+///
+/// The file is sent with the content type it was uploaded with, as a download.
+///
+/// **GET /tickets/<ticket_id>/attachments/<attachment_id>**
+pub async fn get_attachment_by_id_endpoint(
+    State(state): State<TiraState>,
+    Path((ticket_id, attachment_id)): Path<(i64, i64)>,
+) -> Result<Response, TiraError> {
+    let attachment = attachments::get_attachment_by_id(&state, ticket_id, attachment_id).await?;
+    let data = attachments::load_attachment(&state, &attachment).await?;
+
+    let content_type = HeaderValue::from_str(&attachment.content_type)
+        .unwrap_or(HeaderValue::from_static(attachments::DEFAULT_CONTENT_TYPE));
+    let headers = [
+        (CONTENT_TYPE, content_type),
+        (
+            CONTENT_DISPOSITION,
+            HeaderValue::from_str(&content_disposition(&attachment.file_name))?,
+        ),
+        (X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+    ];
+    Ok((headers, data).into_response())
+}
+
+/// Endpoint for deleting an attachment of a ticket.
+///
+/// Requires authentication as the uploader of the attachment or an admin.
+///
+/// **DELETE /tickets/<ticket_id>/attachments/<attachment_id>**
+pub async fn delete_attachment_by_id_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Path((ticket_id, attachment_id)): Path<(i64, i64)>,
+) -> Result<Response, TiraError> {
+    permissions::require_attachment_owner(&state, &user, attachment_id).await?;
+    attachments::delete_attachment_by_id(&state, ticket_id, attachment_id).await?;
+    let message = format!("Successfully deleted attachment with id {}!", attachment_id);
+    let response = StandardResponse { message };
+    Ok(Json(response).into_response())
+}
@@ -1,7 +1,7 @@
 use super::TiraError;
 use crate::models::success::{AlteredResourceResponse, StandardResponse};
-use crate::models::{Category, Session};
-use crate::service::categories;
+use crate::models::{Category, Session, User};
+use crate::service::{categories, permissions};
 use crate::TiraState;
 use anyhow::Result;
 use axum::extract::{Path, Query, State};
@@ -17,14 +17,15 @@ pub struct ArchiveCategoryQueryParams {
 
 /// Endpoint for archiving a specific category.
 ///
-/// Requires authentication.
+/// Requires authentication as an admin.
 ///
 /// **DELETE /categories/<category_id>**
-
 pub async fn archive_category_by_id_endpoint(
     State(state): State<TiraState>,
+    Extension(user): Extension<User>,
     query_params: Query<ArchiveCategoryQueryParams>,
 ) -> Result<Response, TiraError> {
+    permissions::require_admin(&user)?;
     categories::archive_category_by_id(&state, query_params.category_id).await?;
 
     let message = format!(
@@ -74,7 +75,6 @@ pub struct GetCategoryQueryParams {
 /// Query Parameters:
 ///
 /// archived: Used to filter categories that are archived or not. Takes a boolean value. (optional)
-
 pub async fn get_categories_endpoint(
     State(state): State<TiraState>,
     query_params: Query<GetCategoryQueryParams>,
@@ -86,7 +86,6 @@ pub async fn get_categories_endpoint(
 /// Endpoint for retrieving a category.
 ///
 /// **GET /categories/<category_id>**
-
 pub async fn get_category_by_id_endpoint(
     State(state): State<TiraState>,
     Path(category_id): Path<i64>,
@@ -1,32 +1,37 @@
 use super::TiraError;
 use crate::models::patch::UpdateComment;
-use crate::models::success::AlteredResourceResponse;
-use crate::service;
+use crate::models::success::{AlteredResourceResponse, StandardResponse};
+use crate::models::User;
+use crate::service::{self, permissions};
 use crate::TiraState;
 use anyhow::Result;
 use axum::extract::Path;
 use axum::extract::State;
 use axum::response::IntoResponse;
 use axum::response::Response;
-use axum::Json;
+use axum::{Extension, Json};
 
 /// Endpoint for updating a comment.
 ///
-/// Requires authentication.
+/// Requires authentication as the author of the comment or an admin. The previous content is
+/// kept as a revision. Leaving out `content_format` keeps the format the comment was written in.
 ///
 /// **PATCH /comments/<comment_id>**
 ///
 /// Example JSON Body:
 ///
 /// {
-///     "content": "This is a comment"
+///     "content": "This is a *comment*",
+///     "content_format": "markdown"
 /// }
 pub async fn patch_comment_by_id_endpoint(
     State(state): State<TiraState>,
+    Extension(user): Extension<User>,
     Path(comment_id): Path<i64>,
     Json(comment): Json<UpdateComment>,
 ) -> Result<Response, TiraError> {
-    service::comments::update_comment_by_id(&state, comment, comment_id).await?;
+    permissions::require_comment_editor(&state, &user, comment_id).await?;
+    service::comments::update_comment_by_id(&state, comment, comment_id, user.id).await?;
     let message = "Successfully edited comment!".to_string();
     let response = AlteredResourceResponse {
         message,
@@ -34,3 +39,69 @@ pub async fn patch_comment_by_id_endpoint(
     };
     Ok(Json(response).into_response())
 }
+
+/// Endpoint for deleting a comment.
+///
+/// Requires authentication as the author of the comment or an admin. The comment stays in the
+/// ticket's comments with a placeholder instead of its content.
+///
+/// **DELETE /comments/<comment_id>**
+pub async fn delete_comment_by_id_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Path(comment_id): Path<i64>,
+) -> Result<Response, TiraError> {
+    permissions::require_comment_editor(&state, &user, comment_id).await?;
+    service::comments::delete_comment_by_id(&state, comment_id).await?;
+    let message = format!("Successfully deleted comment with id {}!", comment_id);
+    let response = StandardResponse { message };
+    Ok(Json(response).into_response())
+}
+
+/// Endpoint for retrieving the earlier contents of a comment, oldest first.
+///
+/// The revisions of a deleted comment can only be retrieved by admins.
+///
+/// **GET /comments/<comment_id>/revisions**
+pub async fn get_revisions_by_comment_id_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Path(comment_id): Path<i64>,
+) -> Result<Response, TiraError> {
+    let comment = service::comments::get_comment_by_id(&state, comment_id).await?;
+    if comment.deleted.is_some() {
+        permissions::require_admin(&user)?;
+    }
+    let revisions = service::comments::get_revisions_by_comment_id(&state, comment_id).await?;
+    Ok(Json(revisions).into_response())
+}
+
+/// Endpoint for reacting to a comment with an emoji.
+///
+/// Requires authentication. Returns the reactions to the comment per emoji, including whether
+/// the current user reacted.
+///
+/// **POST /comments/<comment_id>/reactions/<emoji>**
+pub async fn create_reaction_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Path((comment_id, emoji)): Path<(i64, String)>,
+) -> Result<Response, TiraError> {
+    let reactions = service::comments::create_reaction(&state, comment_id, user.id, &emoji).await?;
+    Ok(Json(reactions).into_response())
+}
+
+/// Endpoint for taking back a reaction to a comment.
+///
+/// Requires authentication. Returns the reactions to the comment per emoji, including whether
+/// the current user reacted.
+///
+/// **DELETE /comments/<comment_id>/reactions/<emoji>**
+pub async fn delete_reaction_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Path((comment_id, emoji)): Path<(i64, String)>,
+) -> Result<Response, TiraError> {
+    let reactions = service::comments::delete_reaction(&state, comment_id, user.id, &emoji).await?;
+    Ok(Json(reactions).into_response())
+}
@@ -0,0 +1,107 @@
+use super::TiraError;
+use crate::models::success::{AlteredResourceResponse, CountResponse};
+use crate::models::User;
+use crate::service::{emails, inbound_emails, permissions, security, ServiceError};
+use crate::TiraState;
+use anyhow::Result;
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use serde::Deserialize;
+
+/// Header the MTA sends the inbound email token in.
+const INBOUND_TOKEN_HEADER: &str = "x-tira-inbound-token";
+
+#[derive(Deserialize)]
+pub struct GetOutboxQueryParams {
+    status: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Endpoint for retrieving the emails in the outbox, newest first.
+///
+/// Requires authentication as an admin.
+///
+/// **GET /outbox**
+///
+/// Query Parameters:
+///
+/// status: Used to filter emails by 'pending', 'sent' or 'dead'. (optional)
+/// limit: How many emails should be retrieved (optional, default is 10)
+/// offset: The offset for the list of emails (optional, default is 0)
+pub async fn get_outbox_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Query(query_params): Query<GetOutboxQueryParams>,
+) -> Result<Response, TiraError> {
+    permissions::require_admin(&user)?;
+
+    let data = emails::get_outbox_emails(
+        &state,
+        query_params.status.clone(),
+        query_params.limit,
+        query_params.offset,
+    )
+    .await?;
+    let total_count = emails::count_outbox_emails(&state, query_params.status).await?;
+
+    let response = CountResponse { data, total_count };
+    Ok(Json(response).into_response())
+}
+
+/// Endpoint for trying to send a dead-lettered email again.
+///
+/// Requires authentication as an admin.
+///
+/// **POST /outbox/<email_id>/retry**
+pub async fn retry_outbox_email_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Path(email_id): Path<i64>,
+) -> Result<Response, TiraError> {
+    permissions::require_admin(&user)?;
+    emails::retry_outbox_email_by_id(&state, email_id).await?;
+
+    let message = "Successfully queued email again!".to_string();
+    let response = AlteredResourceResponse {
+        message,
+        id: email_id,
+    };
+    Ok(Json(response).into_response())
+}
+
+/// Endpoint for adding a reply to a notification email as a comment on its ticket.
+///
+/// Meant to be piped to by the local MTA, which authenticates with the shared token in the
+/// `X-Tira-Inbound-Token` header. The reply address is signed for the ticket and user.
+///
+/// **POST /inbound-emails**
+///
+/// The body is the raw RFC 822 message.
+pub async fn receive_email_endpoint(
+    State(state): State<TiraState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, TiraError> {
+    let Some(token) = &state.inbound_email_token else {
+        let message = "Receiving emails over HTTP is not enabled".to_string();
+        return Err(ServiceError::NotFound(message).into());
+    };
+    let given_token = headers
+        .get(INBOUND_TOKEN_HEADER)
+        .map(|value| value.as_bytes())
+        .unwrap_or_default();
+    if !security::constant_time_eq(given_token, token.as_bytes()) {
+        let message = "Invalid inbound email token".to_string();
+        return Err(ServiceError::Unauthorized(message).into());
+    }
+
+    let id = inbound_emails::receive_email(&state, &body).await?;
+
+    let message = "Successfully created comment!".to_string();
+    let response = AlteredResourceResponse { message, id };
+    Ok(Json(response).into_response())
+}
@@ -0,0 +1,41 @@
+use super::TiraError;
+use crate::models::LiveEventFilter;
+use crate::service::live_events;
+use crate::TiraState;
+use anyhow::Result;
+use axum::extract::{Query, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use tokio_stream::StreamExt;
+
+/// Endpoint for following what happens to tickets as Server-Sent Events.
+///
+/// Requires authentication.
+///
+/// Every event is named after what happened ('ticket.created', 'ticket.updated', 'comment.added'
+/// or 'assignment.changed') and carries the ids involved, so that clients can fetch what changed.
+///
+/// **GET /events**
+///
+/// Query Parameters:
+///
+/// ticket_id: Used to only follow the events of a ticket. (optional)
+/// category_id: Used to only follow the events of the tickets in a category. (optional)
+///
+/// Example Event:
+///
+/// event: comment.added
+/// data: {"event":"comment.added","ticket_id":1,"category_id":2,"actor_id":3,"comment_id":4}
+pub async fn get_events_endpoint(
+    State(state): State<TiraState>,
+    Query(filter): Query<LiveEventFilter>,
+) -> Result<Response, TiraError> {
+    let events = live_events::subscribe(&state, filter).map(|event| {
+        Event::default()
+            .event(event.event.as_str())
+            .json_data(&event)
+    });
+    Ok(Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
@@ -1,44 +1,151 @@
-use crate::{models::Session, TiraState};
+use crate::{
+    models::{
+        error::{ErrorResponse, FieldError},
+        Session,
+    },
+    service::{self, ServiceError},
+    TiraState,
+};
 use anyhow::Result;
 use axum::{
     extract::{Request, State},
-    http::StatusCode,
+    http::{Method, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
+    Json,
 };
 use axum_extra::extract::CookieJar;
+use log::error;
 pub mod assignments;
+pub mod attachments;
 pub mod categories;
 pub mod comments;
+pub mod emails;
+pub mod events;
 pub mod images;
+pub mod search;
 pub mod sessions;
 pub mod tickets;
 pub mod users;
-use anyhow::anyhow;
+pub mod webhooks;
+pub mod workflows;
 
 const TIRA_AUTH_COOKIE: &str = "tirauth";
 
-pub struct TiraError(anyhow::Error);
+// Postgres error codes for constraint violations
+const UNIQUE_VIOLATION: &str = "23505";
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+
+/// Whether a path only changes things for the current user, which even viewers may do.
+fn is_personal_path(path: &str) -> bool {
+    path == "/logout"
+        || path == "/users/current/notification-preferences"
+        || (path.starts_with("/tickets/") && path.ends_with("/watch"))
+}
+
+/// Error returned by endpoints.
+///
+/// Every variant maps to an HTTP status code and is sent to the client as an [`ErrorResponse`].
+#[derive(Debug)]
+pub enum TiraError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    Validation(Vec<FieldError>),
+    /// Anything unexpected. The error is logged but never sent to the client.
+    Internal(anyhow::Error),
+}
+
+impl TiraError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::BadRequest(_) => "bad_request",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::Forbidden(_) => "forbidden",
+            Self::NotFound(_) => "not_found",
+            Self::Conflict(_) => "conflict",
+            Self::Validation(_) => "validation_failed",
+            Self::Internal(_) => "internal_error",
+        }
+    }
+}
 
-// Tell axum how to convert `AppError` into a response.
+// Tell axum how to convert `TiraError` into a response.
 impl IntoResponse for TiraError {
     fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
-        )
-            .into_response()
+        let status_code = self.status_code();
+        let code = self.code();
+        let (message, field_errors) = match self {
+            Self::BadRequest(message)
+            | Self::Unauthorized(message)
+            | Self::Forbidden(message)
+            | Self::NotFound(message)
+            | Self::Conflict(message) => (message, Vec::new()),
+            Self::Validation(field_errors) => ("Request is invalid".to_string(), field_errors),
+            Self::Internal(err) => {
+                error!("Internal error: {:?}", err);
+                ("Something went wrong".to_string(), Vec::new())
+            }
+        };
+
+        let response = ErrorResponse {
+            code,
+            message,
+            field_errors,
+        };
+        (status_code, Json(response)).into_response()
     }
 }
 
 // This enables using `?` on functions that return `Result<_, anyhow::Error>` to turn them into
-// `Result<_, TiraError>`. That way you don't need to do that manually.
+// `Result<_, TiraError>`. Errors the service layer raised on purpose and database errors caused
+// by the request get their own status code, everything else is internal.
 impl<E> From<E> for TiraError
 where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        let err = err.into();
+
+        let err = match err.downcast::<ServiceError>() {
+            Ok(service_error) => {
+                return match service_error {
+                    ServiceError::BadRequest(message) => Self::BadRequest(message),
+                    ServiceError::Unauthorized(message) => Self::Unauthorized(message),
+                    ServiceError::Forbidden(message) => Self::Forbidden(message),
+                    ServiceError::NotFound(message) => Self::NotFound(message),
+                    ServiceError::Conflict(message) => Self::Conflict(message),
+                    ServiceError::Validation(field_errors) => Self::Validation(field_errors),
+                }
+            }
+            Err(err) => err,
+        };
+
+        match err.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::RowNotFound) => Self::NotFound("Resource not found".to_string()),
+            Some(sqlx::Error::Database(db_error)) => match db_error.code().as_deref() {
+                Some(UNIQUE_VIOLATION) => Self::Conflict("Resource already exists".to_string()),
+                Some(FOREIGN_KEY_VIOLATION) => {
+                    Self::Conflict("Referenced resource does not exist".to_string())
+                }
+                _ => Self::Internal(err),
+            },
+            _ => Self::Internal(err),
+        }
     }
 }
 
@@ -48,7 +155,8 @@ pub async fn health() -> Result<Response, TiraError> {
 
 // Service method that checks for authentication given a user's cookies
 //
-// Returns the user id of that user and their session uuid if they are authenticated or returns an error response
+// Adds the session and the user it belongs to onto the request if they are authenticated or returns an error response.
+// Viewers are only let through for requests that don't change anything.
 pub async fn authentication(
     State(state): State<TiraState>,
     cookie_jar: CookieJar,
@@ -65,13 +173,27 @@ pub async fn authentication(
                 "SELECT * FROM sessions WHERE uuid = $1 and (expiration >= now() or expiration is null)",
                 session_uuid,
             )
-            .fetch_one(&state.pool)
-            .await?;
+            .fetch_optional(&state.pool)
+            .await?
+            .ok_or_else(|| TiraError::Unauthorized("Session is invalid or expired".to_string()))?;
+
+            let user = service::users::get_user_by_id(&state, session.user_id).await?;
+            if user.archived {
+                return Err(TiraError::Unauthorized("User is archived".to_string()));
+            }
+
+            let read_only = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+            if !read_only && !is_personal_path(req.uri().path()) {
+                service::permissions::require_write(&user)?;
+            }
 
             req.extensions_mut().insert(session);
+            req.extensions_mut().insert(user);
 
             Ok(next.run(req).await)
         }
-        None => Err(TiraError(anyhow!("User not authenticated"))),
+        None => Err(TiraError::Unauthorized(
+            "User not authenticated".to_string(),
+        )),
     }
 }
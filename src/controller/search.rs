@@ -0,0 +1,73 @@
+use super::TiraError;
+use crate::models::success::{CountResponse, SearchResultResponse};
+use crate::models::{TicketFilter, TicketWithoutDescription};
+use crate::service;
+use crate::TiraState;
+use anyhow::Result;
+use axum::extract::{Query, State};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct SearchQueryParams {
+    #[serde(default)]
+    q: String,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Endpoint for searching tickets and their comments.
+///
+/// Every matching ticket is returned once, best match first, with a snippet of the match where
+/// the search terms are wrapped in `<mark>` tags. `comment_id` is set when the best match is
+/// in a comment.
+///
+/// **GET /search**
+///
+/// Query Parameters:
+///
+/// q: The text to search for. Supports quoted phrases, OR and -excluded words.
+/// limit: How many tickets should be retrieved (optional, default is 10)
+/// offset: The offset for the list of tickets (optional, default is 0)
+///
+/// Also takes the same filters as **GET /tickets**.
+pub async fn search_endpoint(
+    State(state): State<TiraState>,
+    Query(query): Query<SearchQueryParams>,
+    Query(filter): Query<TicketFilter>,
+) -> Result<Response, TiraError> {
+    let hits =
+        service::search::search_tickets(&state, &query.q, &filter, query.limit, query.offset)
+            .await?;
+    let total_count = service::search::count_search_hits(&state, &query.q, &filter).await?;
+
+    let batch = service::tickets::load_ticket_batch(&state, &hits, Vec::new()).await?;
+    let results = hits
+        .into_iter()
+        .map(|hit| {
+            let ticket = TicketWithoutDescription {
+                id: hit.id,
+                subject: hit.subject,
+                category_id: hit.category_id,
+                priority: hit.priority,
+                status: hit.status,
+                created: hit.created,
+                reporter_id: hit.reporter_id,
+            };
+
+            Ok(SearchResultResponse {
+                ticket: batch.ticket_without_description_response(ticket)?,
+                comment_id: hit.comment_id,
+                rank: hit.rank,
+                snippet: hit.snippet,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let response = CountResponse {
+        data: results,
+        total_count,
+    };
+    Ok(Json(response).into_response())
+}
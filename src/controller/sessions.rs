@@ -34,8 +34,7 @@ pub async fn login_endpoint(
     cookie_jar: CookieJar,
     login_info: Json<Login>,
 ) -> Result<Response, TiraError> {
-    let mut login_info = login_info.0;
-    login_info.password = service::security::sha256(&login_info.password);
+    let login_info = login_info.0;
     let remember_me = login_info.remember_me;
 
     let uuid_and_user = service::sessions::login(&state, login_info).await?;
@@ -1,21 +1,23 @@
 use super::TiraError;
 use crate::models::patch::UpdateTicket;
 use crate::models::success::{
-    AlteredResourceResponse, CommentResponse, CountResponse, TicketResponse,
-    TicketWithoutDescriptionResponse,
+    AlteredResourceResponse, CountResponse, HistoryEntryResponse, HistoryEvent,
 };
-use crate::models::{CreateAssignmentWithUserId, CreateComment, CreateTicket, Session};
-use crate::service::{self, tickets};
+use crate::models::{
+    CreateAssignmentWithUserId, CreateComment, CreateTicket, Session, TicketFilter, User,
+};
+use crate::service::{self, notifications, permissions, tickets};
 use crate::TiraState;
 use anyhow::Result;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::response::{IntoResponse, Response};
 use axum::{Extension, Json};
 use serde::Deserialize;
+use std::slice;
 
 /// Endpoint for creating an assignment for a ticket.
 ///
-/// Requires authentication.
+/// Requires authentication as the reporter, an assignee or an admin.
 ///
 /// **POST /tickets/<ticket_id>/assignments**
 ///
@@ -26,38 +28,20 @@ use serde::Deserialize;
 /// }
 pub async fn create_assignment_by_ticket_id_endpoint(
     State(state): State<TiraState>,
+    Extension(user): Extension<User>,
     Path(ticket_id): Path<i64>,
-    Extension(session): Extension<Session>,
     Json(assignment): Json<CreateAssignmentWithUserId>,
 ) -> Result<Response, TiraError> {
+    permissions::require_ticket_editor(&state, &user, ticket_id).await?;
     let assignee_id = assignment.assignee_id;
     let created_assignment_id = tickets::create_assignment_by_ticket_id_and_assigner_id(
         &state,
         assignee_id,
         ticket_id,
-        session.user_id,
+        user.id,
     )
     .await?;
 
-    let assignee = service::users::get_user_by_id(&state, assignee_id).await?;
-
-    if let Some(email_address) = assignee.email_address {
-        let assigner = service::users::get_user_by_id(&state, session.user_id).await?;
-        let ticket = service::tickets::get_ticket_by_id(&state, ticket_id).await?;
-
-        let body =
-            service::emails::create_assignment_email_body(&assigner, &ticket.subject, ticket.id);
-
-        state
-            .email_tx
-            .send(service::emails::Email {
-                to: email_address,
-                subject: ticket.subject,
-                body,
-            })
-            .unwrap();
-    }
-
     let message = "Successfully created assignment!".to_string();
     let response = AlteredResourceResponse {
         message,
@@ -66,6 +50,29 @@ pub async fn create_assignment_by_ticket_id_endpoint(
     Ok(Json(response).into_response())
 }
 
+/// Endpoint for removing a user from a ticket.
+///
+/// The assignment is kept in the ticket's history as ended.
+///
+/// Requires authentication as the reporter, an assignee or an admin.
+///
+/// **DELETE /tickets/<ticket_id>/assignments/<assignee_id>**
+pub async fn delete_assignment_by_ticket_id_and_assignee_id_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Path((ticket_id, assignee_id)): Path<(i64, i64)>,
+) -> Result<Response, TiraError> {
+    permissions::require_ticket_editor(&state, &user, ticket_id).await?;
+    tickets::unassign_by_ticket_id_and_assignee_id(&state, ticket_id, assignee_id, user.id).await?;
+
+    let message = "Successfully removed assignment!".to_string();
+    let response = AlteredResourceResponse {
+        message,
+        id: ticket_id,
+    };
+    Ok(Json(response).into_response())
+}
+
 /// Endpoint for creating a comment for a ticket.
 ///
 /// Requires authentication.
@@ -75,7 +82,9 @@ pub async fn create_assignment_by_ticket_id_endpoint(
 /// Example JSON Body:
 ///
 /// {
-///     "content": "This is a comment"
+///     "content": "This is a **reply**",
+///     "content_format": "markdown",
+///     "parent_comment_id": 1
 /// }
 pub async fn create_comment_by_ticket_id_endpoint(
     State(state): State<TiraState>,
@@ -83,41 +92,16 @@ pub async fn create_comment_by_ticket_id_endpoint(
     Extension(session): Extension<Session>,
     Json(comment): Json<CreateComment>,
 ) -> Result<Response, TiraError> {
-    let commenter = service::users::get_user_by_id(&state, session.user_id).await?;
-
     let created_comment_id = tickets::create_comment_by_ticket_id_and_commenter_id(
         &state,
         &comment.content,
+        comment.content_format.as_deref(),
         ticket_id,
         session.user_id,
+        comment.parent_comment_id,
     )
     .await?;
 
-    // Email all users about new comment (except for commenter)
-    let users = service::users::get_users(&state, None).await?;
-    let ticket = service::tickets::get_ticket_by_id(&state, ticket_id).await?;
-    for user in users {
-        if user.id != session.user_id {
-            if let Some(email_address) = user.email_address {
-                let body = service::emails::create_comment_email_body(
-                    &commenter,
-                    &comment.content,
-                    &ticket.subject,
-                    ticket_id,
-                );
-
-                state
-                    .email_tx
-                    .send(service::emails::Email {
-                        to: email_address,
-                        subject: ticket.subject.clone(),
-                        body,
-                    })
-                    .unwrap();
-            }
-        }
-    }
-
     let message = "Successfully created comment!".to_string();
     let response = AlteredResourceResponse {
         message,
@@ -137,58 +121,76 @@ pub async fn create_comment_by_ticket_id_endpoint(
 /// {
 ///     "category_id": "123",
 ///     "subject": "Finish Tira",
-///     "description": "Finish working on the code for Tira",
+///     "description": "Finish working on the code for *Tira*",
+///     "content_format": "markdown",
 ///     "status": "IN PROGRESS",
 ///     "priority": "3"
 /// }
-
 pub async fn create_ticket_endpoint(
     State(state): State<TiraState>,
     Extension(session): Extension<Session>,
     Json(ticket): Json<CreateTicket>,
 ) -> Result<Response, TiraError> {
-    let created_ticket_id =
-        service::tickets::create_ticket_by_reporter_id(&state, &ticket, session.user_id).await?;
+    service::tickets::create_ticket_by_reporter_id(&state, ticket, session.user_id).await?;
 
-    let reporter = service::users::get_user_by_id(&state, session.user_id).await?;
+    let message = "Successfully created ticket!".to_string();
+    Ok(message.into_response())
+}
 
-    // Email users about new ticket (except for reporter)
-    let users = service::users::get_users(&state, Some(false)).await?;
-    for user in users {
-        if user.id != reporter.id {
-            if let Some(email_address) = user.email_address {
-                let body = service::emails::create_ticket_creation_email_body(
-                    &reporter,
-                    &ticket.subject,
-                    &ticket
-                        .description
-                        .clone()
-                        .map_or(String::new(), |description| {
-                            format!("<p>{}</p>", description)
-                        }),
-                    created_ticket_id,
-                );
+/// Endpoint for watching a ticket, which gets the current user notified about it.
+///
+/// Requires authentication.
+///
+/// **POST /tickets/<ticket_id>/watch**
+pub async fn watch_ticket_endpoint(
+    State(state): State<TiraState>,
+    Extension(session): Extension<Session>,
+    Path(ticket_id): Path<i64>,
+) -> Result<Response, TiraError> {
+    notifications::watch_ticket(&state, ticket_id, session.user_id).await?;
 
-                state
-                    .email_tx
-                    .send(service::emails::Email {
-                        to: email_address,
-                        subject: ticket.subject.clone(),
-                        body,
-                    })
-                    .unwrap();
-            }
-        }
-    }
+    let message = "Successfully watched ticket!".to_string();
+    let response = AlteredResourceResponse {
+        message,
+        id: ticket_id,
+    };
+    Ok(Json(response).into_response())
+}
 
-    let message = "Successfully created ticket!".to_string();
-    Ok(message.into_response())
+/// Endpoint for no longer watching a ticket.
+///
+/// Requires authentication.
+///
+/// **DELETE /tickets/<ticket_id>/watch**
+pub async fn unwatch_ticket_endpoint(
+    State(state): State<TiraState>,
+    Extension(session): Extension<Session>,
+    Path(ticket_id): Path<i64>,
+) -> Result<Response, TiraError> {
+    notifications::unwatch_ticket(&state, ticket_id, session.user_id).await?;
+
+    let message = "Successfully unwatched ticket!".to_string();
+    let response = AlteredResourceResponse {
+        message,
+        id: ticket_id,
+    };
+    Ok(Json(response).into_response())
+}
+
+/// Endpoint for retrieving the users watching a ticket.
+///
+/// **GET /tickets/<ticket_id>/watchers**
+pub async fn get_watchers_by_ticket_id_endpoint(
+    State(state): State<TiraState>,
+    Path(ticket_id): Path<i64>,
+) -> Result<Response, TiraError> {
+    let watchers = notifications::get_watchers_by_ticket_id(&state, ticket_id).await?;
+    Ok(Json(watchers).into_response())
 }
 
 /// Endpoint for retrieving all assignments for a ticket.
 ///
 /// **GET /tickets/<ticket_id>/assignments**
-
 pub async fn get_assignments_by_ticket_id_endpoint(
     State(state): State<TiraState>,
     Path(ticket_id): Path<i64>,
@@ -197,82 +199,140 @@ pub async fn get_assignments_by_ticket_id_endpoint(
     Ok(Json(assignments).into_response())
 }
 
-/// Endpoint for retrieving all comments for a ticket.
+/// Endpoint for retrieving all comments for a ticket, oldest first.
+///
+/// Replies have the id of the comment they reply to as `parent_comment_id`. Every comment comes
+/// with its reactions per emoji and whether the current user reacted.
 ///
 /// **GET /tickets/<ticket_id>/comments**
-
 pub async fn get_comments_by_ticket_id_endpoint(
     State(state): State<TiraState>,
+    Extension(user): Extension<User>,
     Path(ticket_id): Path<i64>,
 ) -> Result<Response, TiraError> {
+    let ticket = service::tickets::get_ticket_by_id(&state, ticket_id).await?;
     let comments = service::tickets::get_comments_by_ticket_id(&state, ticket_id).await?;
-    let mut comments_response = Vec::new();
+    let comment_ids: Vec<i64> = comments.iter().map(|comment| comment.id).collect();
+    let mut reactions =
+        service::comments::get_reactions_by_comment_ids(&state, &comment_ids, user.id).await?;
 
-    for comment in comments {
-        let commenter = service::users::get_user_by_id(&state, comment.commenter_id).await?;
+    let commenter_ids = comments
+        .iter()
+        .map(|comment| comment.commenter_id)
+        .collect();
+    let batch = service::tickets::load_ticket_batch(&state, &[ticket], commenter_ids).await?;
 
-        let comment_response = CommentResponse {
-            id: comment.id,
-            commenter,
-            content: comment.content,
-            commented: comment.commented,
-        };
+    let comments_response = comments
+        .into_iter()
+        .map(|comment| {
+            let comment_reactions = reactions.remove(&comment.id).unwrap_or_default();
+            batch.comment_response(comment, comment_reactions)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Json(comments_response).into_response())
+}
 
-        comments_response.push(comment_response);
+/// Endpoint for retrieving the history of a ticket.
+///
+/// Returns its creation, field changes, comments and assignments, oldest first.
+///
+/// **GET /tickets/<ticket_id>/history**
+pub async fn get_history_by_ticket_id_endpoint(
+    State(state): State<TiraState>,
+    Path(ticket_id): Path<i64>,
+) -> Result<Response, TiraError> {
+    let ticket = service::tickets::get_ticket_by_id(&state, ticket_id).await?;
+    let events = service::tickets::get_ticket_events_by_ticket_id(&state, ticket_id).await?;
+    let comments = service::tickets::get_comments_by_ticket_id(&state, ticket_id).await?;
+    let assignments = service::tickets::get_all_assignments_by_ticket_id(&state, ticket_id).await?;
+
+    let mut user_ids = vec![ticket.reporter_id];
+    user_ids.extend(events.iter().map(|event| event.actor_id));
+    user_ids.extend(comments.iter().map(|comment| comment.commenter_id));
+    for assignment in &assignments {
+        user_ids.push(assignment.assignee_id);
+        user_ids.push(assignment.assigner_id);
+        user_ids.extend(assignment.unassigner_id);
     }
+    let batch =
+        service::tickets::load_ticket_batch(&state, slice::from_ref(&ticket), user_ids).await?;
+
+    let mut history = vec![HistoryEntryResponse {
+        timestamp: ticket.created,
+        actor: batch.user(ticket.reporter_id)?,
+        event: HistoryEvent::Created,
+    }];
+    for event in events {
+        history.push(HistoryEntryResponse {
+            timestamp: event.changed,
+            actor: batch.user(event.actor_id)?,
+            event: HistoryEvent::FieldChanged {
+                field: event.field,
+                old_value: event.old_value,
+                new_value: event.new_value,
+            },
+        });
+    }
+    for comment in comments {
+        history.push(HistoryEntryResponse {
+            timestamp: comment.commented,
+            actor: batch.user(comment.commenter_id)?,
+            event: HistoryEvent::Commented {
+                comment_id: comment.id,
+                content: comment.content,
+            },
+        });
+    }
+    for assignment in assignments {
+        history.push(HistoryEntryResponse {
+            timestamp: assignment.assigned,
+            actor: batch.user(assignment.assigner_id)?,
+            event: HistoryEvent::Assigned {
+                assignment_id: assignment.id,
+                assignee: batch.user(assignment.assignee_id)?,
+            },
+        });
+        if let (Some(unassigned), Some(unassigner_id)) =
+            (assignment.unassigned, assignment.unassigner_id)
+        {
+            history.push(HistoryEntryResponse {
+                timestamp: unassigned,
+                actor: batch.user(unassigner_id)?,
+                event: HistoryEvent::Unassigned {
+                    assignment_id: assignment.id,
+                    assignee: batch.user(assignment.assignee_id)?,
+                },
+            });
+        }
+    }
+    history.sort_by_key(|entry| entry.timestamp);
 
-    Ok(Json(comments_response).into_response())
+    Ok(Json(history).into_response())
 }
 
 /// Endpoint for retrieving a ticket.
 ///
 /// **GET /tickets/<ticket_id>**
-
 pub async fn get_ticket_by_id_endpoint(
     State(state): State<TiraState>,
     Path(ticket_id): Path<i64>,
 ) -> Result<Response, TiraError> {
     let ticket = service::tickets::get_ticket_by_id(&state, ticket_id).await?;
 
-    let category = if let Some(category_id) = ticket.category_id {
-        Some(service::categories::get_category_by_id(&state, category_id).await?)
-    } else {
-        None
-    };
-    let reporter = service::users::get_user_by_id(&state, ticket.reporter_id).await?;
-
-    let assignments = service::assignments::get_assignments(&state, None, Some(ticket.id)).await?;
-
-    let assignee_ids: Vec<_> = assignments
-        .iter()
-        .map(|assignment| assignment.assignee_id)
-        .collect();
-
-    let assignees = service::users::get_users_by_ids(&state, assignee_ids).await?;
-
-    let ticket_response = TicketResponse {
-        id: ticket.id,
-        subject: ticket.subject,
-        description: ticket.description,
-        category,
-        priority: ticket.priority,
-        status: ticket.status,
-        created: ticket.created,
-        reporter,
-        assignees,
-    };
+    let batch =
+        service::tickets::load_ticket_batch(&state, slice::from_ref(&ticket), Vec::new()).await?;
+    let ticket_response = batch.ticket_response(ticket)?;
 
     Ok(Json(ticket_response).into_response())
 }
 
 #[derive(Deserialize)]
 pub struct GetTicketsQueryParams {
-    // limit: Option<i64>,
-    // offset: Option<i64>,
-    // reporter: Option<i64>,
-    // open: Option<bool>,
-    // sort_by: Option<String>,
-    // order_by: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    sort_by: Option<String>,
+    order_by: Option<String>,
 }
 
 /// Endpoint for retrieving every ticket.
@@ -283,62 +343,40 @@ pub struct GetTicketsQueryParams {
 ///
 /// limit: How many tickets should be retrieved (optional, default is 10)
 /// offset: The offset for the list of tickets (optional, default is 0)
+/// sort_by: The field to sort by. One of 'id', 'subject', 'category', 'priority', 'status', 'created' or 'reporter'. (optional, default is 'created')
+/// order_by: The direction to sort in. Either 'asc' or 'desc'. (optional, default is 'desc')
 /// reporter: Used to filter tickets that were reported by a certain user. Takes a number value. (optional)
 /// open: Used to filter tickets that are open or not. Takes a boolean. (optional)
+/// status: Used to filter tickets that have a certain status. (optional)
+/// priority: Used to filter tickets that have a certain priority. (optional)
+/// category: Used to filter tickets that are in a certain category. Takes a number value. (optional)
+/// assignee: Used to filter tickets that are assigned to a certain user. Takes a number value. (optional)
+/// created_after: Used to filter tickets created on or after a date, e.g. 2024-04-14. (optional)
+/// created_before: Used to filter tickets created before a date, e.g. 2024-04-14. (optional)
 pub async fn get_tickets_endpoint(
     State(state): State<TiraState>,
-    // Query(query): Query<GetTicketsQueryParams>,
+    Query(query): Query<GetTicketsQueryParams>,
+    Query(filter): Query<TicketFilter>,
 ) -> Result<Response, TiraError> {
     let tickets = tickets::get_tickets(
         &state,
-        // query.limit,
-        // query.offset,
-        // query.reporter,
-        // query.open,
-        // query.sort_by,
-        // query.order_by,
+        &filter,
+        query.limit,
+        query.offset,
+        query.sort_by,
+        query.order_by,
     )
     .await?;
-    let ticket_count = tickets.len();
-    let mut tickets_response = Vec::new();
-
-    for ticket in tickets {
-        let reporter = service::users::get_user_by_id(&state, ticket.reporter_id).await?;
-
-        let category = match ticket.category_id {
-            Some(category_id) => {
-                Some(service::categories::get_category_by_id(&state, category_id).await?)
-            }
-            None => None,
-        };
-
-        let assignments =
-            service::assignments::get_assignments(&state, None, Some(ticket.id)).await?;
-
-        let assignee_ids: Vec<_> = assignments
-            .iter()
-            .map(|assignment| assignment.assignee_id)
-            .collect();
-
-        let assignees = service::users::get_users_by_ids(&state, assignee_ids).await?;
-
-        let ticket_response = TicketWithoutDescriptionResponse {
-            id: ticket.id,
-            subject: ticket.subject.clone(),
-            category,
-            priority: ticket.priority.clone(),
-            status: ticket.status.clone(),
-            created: ticket.created,
-            reporter,
-            assignees,
-        };
-
-        tickets_response.push(ticket_response);
-    }
+    let ticket_count = tickets::count_tickets(&state, &filter).await?;
+    let batch = service::tickets::load_ticket_batch(&state, &tickets, Vec::new()).await?;
+    let tickets_response = tickets
+        .into_iter()
+        .map(|ticket| batch.ticket_without_description_response(ticket))
+        .collect::<Result<Vec<_>>>()?;
 
     let response = CountResponse {
         data: tickets_response,
-        total_count: ticket_count as i64,
+        total_count: ticket_count,
     };
 
     Ok(Json(response).into_response())
@@ -346,24 +384,20 @@ pub async fn get_tickets_endpoint(
 
 /// Endpoint for updating a ticket.
 ///
+/// If `assignee_ids` is given it replaces the ticket's assignees and only the newly assigned
+/// users are notified.
+///
+/// Requires authentication as the reporter, an assignee or an admin.
+///
 /// **PATCH /tickets/<ticket_id>**
-
 pub async fn patch_ticket_by_id_endpoint(
     State(state): State<TiraState>,
-    Extension(session): Extension<Session>,
+    Extension(user): Extension<User>,
     Path(ticket_id): Path<i64>,
     Json(ticket): Json<UpdateTicket>,
 ) -> Result<Response, TiraError> {
-    service::tickets::update_ticket_by_id(&state, &ticket, ticket_id).await?;
-    if let Some(assignee_ids) = ticket.assignee_ids {
-        service::tickets::update_assignments_by_ticket_id(
-            &state,
-            ticket_id,
-            assignee_ids,
-            session.user_id,
-        )
-        .await?;
-    }
+    permissions::require_ticket_editor(&state, &user, ticket_id).await?;
+    service::tickets::update_ticket_by_id(&state, ticket, ticket_id, user.id).await?;
 
     let message = "Successfully edited ticket!".to_string();
     let response = AlteredResourceResponse {
@@ -1,28 +1,29 @@
-use crate::models::patch::UpdateUser;
-use crate::models::success::{AlteredResourceResponse, AssignmentResponse};
+use crate::models::patch::{UpdateNotificationPreferences, UpdateUser};
+use crate::models::success::{AlteredResourceResponse, CountResponse, MentionResponse};
+use crate::models::Session;
 use crate::models::User;
-use crate::models::{Session, TicketWithReporterAsUser};
-use crate::service;
+use crate::service::{self, notifications, permissions};
 use crate::TiraState;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use axum::extract::{Path, Query, State};
-use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::{Extension, Json};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 use super::TiraError;
 
 /// Endpoint for archiving a specific user.
 //
-/// Requires authentication.
+/// Requires authentication as an admin.
 ///
 /// **DELETE /users/<user_id>**
-
 pub async fn archive_user_by_id_endpoint(
     State(state): State<TiraState>,
+    Extension(current_user): Extension<User>,
     Path(user_id): Path<i64>,
 ) -> Result<Response, TiraError> {
+    permissions::require_admin(&current_user)?;
     service::users::archive_user_by_id(&state, user_id).await?;
 
     let message = "Successfully archived user!".to_string();
@@ -35,6 +36,8 @@ pub async fn archive_user_by_id_endpoint(
 
 /// Endpoint for creating a user.
 ///
+/// Requires authentication as an admin.
+///
 /// **POST /users**
 ///
 /// Example JSON Body:
@@ -45,13 +48,15 @@ pub async fn archive_user_by_id_endpoint(
 ///     "email_address": "testemailaddress",
 ///     "first_name": "testfirstname",
 ///     "last_name": "testtestname",
+///     "role": "member"
 /// }
-
 pub async fn create_user_endpoint(
     State(state): State<TiraState>,
-    Json(mut user): Json<User>,
+    Extension(current_user): Extension<User>,
+    Json(user): Json<User>,
 ) -> Result<Response, TiraError> {
-    user.password = service::security::sha256(&user.password);
+    permissions::require_admin(&current_user)?;
+    permissions::check_role(&user.role)?;
     let created_user_id = service::users::create_user(&state, user).await?;
     let message = "Successfully created user!".to_string();
     let response = AlteredResourceResponse {
@@ -64,53 +69,37 @@ pub async fn create_user_endpoint(
 /// Endpoint for retrieving all assignments for a user.
 ///
 /// **GET /users/<user_id>/assignments**
-
 pub async fn get_assignments_by_user_id_endpoint(
     State(state): State<TiraState>,
     Path(user_id): Path<i64>,
 ) -> Result<Response, TiraError> {
     let assignments = service::users::get_assignments_by_user_id(&state, user_id).await?;
 
-    let mut ticket_ids = Vec::new();
-    let mut assigner_ids = Vec::new();
-
-    for assignment in &assignments {
-        ticket_ids.push(assignment.ticket_id);
-        assigner_ids.push(assignment.assigner_id);
-    }
+    let ticket_ids = assignments
+        .iter()
+        .map(|assignment| assignment.ticket_id)
+        .collect();
+    let assigner_ids = assignments
+        .iter()
+        .map(|assignment| assignment.assigner_id)
+        .collect();
 
     let tickets = service::tickets::get_tickets_by_ids(&state, ticket_ids).await?;
-    let assigners = service::users::get_users_by_ids(&state, assigner_ids).await?;
+    let batch = service::tickets::load_ticket_batch(&state, &tickets, assigner_ids).await?;
+    let tickets: HashMap<_, _> = tickets
+        .into_iter()
+        .map(|ticket| (ticket.id, ticket))
+        .collect();
 
-    let mut ticket_reporter_ids = Vec::new();
-
-    for ticket in &tickets {
-        ticket_reporter_ids.push(ticket.reporter_id);
-    }
-
-    let ticket_reporters = service::users::get_users_by_ids(&state, ticket_reporter_ids).await?;
-
-    let mut assignment_responses = Vec::new();
-    for (index, assignment) in assignments.iter().enumerate() {
-        let ticket = &tickets[index];
-        let ticket_with_reporter_as_user = TicketWithReporterAsUser {
-            id: ticket.id,
-            subject: ticket.subject.clone(),
-            description: ticket.description.clone(),
-            category_id: ticket.category_id,
-            priority: ticket.priority.clone(),
-            status: ticket.status.clone(),
-            created: ticket.created,
-            reporter: ticket_reporters[index].clone(),
-        };
-
-        assignment_responses.push(AssignmentResponse {
-            id: assignment.id,
-            ticket: ticket_with_reporter_as_user,
-            assigner: assigners[index].clone(),
-            assigned: assignment.assigned,
-        });
-    }
+    let assignment_responses = assignments
+        .into_iter()
+        .map(|assignment| {
+            let ticket = tickets
+                .get(&assignment.ticket_id)
+                .context("ticket for assignment was not loaded")?;
+            batch.assignment_response(assignment, ticket)
+        })
+        .collect::<Result<Vec<_>>>()?;
 
     Ok(Json(assignment_responses).into_response())
 }
@@ -120,7 +109,6 @@ pub async fn get_assignments_by_user_id_endpoint(
 /// Requires authentication.
 ///
 /// **GET /users/current**
-
 pub async fn get_current_user_endpoint(
     State(state): State<TiraState>,
     Extension(session): Extension<Session>,
@@ -129,10 +117,116 @@ pub async fn get_current_user_endpoint(
     Ok(Json(user).into_response())
 }
 
+#[derive(Deserialize)]
+pub struct GetMentionsQueryParams {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Endpoint for retrieving the comments that mention the current user, newest first.
+///
+/// Requires authentication. Deleted comments are left out.
+///
+/// **GET /users/current/mentions**
+///
+/// Query Parameters:
+///
+/// limit: How many mentions should be retrieved (optional, default is 10)
+/// offset: The offset for the list of mentions (optional, default is 0)
+pub async fn get_mentions_endpoint(
+    State(state): State<TiraState>,
+    Extension(session): Extension<Session>,
+    Query(query_params): Query<GetMentionsQueryParams>,
+) -> Result<Response, TiraError> {
+    let mentions = service::comments::get_mentions_by_user_id(
+        &state,
+        session.user_id,
+        query_params.limit,
+        query_params.offset,
+    )
+    .await?;
+    let total_count = service::comments::count_mentions_by_user_id(&state, session.user_id).await?;
+
+    let commenter_ids = mentions
+        .iter()
+        .map(|mention| mention.commenter_id)
+        .collect();
+    let commenters: HashMap<_, _> = service::users::get_users_by_ids(&state, commenter_ids)
+        .await?
+        .into_iter()
+        .map(|user| (user.id, user))
+        .collect();
+
+    let data = mentions
+        .into_iter()
+        .map(|mention| {
+            let commenter = commenters
+                .get(&mention.commenter_id)
+                .cloned()
+                .context("commenter of mention was not loaded")?;
+            Ok(MentionResponse {
+                comment_id: mention.comment_id,
+                ticket_id: mention.ticket_id,
+                ticket_subject: mention.ticket_subject,
+                commenter,
+                content: mention.content,
+                commented: mention.commented,
+                mentioned: mention.mentioned,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let response = CountResponse { data, total_count };
+    Ok(Json(response).into_response())
+}
+
+/// Endpoint for retrieving which events on watched tickets the current user gets emailed about.
+///
+/// Requires authentication.
+///
+/// **GET /users/current/notification-preferences**
+pub async fn get_notification_preferences_endpoint(
+    State(state): State<TiraState>,
+    Extension(session): Extension<Session>,
+) -> Result<Response, TiraError> {
+    let preferences =
+        notifications::get_notification_preferences_by_user_id(&state, session.user_id).await?;
+    Ok(Json(preferences).into_response())
+}
+
+/// Endpoint for changing which events on watched tickets the current user gets emailed about.
+///
+/// Requires authentication.
+///
+/// **PATCH /users/current/notification-preferences**
+///
+/// Example JSON Body:
+///
+/// {
+///     "commented": false,
+///     "status_changed": true,
+///     "mentioned": true
+/// }
+pub async fn patch_notification_preferences_endpoint(
+    State(state): State<TiraState>,
+    Extension(session): Extension<Session>,
+    Json(preferences): Json<UpdateNotificationPreferences>,
+) -> Result<Response, TiraError> {
+    notifications::update_notification_preferences_by_user_id(
+        &state,
+        session.user_id,
+        &preferences,
+    )
+    .await?;
+
+    let preferences =
+        notifications::get_notification_preferences_by_user_id(&state, session.user_id).await?;
+    Ok(Json(preferences).into_response())
+}
+
 /// Endpoint for retrieving a user.
 ///
 /// **GET /users/<user_id>**
-
 pub async fn get_user_by_id_endpoint(
     State(state): State<TiraState>,
     Extension(session): Extension<Session>,
@@ -163,27 +257,30 @@ pub async fn get_users_endpoint(
 
 /// Endpoint for updating a user.
 ///
+/// Users can edit themselves. Admins can edit anyone and are the only ones who can change
+/// `archived` or `role`.
+///
 /// **PATCH /users/<user_id>**
 pub async fn patch_user_by_id_endpoint(
     State(state): State<TiraState>,
-    Extension(session): Extension<Session>,
+    Extension(current_user): Extension<User>,
     Path(user_id): Path<i64>,
     Json(user): Json<UpdateUser>,
 ) -> Result<Response, TiraError> {
-    if user_id != session.user_id {
-        return Ok((
-            StatusCode::BAD_REQUEST,
-            "Cannot edit another person's user!",
-        )
-            .into_response());
+    permissions::require_user_editor(&current_user, user_id)?;
+    if user.archived.is_some() || user.role.is_some() {
+        permissions::require_admin(&current_user)?;
+    }
+    if let Some(role) = &user.role {
+        permissions::check_role(role)?;
     }
 
-    service::users::update_user_by_id(&state, user, session.user_id).await?;
+    service::users::update_user_by_id(&state, user, user_id).await?;
 
     let message = "Successfully edited user!".to_string();
     let response = AlteredResourceResponse {
         message,
-        id: session.user_id,
+        id: user_id,
     };
     Ok(Json(response).into_response())
 }
@@ -0,0 +1,163 @@
+use super::TiraError;
+use crate::models::patch::UpdateWebhook;
+use crate::models::success::{AlteredResourceResponse, CountResponse, StandardResponse};
+use crate::models::{CreateWebhook, User};
+use crate::service::{permissions, webhooks};
+use crate::TiraState;
+use anyhow::Result;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use serde::Deserialize;
+
+/// Endpoint for creating a webhook.
+///
+/// Requires authentication as an admin.
+///
+/// Every request to the webhook is signed with an HMAC-SHA256 of its body, keyed with the
+/// secret, in the `X-Tira-Signature` header as `sha256=<hex>`.
+///
+/// **POST /webhooks**
+///
+/// Example JSON Body:
+///
+/// {
+///     "url": "https://chat.example.com/hooks/tira",
+///     "secret": "a long random string",
+///     "events": ["ticket.created", "ticket.updated", "comment.created", "comment.edited", "assignment.created", "assignment.deleted"]
+/// }
+pub async fn create_webhook_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Json(webhook): Json<CreateWebhook>,
+) -> Result<Response, TiraError> {
+    permissions::require_admin(&user)?;
+    let webhook_id = webhooks::create_webhook(&state, &webhook, user.id).await?;
+
+    let message = format!("Successfully created webhook with id {}", webhook_id);
+    let response = AlteredResourceResponse {
+        message,
+        id: webhook_id,
+    };
+    Ok((StatusCode::CREATED, Json(response)).into_response())
+}
+
+/// Endpoint for retrieving every webhook.
+///
+/// Requires authentication as an admin.
+///
+/// **GET /webhooks**
+pub async fn get_webhooks_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+) -> Result<Response, TiraError> {
+    permissions::require_admin(&user)?;
+    let webhooks = webhooks::get_webhooks(&state).await?;
+    Ok(Json(webhooks).into_response())
+}
+
+/// Endpoint for retrieving a webhook.
+///
+/// Requires authentication as an admin.
+///
+/// **GET /webhooks/<webhook_id>**
+pub async fn get_webhook_by_id_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Path(webhook_id): Path<i64>,
+) -> Result<Response, TiraError> {
+    permissions::require_admin(&user)?;
+    let webhook = webhooks::get_webhook_by_id(&state, webhook_id).await?;
+    Ok(Json(webhook).into_response())
+}
+
+/// Endpoint for updating a webhook.
+///
+/// Requires authentication as an admin.
+///
+/// **PATCH /webhooks/<webhook_id>**
+///
+/// Example JSON Body:
+///
+/// {
+///     "url": "https://chat.example.com/hooks/tira",
+///     "secret": "another long random string",
+///     "events": ["comment.created"],
+///     "active": false
+/// }
+pub async fn patch_webhook_by_id_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Path(webhook_id): Path<i64>,
+    Json(webhook): Json<UpdateWebhook>,
+) -> Result<Response, TiraError> {
+    permissions::require_admin(&user)?;
+    webhooks::update_webhook_by_id(&state, &webhook, webhook_id).await?;
+
+    let message = "Successfully updated webhook!".to_string();
+    let response = AlteredResourceResponse {
+        message,
+        id: webhook_id,
+    };
+    Ok(Json(response).into_response())
+}
+
+/// Endpoint for deleting a webhook along with its deliveries.
+///
+/// Requires authentication as an admin.
+///
+/// **DELETE /webhooks/<webhook_id>**
+pub async fn delete_webhook_by_id_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Path(webhook_id): Path<i64>,
+) -> Result<Response, TiraError> {
+    permissions::require_admin(&user)?;
+    webhooks::delete_webhook_by_id(&state, webhook_id).await?;
+
+    let message = format!("Successfully deleted webhook with id {}!", webhook_id);
+    let response = StandardResponse { message };
+    Ok(Json(response).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct GetDeliveriesQueryParams {
+    status: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Endpoint for retrieving the deliveries of a webhook, newest first.
+///
+/// Requires authentication as an admin.
+///
+/// **GET /webhooks/<webhook_id>/deliveries**
+///
+/// Query Parameters:
+///
+/// status: Used to filter deliveries by 'pending', 'delivered' or 'dead'. (optional)
+/// limit: How many deliveries should be retrieved (optional, default is 10)
+/// offset: The offset for the list of deliveries (optional, default is 0)
+pub async fn get_deliveries_by_webhook_id_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Path(webhook_id): Path<i64>,
+    Query(query_params): Query<GetDeliveriesQueryParams>,
+) -> Result<Response, TiraError> {
+    permissions::require_admin(&user)?;
+
+    let data = webhooks::get_deliveries_by_webhook_id(
+        &state,
+        webhook_id,
+        query_params.status.clone(),
+        query_params.limit,
+        query_params.offset,
+    )
+    .await?;
+    let total_count =
+        webhooks::count_deliveries_by_webhook_id(&state, webhook_id, query_params.status).await?;
+
+    let response = CountResponse { data, total_count };
+    Ok(Json(response).into_response())
+}
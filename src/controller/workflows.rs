@@ -0,0 +1,209 @@
+use super::TiraError;
+use crate::models::patch::UpdateTicketStatus;
+use crate::models::success::{StandardResponse, WorkflowResponse};
+use crate::models::{TicketPriority, TicketStatus, User, Workflow};
+use crate::service::{permissions, workflows};
+use crate::TiraState;
+use anyhow::Result;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+
+/// Endpoint for creating a status.
+///
+/// Requires authentication as an admin.
+///
+/// **POST /statuses**
+///
+/// Example JSON Body:
+///
+/// {
+///     "name": "In Review",
+///     "open": true,
+///     "position": 2
+/// }
+pub async fn create_status_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Json(status): Json<TicketStatus>,
+) -> Result<Response, TiraError> {
+    permissions::require_admin(&user)?;
+    workflows::create_status(&state, &status).await?;
+
+    let message = format!("Successfully created status '{}'!", status.name);
+    let response = StandardResponse { message };
+    Ok((StatusCode::CREATED, Json(response)).into_response())
+}
+
+/// Endpoint for retrieving every status.
+///
+/// **GET /statuses**
+pub async fn get_statuses_endpoint(State(state): State<TiraState>) -> Result<Response, TiraError> {
+    let statuses = workflows::get_statuses(&state).await?;
+    Ok(Json(statuses).into_response())
+}
+
+/// Endpoint for updating a status.
+///
+/// Requires authentication as an admin.
+///
+/// **PATCH /statuses/<name>**
+///
+/// Example JSON Body:
+///
+/// {
+///     "open": false
+/// }
+pub async fn patch_status_by_name_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Path(name): Path<String>,
+    Json(status): Json<UpdateTicketStatus>,
+) -> Result<Response, TiraError> {
+    permissions::require_admin(&user)?;
+    workflows::update_status_by_name(&state, &name, &status).await?;
+
+    let message = format!("Successfully edited status '{}'!", name);
+    let response = StandardResponse { message };
+    Ok(Json(response).into_response())
+}
+
+/// Endpoint for creating a priority.
+///
+/// Requires authentication as an admin.
+///
+/// **POST /priorities**
+///
+/// Example JSON Body:
+///
+/// {
+///     "name": "Urgent",
+///     "position": 3
+/// }
+pub async fn create_priority_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Json(priority): Json<TicketPriority>,
+) -> Result<Response, TiraError> {
+    permissions::require_admin(&user)?;
+    workflows::create_priority(&state, &priority).await?;
+
+    let message = format!("Successfully created priority '{}'!", priority.name);
+    let response = StandardResponse { message };
+    Ok((StatusCode::CREATED, Json(response)).into_response())
+}
+
+/// Endpoint for retrieving every priority.
+///
+/// **GET /priorities**
+pub async fn get_priorities_endpoint(
+    State(state): State<TiraState>,
+) -> Result<Response, TiraError> {
+    let priorities = workflows::get_priorities(&state).await?;
+    Ok(Json(priorities).into_response())
+}
+
+/// Endpoint for retrieving the default workflow.
+///
+/// **GET /workflow**
+pub async fn get_default_workflow_endpoint(
+    State(state): State<TiraState>,
+) -> Result<Response, TiraError> {
+    let (category_id, transitions) = workflows::get_workflow_by_category_id(&state, None).await?;
+    let response = WorkflowResponse {
+        category_id,
+        transitions,
+    };
+    Ok(Json(response).into_response())
+}
+
+/// Endpoint for replacing the default workflow, which is used by every category without its own.
+///
+/// Requires authentication as an admin.
+///
+/// **PUT /workflow**
+///
+/// Example JSON Body:
+///
+/// {
+///     "transitions": [
+///         { "from_status": null, "to_status": "Backlog" },
+///         { "from_status": "Backlog", "to_status": "In Progress" }
+///     ]
+/// }
+pub async fn put_default_workflow_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Json(workflow): Json<Workflow>,
+) -> Result<Response, TiraError> {
+    permissions::require_admin(&user)?;
+    workflows::replace_workflow_by_category_id(&state, None, workflow).await?;
+
+    let message = "Successfully replaced default workflow!".to_string();
+    let response = StandardResponse { message };
+    Ok(Json(response).into_response())
+}
+
+/// Endpoint for retrieving the workflow that applies to a category.
+///
+/// `category_id` in the response is null if the category uses the default workflow.
+///
+/// **GET /categories/<category_id>/workflow**
+pub async fn get_workflow_by_category_id_endpoint(
+    State(state): State<TiraState>,
+    Path(category_id): Path<i64>,
+) -> Result<Response, TiraError> {
+    let (category_id, transitions) =
+        workflows::get_workflow_by_category_id(&state, Some(category_id)).await?;
+    let response = WorkflowResponse {
+        category_id,
+        transitions,
+    };
+    Ok(Json(response).into_response())
+}
+
+/// Endpoint for giving a category its own workflow.
+///
+/// Requires authentication as an admin.
+///
+/// **PUT /categories/<category_id>/workflow**
+///
+/// Takes the same JSON body as **PUT /workflow**.
+pub async fn put_workflow_by_category_id_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Path(category_id): Path<i64>,
+    Json(workflow): Json<Workflow>,
+) -> Result<Response, TiraError> {
+    permissions::require_admin(&user)?;
+    workflows::replace_workflow_by_category_id(&state, Some(category_id), workflow).await?;
+
+    let message = format!(
+        "Successfully replaced workflow of category with id {}!",
+        category_id
+    );
+    let response = StandardResponse { message };
+    Ok(Json(response).into_response())
+}
+
+/// Endpoint for removing the own workflow of a category so it uses the default workflow again.
+///
+/// Requires authentication as an admin.
+///
+/// **DELETE /categories/<category_id>/workflow**
+pub async fn delete_workflow_by_category_id_endpoint(
+    State(state): State<TiraState>,
+    Extension(user): Extension<User>,
+    Path(category_id): Path<i64>,
+) -> Result<Response, TiraError> {
+    permissions::require_admin(&user)?;
+    workflows::delete_workflow_by_category_id(&state, category_id).await?;
+
+    let message = format!(
+        "Successfully removed workflow of category with id {}!",
+        category_id
+    );
+    let response = StandardResponse { message };
+    Ok(Json(response).into_response())
+}
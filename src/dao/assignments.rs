@@ -1,15 +1,15 @@
 use crate::{models::Assignment, TiraState};
 use anyhow::Result;
-use sqlx::QueryBuilder;
+use sqlx::{Postgres, QueryBuilder, Transaction};
 
-/// DAO function for retrieving all assignments.
+/// DAO function for retrieving all current assignments.
 pub async fn get_assignments(
     state: &TiraState,
     assignee_id: Option<i64>,
     ticket_id: Option<i64>,
 ) -> Result<Vec<Assignment>> {
     let mut query = QueryBuilder::new(
-        "SELECT id, ticket_id, assignee_id, assigner_id, assigned from assignments where 1=1 ",
+        "SELECT id, ticket_id, assignee_id, assigner_id, assigned, unassigned, unassigner_id from assignments where unassigned IS NULL ",
     );
 
     if let Some(assignee_id) = assignee_id {
@@ -30,19 +30,88 @@ pub async fn get_assignments(
     Ok(assignments)
 }
 
-/// DAO function for updating assignments by ticket id.
-pub async fn update_assignments_by_ticket_id(
+/// DAO function for retrieving the current assignments of several tickets.
+pub async fn get_assignments_by_ticket_ids(
     state: &TiraState,
+    ticket_ids: Vec<i64>,
+) -> Result<Vec<Assignment>> {
+    let assignments = sqlx::query_as!(
+        Assignment,
+        "SELECT * FROM assignments WHERE ticket_id IN (SELECT unnest($1::bigint[])) AND unassigned IS NULL ORDER BY assigned, id",
+        &ticket_ids,
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(assignments)
+}
+
+/// DAO function for retrieving the current assignments of several tickets as part of a
+/// transaction.
+pub async fn get_assignments_by_ticket_ids_in_transaction(
+    tx: &mut Transaction<'_, Postgres>,
+    ticket_ids: Vec<i64>,
+) -> Result<Vec<Assignment>> {
+    let assignments = sqlx::query_as!(
+        Assignment,
+        "SELECT * FROM assignments WHERE ticket_id IN (SELECT unnest($1::bigint[])) AND unassigned IS NULL ORDER BY assigned, id",
+        &ticket_ids,
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    Ok(assignments)
+}
+
+/// DAO function for retrieving the current assignments of a ticket as part of a transaction.
+pub async fn get_assignments_by_ticket_id_in_transaction(
+    tx: &mut Transaction<'_, Postgres>,
+    ticket_id: i64,
+) -> Result<Vec<Assignment>> {
+    let assignments = sqlx::query_as!(
+        Assignment,
+        "SELECT * FROM assignments WHERE ticket_id = $1 AND unassigned IS NULL",
+        ticket_id
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    Ok(assignments)
+}
+
+/// DAO function for assigning several users to a ticket.
+pub async fn create_assignments_by_ticket_id(
+    tx: &mut Transaction<'_, Postgres>,
     ticket_id: i64,
-    assignee_ids: Vec<i64>,
+    assignee_ids: &[i64],
     assigner_id: i64,
 ) -> Result<()> {
-    //  TODO: use transaction
-    for id in assignee_ids {
-        sqlx::query!(
-            "UPDATE assignments SET assignee_id = $1, assigner_id = $2 WHERE ticket_id = $3 RETURNING ticket_id",id, assigner_id, ticket_id
-        ).fetch_all(&state.pool).await?;
-    }
-
+    sqlx::query!(
+        "INSERT INTO assignments (ticket_id, assignee_id, assigner_id) SELECT $1, unnest($2::bigint[]), $3",
+        ticket_id,
+        assignee_ids,
+        assigner_id
+    )
+    .execute(&mut **tx)
+    .await?;
     Ok(())
 }
+
+/// DAO function for unassigning several users from a ticket.
+///
+/// The assignments are kept and marked as unassigned so they stay in the ticket's history.
+///
+/// Returns the number of assignments that were ended.
+pub async fn unassign_by_ticket_id_and_assignee_ids(
+    tx: &mut Transaction<'_, Postgres>,
+    ticket_id: i64,
+    assignee_ids: &[i64],
+    unassigner_id: i64,
+) -> Result<u64> {
+    let result = sqlx::query!(
+        "UPDATE assignments SET unassigned = NOW(), unassigner_id = $3 WHERE ticket_id = $1 AND assignee_id = ANY($2::bigint[]) AND unassigned IS NULL",
+        ticket_id,
+        assignee_ids,
+        unassigner_id
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(result.rows_affected())
+}
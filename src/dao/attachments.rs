@@ -0,0 +1,83 @@
+use crate::{
+    models::{Attachment, CreateAttachment},
+    TiraState,
+};
+use anyhow::Result;
+use sqlx::{Postgres, Transaction};
+
+/// DAO function for recording a file uploaded to a ticket.
+///
+/// `checksum` is the SHA-256 of the content and `storage_key` where it was stored.
+pub async fn create_attachment(
+    tx: &mut Transaction<'_, Postgres>,
+    attachment: &CreateAttachment,
+    ticket_id: i64,
+    owner_id: i64,
+    checksum: &str,
+    storage_key: &str,
+) -> Result<i64> {
+    let result = sqlx::query!(
+        "INSERT INTO attachments (ticket_id, comment_id, owner_id, file_name, content_type, size, checksum, storage_key) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id",
+        ticket_id,
+        attachment.comment_id,
+        owner_id,
+        attachment.file_name,
+        attachment.content_type,
+        attachment.data.len() as i64,
+        checksum,
+        storage_key
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    Ok(result.id)
+}
+
+/// DAO function for retrieving an attachment by id.
+pub async fn get_attachment_by_id(state: &TiraState, attachment_id: i64) -> Result<Attachment> {
+    let attachment = sqlx::query_as!(
+        Attachment,
+        "SELECT * FROM attachments WHERE id = $1",
+        attachment_id
+    )
+    .fetch_one(&state.pool)
+    .await?;
+    Ok(attachment)
+}
+
+/// DAO function for retrieving the attachments of tickets, oldest first.
+pub async fn get_attachments_by_ticket_ids(
+    state: &TiraState,
+    ticket_ids: &[i64],
+) -> Result<Vec<Attachment>> {
+    let attachments = sqlx::query_as!(
+        Attachment,
+        "SELECT * FROM attachments WHERE ticket_id = ANY($1) ORDER BY uploaded, id",
+        ticket_ids
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(attachments)
+}
+
+/// DAO function for retrieving the attachments of tickets as part of a transaction, oldest first.
+pub async fn get_attachments_by_ticket_ids_in_transaction(
+    tx: &mut Transaction<'_, Postgres>,
+    ticket_ids: &[i64],
+) -> Result<Vec<Attachment>> {
+    let attachments = sqlx::query_as!(
+        Attachment,
+        "SELECT * FROM attachments WHERE ticket_id = ANY($1) ORDER BY uploaded, id",
+        ticket_ids
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    Ok(attachments)
+}
+
+/// DAO function for deleting an attachment by id.
+pub async fn delete_attachment_by_id(state: &TiraState, attachment_id: i64) -> Result<u64> {
+    let result = sqlx::query!("DELETE FROM attachments WHERE id = $1", attachment_id)
+        .execute(&state.pool)
+        .await?;
+    Ok(result.rows_affected())
+}
@@ -57,3 +57,18 @@ pub async fn get_category_by_id(state: &TiraState, user_id: i64) -> Result<Categ
         .await?;
     Ok(categories)
 }
+
+/// DAO function for retrieving categories by ids.
+pub async fn get_categories_by_ids(
+    state: &TiraState,
+    category_ids: Vec<i64>,
+) -> Result<Vec<Category>> {
+    let categories = sqlx::query_as!(
+        Category,
+        "SELECT * FROM categories WHERE id IN (SELECT unnest($1::bigint[]))",
+        &category_ids,
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(categories)
+}
@@ -1,15 +1,240 @@
-use crate::{models::patch::UpdateComment, TiraState};
+use crate::{
+    models::{Comment, CommentRevision, Mention},
+    TiraState,
+};
+use sqlx::{Postgres, Transaction};
 
-/// DAO function for updating a comment by id.
+/// How many users reacted to a comment with an emoji.
+pub struct CommentReactionCount {
+    pub comment_id: i64,
+    pub emoji: String,
+    pub count: i64,
+    /// Whether the user the counts were retrieved for is one of them.
+    pub reacted: bool,
+}
+
+/// DAO function for retrieving a comment by id.
+pub async fn get_comment_by_id(state: &TiraState, comment_id: i64) -> anyhow::Result<Comment> {
+    let comment = sqlx::query_as!(
+        Comment,
+        "SELECT id, ticket_id, commenter_id, content, content_source, content_format, commented, edited, deleted, parent_comment_id FROM comments WHERE id = $1",
+        comment_id
+    )
+    .fetch_one(&state.pool)
+    .await?;
+    Ok(comment)
+}
+
+/// DAO function for retrieving a comment by id as part of a transaction.
+pub async fn get_comment_by_id_in_transaction(
+    tx: &mut Transaction<'_, Postgres>,
+    comment_id: i64,
+) -> anyhow::Result<Comment> {
+    let comment = sqlx::query_as!(
+        Comment,
+        "SELECT id, ticket_id, commenter_id, content, content_source, content_format, commented, edited, deleted, parent_comment_id FROM comments WHERE id = $1",
+        comment_id
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    Ok(comment)
+}
+
+/// DAO function for retrieving a comment by id and locking it until the transaction ends.
+pub async fn get_comment_by_id_for_update(
+    tx: &mut Transaction<'_, Postgres>,
+    comment_id: i64,
+) -> anyhow::Result<Comment> {
+    let comment = sqlx::query_as!(
+        Comment,
+        "SELECT id, ticket_id, commenter_id, content, content_source, content_format, commented, edited, deleted, parent_comment_id FROM comments WHERE id = $1 FOR UPDATE",
+        comment_id
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    Ok(comment)
+}
+
+/// DAO function for updating the content of a comment that is not deleted.
+///
+/// `content` is the HTML the comment renders to and `content_source` what was written in
+/// `content_format`. `content_without_tags` is what gets indexed for searching.
 pub async fn update_comment_by_id(
+    tx: &mut Transaction<'_, Postgres>,
+    content: &str,
+    content_source: &str,
+    content_format: &str,
+    content_without_tags: &str,
+    comment_id: i64,
+) -> anyhow::Result<u64> {
+    let result = sqlx::query!(
+        "UPDATE comments SET content = $1, content_source = $2, content_format = $3, search_vector = to_tsvector('english', $4), edited = NOW() WHERE id = $5 AND deleted IS NULL",
+        content,
+        content_source,
+        content_format,
+        content_without_tags,
+        comment_id
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// DAO function for soft-deleting a comment by id.
+pub async fn delete_comment_by_id(state: &TiraState, comment_id: i64) -> anyhow::Result<u64> {
+    let result = sqlx::query!(
+        "UPDATE comments SET deleted = NOW() WHERE id = $1 AND deleted IS NULL",
+        comment_id
+    )
+    .execute(&state.pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// DAO function for recording the content a comment had before an edit.
+pub async fn create_comment_revision(
+    tx: &mut Transaction<'_, Postgres>,
+    comment: &Comment,
+    editor_id: i64,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "INSERT INTO comment_revisions (comment_id, content, content_source, content_format, editor_id) VALUES ($1, $2, $3, $4, $5)",
+        comment.id,
+        comment.content,
+        comment.content_source,
+        comment.content_format,
+        editor_id
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// DAO function for retrieving the revisions of a comment, oldest first.
+pub async fn get_revisions_by_comment_id(
     state: &TiraState,
-    comment: UpdateComment,
     comment_id: i64,
+) -> anyhow::Result<Vec<CommentRevision>> {
+    let revisions = sqlx::query_as!(
+        CommentRevision,
+        "SELECT * FROM comment_revisions WHERE comment_id = $1 ORDER BY revised, id",
+        comment_id
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(revisions)
+}
+
+/// DAO function for reacting to a comment with an emoji.
+///
+/// Reacting again with the same emoji changes nothing.
+pub async fn create_reaction(
+    state: &TiraState,
+    comment_id: i64,
+    user_id: i64,
+    emoji: &str,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "INSERT INTO comment_reactions (comment_id, user_id, emoji) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+        comment_id,
+        user_id,
+        emoji
+    )
+    .execute(&state.pool)
+    .await?;
+    Ok(())
+}
+
+/// DAO function for taking back a reaction to a comment.
+pub async fn delete_reaction(
+    state: &TiraState,
+    comment_id: i64,
+    user_id: i64,
+    emoji: &str,
 ) -> anyhow::Result<u64> {
-    let result = sqlx::query("UPDATE comments SET comment = $1 WHERE id = $2")
-        .bind(comment.content)
-        .bind(comment_id)
-        .execute(&state.pool)
-        .await?;
+    let result = sqlx::query!(
+        "DELETE FROM comment_reactions WHERE comment_id = $1 AND user_id = $2 AND emoji = $3",
+        comment_id,
+        user_id,
+        emoji
+    )
+    .execute(&state.pool)
+    .await?;
     Ok(result.rows_affected())
 }
+
+/// DAO function for counting the reactions to comments per emoji, as seen by `user_id`.
+///
+/// The emojis of a comment are in the order they were first used.
+pub async fn get_reaction_counts_by_comment_ids(
+    state: &TiraState,
+    comment_ids: &[i64],
+    user_id: i64,
+) -> anyhow::Result<Vec<CommentReactionCount>> {
+    let counts = sqlx::query_as!(
+        CommentReactionCount,
+        r#"SELECT comment_id, emoji, COUNT(*) AS "count!", BOOL_OR(user_id = $2) AS "reacted!" FROM comment_reactions WHERE comment_id = ANY($1) GROUP BY comment_id, emoji ORDER BY comment_id, MIN(created), emoji"#,
+        comment_ids,
+        user_id
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(counts)
+}
+
+/// DAO function for replacing the users a comment mentions.
+///
+/// Returns the ids of the users that were not mentioned before.
+pub async fn replace_mentions(
+    tx: &mut Transaction<'_, Postgres>,
+    comment_id: i64,
+    user_ids: &[i64],
+) -> anyhow::Result<Vec<i64>> {
+    sqlx::query!(
+        "DELETE FROM comment_mentions WHERE comment_id = $1 AND user_id <> ALL($2)",
+        comment_id,
+        user_ids
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    let result = sqlx::query!(
+        "INSERT INTO comment_mentions (comment_id, user_id) SELECT $1, unnest($2::bigint[]) ON CONFLICT DO NOTHING RETURNING user_id",
+        comment_id,
+        user_ids
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    Ok(result.into_iter().map(|row| row.user_id).collect())
+}
+
+/// DAO function for counting the comments that mention a user, leaving out deleted ones.
+pub async fn count_mentions_by_user_id(state: &TiraState, user_id: i64) -> anyhow::Result<i64> {
+    let result = sqlx::query!(
+        r#"SELECT COUNT(*) AS "cnt!" FROM comment_mentions JOIN comments ON comments.id = comment_mentions.comment_id WHERE comments.deleted IS NULL AND comment_mentions.user_id = $1"#,
+        user_id
+    )
+    .fetch_one(&state.pool)
+    .await?;
+    Ok(result.cnt)
+}
+
+/// DAO function for retrieving the comments that mention a user, newest first, leaving out
+/// deleted ones.
+pub async fn get_mentions_by_user_id(
+    state: &TiraState,
+    user_id: i64,
+    limit: i64,
+    offset: i64,
+) -> anyhow::Result<Vec<Mention>> {
+    let mentions = sqlx::query_as!(
+        Mention,
+        "SELECT comments.id AS comment_id, comments.ticket_id, tickets.subject AS ticket_subject, comments.commenter_id, comments.content, comments.commented, comment_mentions.mentioned FROM comment_mentions JOIN comments ON comments.id = comment_mentions.comment_id JOIN tickets ON tickets.id = comments.ticket_id WHERE comments.deleted IS NULL AND comment_mentions.user_id = $1 ORDER BY comment_mentions.mentioned DESC, comments.id DESC LIMIT $2 OFFSET $3",
+        user_id,
+        limit,
+        offset
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(mentions)
+}
@@ -0,0 +1,156 @@
+use crate::{models::DigestItem, service::email_templates::EmailBody, TiraState};
+use anyhow::Result;
+use sqlx::{Postgres, Transaction};
+
+/// DAO function for retrieving how often a user gets digests, if they ever chose.
+pub async fn get_digest_frequency_by_user_id(
+    state: &TiraState,
+    user_id: i64,
+) -> Result<Option<String>> {
+    let result = sqlx::query!(
+        "SELECT frequency FROM digest_settings WHERE user_id = $1",
+        user_id
+    )
+    .fetch_optional(&state.pool)
+    .await?;
+    Ok(result.map(|row| row.frequency))
+}
+
+/// DAO function for storing how often a user gets digests.
+///
+/// The next digest is counted from when the user first turned them on.
+pub async fn upsert_digest_frequency(
+    state: &TiraState,
+    user_id: i64,
+    frequency: &str,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO digest_settings (user_id, frequency) VALUES ($1, $2) ON CONFLICT (user_id) DO UPDATE SET frequency = excluded.frequency",
+        user_id,
+        frequency
+    )
+    .execute(&state.pool)
+    .await?;
+    Ok(())
+}
+
+/// DAO function for retrieving which of the given users collect their notifications in digests.
+pub async fn get_digest_user_ids(
+    state: &TiraState,
+    user_ids: Vec<i64>,
+    off_frequency: &str,
+) -> Result<Vec<i64>> {
+    let result = sqlx::query!(
+        "SELECT user_id FROM digest_settings WHERE user_id IN (SELECT unnest($1::bigint[])) AND frequency <> $2",
+        &user_ids,
+        off_frequency
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(result.into_iter().map(|row| row.user_id).collect())
+}
+
+/// DAO function for saving a notification for a user's next digest.
+pub async fn create_digest_item(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: i64,
+    ticket_id: i64,
+    body: &EmailBody,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO digest_items (user_id, ticket_id, body, text_body) VALUES ($1, $2, $3, $4)",
+        user_id,
+        ticket_id,
+        body.html,
+        body.text
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// DAO function for moving a user's digest to the back of the queue, after it could not be sent.
+///
+/// The notifications are kept for the next attempt.
+pub async fn postpone_digest(tx: &mut Transaction<'_, Postgres>, user_id: i64) -> Result<()> {
+    sqlx::query!(
+        "UPDATE digest_settings SET last_sent = NOW() WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// DAO function for claiming the users whose digest is due.
+///
+/// A digest is due once its period has passed since the last one and there is something in it.
+/// Users that turned digests off get whatever is left right away.
+/// The claimed users stay locked until the transaction ends and are skipped by other workers.
+///
+/// Returns the ids and digest frequencies of the users.
+pub async fn claim_due_digests(
+    tx: &mut Transaction<'_, Postgres>,
+    daily_frequency: &str,
+    weekly_frequency: &str,
+    limit: i64,
+) -> Result<Vec<(i64, String)>> {
+    let result = sqlx::query!(
+        r#"SELECT user_id, frequency FROM digest_settings
+        WHERE EXISTS (SELECT 1 FROM digest_items WHERE digest_items.user_id = digest_settings.user_id)
+        AND CASE frequency
+            WHEN $1 THEN last_sent <= NOW() - INTERVAL '1 day'
+            WHEN $2 THEN last_sent <= NOW() - INTERVAL '7 days'
+            ELSE TRUE
+        END
+        ORDER BY last_sent
+        LIMIT $3
+        FOR UPDATE SKIP LOCKED"#,
+        daily_frequency,
+        weekly_frequency,
+        limit
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    Ok(result
+        .into_iter()
+        .map(|row| (row.user_id, row.frequency))
+        .collect())
+}
+
+/// DAO function for retrieving the notifications waiting for a user's digest, grouped by ticket.
+pub async fn get_digest_items_by_user_id(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: i64,
+) -> Result<Vec<DigestItem>> {
+    let items = sqlx::query_as!(
+        DigestItem,
+        "SELECT digest_items.id, digest_items.ticket_id, tickets.subject AS ticket_subject, digest_items.body, digest_items.text_body, digest_items.created FROM digest_items JOIN tickets ON tickets.id = digest_items.ticket_id WHERE digest_items.user_id = $1 ORDER BY digest_items.ticket_id, digest_items.created, digest_items.id",
+        user_id
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    Ok(items)
+}
+
+/// DAO function for removing the notifications that went out in a digest and remembering when
+/// it was sent.
+pub async fn mark_digest_sent(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: i64,
+    item_ids: &[i64],
+) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM digest_items WHERE id = ANY($1::bigint[])",
+        item_ids
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE digest_settings SET last_sent = NOW() WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
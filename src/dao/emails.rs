@@ -0,0 +1,143 @@
+use crate::{
+    models::{Count, OutboxEmail},
+    service::emails::Email,
+    TiraState,
+};
+use anyhow::Result;
+use sqlx::{Postgres, QueryBuilder, Transaction};
+
+/// DAO function for adding an email to the outbox as part of a transaction.
+///
+/// Returns the id of the outbox entry.
+pub async fn create_email_in_transaction(
+    tx: &mut Transaction<'_, Postgres>,
+    email: &Email,
+) -> Result<i64> {
+    let result = sqlx::query!(
+        "INSERT INTO email_outbox (recipient, subject, body, text_body, reply_to) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+        email.to,
+        email.subject,
+        email.body,
+        email.text_body,
+        email.reply_to
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    Ok(result.id)
+}
+
+/// DAO function for claiming the pending emails that are due.
+///
+/// The next attempt at the emails is moved `lease_secs` seconds ahead, so that other workers skip
+/// them until then, and the attempt is counted right away, so that it counts even if it never
+/// finishes.
+pub async fn claim_due_emails(
+    state: &TiraState,
+    pending_status: &str,
+    limit: i64,
+    lease_secs: i64,
+) -> Result<Vec<OutboxEmail>> {
+    let emails = sqlx::query_as!(
+        OutboxEmail,
+        "UPDATE email_outbox SET attempts = attempts + 1, next_attempt = NOW() + $3::bigint * INTERVAL '1 second' WHERE id IN (SELECT id FROM email_outbox WHERE status = $1 AND next_attempt <= NOW() ORDER BY next_attempt, id LIMIT $2 FOR UPDATE SKIP LOCKED) RETURNING *",
+        pending_status,
+        limit,
+        lease_secs
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(emails)
+}
+
+/// DAO function for marking an email as sent.
+pub async fn mark_email_sent(state: &TiraState, id: i64, sent_status: &str) -> Result<()> {
+    sqlx::query!(
+        "UPDATE email_outbox SET status = $2, last_error = NULL, sent = NOW() WHERE id = $1",
+        id,
+        sent_status
+    )
+    .execute(&state.pool)
+    .await?;
+    Ok(())
+}
+
+/// DAO function for recording a failed attempt at sending an email.
+///
+/// The email is moved to `status` and tried again in `retry_in_secs` seconds if it is still
+/// pending.
+pub async fn mark_email_failed(
+    state: &TiraState,
+    id: i64,
+    status: &str,
+    error: &str,
+    retry_in_secs: i64,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE email_outbox SET status = $2, last_error = $3, next_attempt = NOW() + $4::bigint * INTERVAL '1 second' WHERE id = $1",
+        id,
+        status,
+        error,
+        retry_in_secs
+    )
+    .execute(&state.pool)
+    .await?;
+    Ok(())
+}
+
+fn push_status_filter(query: &mut QueryBuilder<'_, Postgres>, status: Option<String>) {
+    if let Some(status) = status {
+        query.push(" and status = ");
+        query.push_bind(status);
+    }
+}
+
+/// DAO function for counting the emails in the outbox.
+pub async fn count_emails(state: &TiraState, status: Option<String>) -> Result<i64> {
+    let mut query = QueryBuilder::new("SELECT COUNT(*) AS cnt FROM email_outbox WHERE 1=1");
+    push_status_filter(&mut query, status);
+
+    let count = query
+        .build_query_as::<Count>()
+        .fetch_one(&state.pool)
+        .await?;
+    Ok(count.cnt.unwrap_or(0))
+}
+
+/// DAO function for retrieving the emails in the outbox, newest first.
+pub async fn get_emails(
+    state: &TiraState,
+    status: Option<String>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<OutboxEmail>> {
+    let mut query = QueryBuilder::new("SELECT * FROM email_outbox WHERE 1=1");
+    push_status_filter(&mut query, status);
+    query.push(" ORDER BY created DESC, id DESC LIMIT ");
+    query.push_bind(limit);
+    query.push(" OFFSET ");
+    query.push_bind(offset);
+
+    let emails = query
+        .build_query_as::<OutboxEmail>()
+        .fetch_all(&state.pool)
+        .await?;
+    Ok(emails)
+}
+
+/// DAO function for queueing an email in `from_status` to be sent again right away.
+pub async fn retry_email_by_id(
+    state: &TiraState,
+    id: i64,
+    from_status: &str,
+    pending_status: &str,
+) -> Result<u64> {
+    let result = sqlx::query!(
+        "UPDATE email_outbox SET status = $3, attempts = 0, next_attempt = NOW() WHERE id = $1 AND status = $2",
+        id,
+        from_status,
+        pending_status
+    )
+    .execute(&state.pool)
+    .await?;
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,117 @@
+use crate::TiraState;
+use anyhow::Result;
+
+/// A column that user-written HTML is stored in.
+#[derive(Debug, Clone, Copy)]
+pub enum StoredHtml {
+    TicketDescriptions,
+    CommentContents,
+    CommentRevisionContents,
+}
+
+impl StoredHtml {
+    pub const ALL: [StoredHtml; 3] = [
+        StoredHtml::TicketDescriptions,
+        StoredHtml::CommentContents,
+        StoredHtml::CommentRevisionContents,
+    ];
+}
+
+/// The HTML stored in a row, with what it was rendered from.
+pub struct HtmlRow {
+    pub id: i64,
+    pub source: String,
+    pub format: String,
+    pub html: String,
+}
+
+/// DAO function for retrieving the HTML stored in a column and its source, by id, starting after
+/// `after_id`.
+pub async fn get_html_after_id(
+    state: &TiraState,
+    column: StoredHtml,
+    after_id: i64,
+    limit: i64,
+) -> Result<Vec<HtmlRow>> {
+    let rows = match column {
+        StoredHtml::TicketDescriptions => {
+            sqlx::query_as!(
+                HtmlRow,
+                r#"SELECT id, COALESCE(description_source, description) AS "source!", content_format AS format, description AS "html!" FROM tickets WHERE id > $1 AND description IS NOT NULL ORDER BY id LIMIT $2"#,
+                after_id,
+                limit
+            )
+            .fetch_all(&state.pool)
+            .await?
+        }
+        StoredHtml::CommentContents => {
+            sqlx::query_as!(
+                HtmlRow,
+                "SELECT id, content_source AS source, content_format AS format, content AS html FROM comments WHERE id > $1 ORDER BY id LIMIT $2",
+                after_id,
+                limit
+            )
+            .fetch_all(&state.pool)
+            .await?
+        }
+        StoredHtml::CommentRevisionContents => {
+            sqlx::query_as!(
+                HtmlRow,
+                "SELECT id, content_source AS source, content_format AS format, content AS html FROM comment_revisions WHERE id > $1 ORDER BY id LIMIT $2",
+                after_id,
+                limit
+            )
+            .fetch_all(&state.pool)
+            .await?
+        }
+    };
+    Ok(rows)
+}
+
+/// DAO function for replacing the HTML stored in a row and its source.
+///
+/// `text` is the HTML without tags, which descriptions and comments are indexed by for searching.
+pub async fn update_html(
+    state: &TiraState,
+    column: StoredHtml,
+    id: i64,
+    source: &str,
+    html: &str,
+    text: &str,
+) -> Result<()> {
+    match column {
+        StoredHtml::TicketDescriptions => {
+            sqlx::query!(
+                "UPDATE tickets SET description_source = $2, description = $3, search_vector = setweight(to_tsvector('english', subject), 'A') || setweight(to_tsvector('english', $4), 'B') WHERE id = $1",
+                id,
+                source,
+                html,
+                text
+            )
+            .execute(&state.pool)
+            .await?;
+        }
+        StoredHtml::CommentContents => {
+            sqlx::query!(
+                "UPDATE comments SET content_source = $2, content = $3, search_vector = to_tsvector('english', $4) WHERE id = $1",
+                id,
+                source,
+                html,
+                text
+            )
+            .execute(&state.pool)
+            .await?;
+        }
+        StoredHtml::CommentRevisionContents => {
+            sqlx::query!(
+                "UPDATE comment_revisions SET content_source = $2, content = $3 WHERE id = $1",
+                id,
+                source,
+                html
+            )
+            .execute(&state.pool)
+            .await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,10 @@
+use crate::TiraState;
+use anyhow::Result;
+
+/// DAO function for sending a notification to everyone listening on a channel.
+pub async fn notify(state: &TiraState, channel: &str, payload: &str) -> Result<()> {
+    sqlx::query!("SELECT pg_notify($1, $2)", channel, payload)
+        .execute(&state.pool)
+        .await?;
+    Ok(())
+}
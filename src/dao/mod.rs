@@ -1,9 +1,20 @@
 pub mod assignments;
+pub mod attachments;
 pub mod categories;
 pub mod comments;
+pub mod digests;
+pub mod emails;
+pub mod html;
+pub mod live_events;
+pub mod notifications;
+pub mod search;
 pub mod sessions;
+pub mod ticket_events;
 pub mod tickets;
 pub mod users;
+pub mod watchers;
+pub mod webhooks;
+pub mod workflows;
 
 // fn get_user_from_session_uuid(conn: TiraDbConn, session_uuid: String) {
 //     use crate::schema::sessions::dsl::*;
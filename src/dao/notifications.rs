@@ -0,0 +1,65 @@
+use crate::{models::User, TiraState};
+use anyhow::Result;
+
+/// DAO function for retrieving the notification preferences of a user.
+///
+/// Returns the events that have a preference stored and whether they are emailed.
+pub async fn get_notification_preferences_by_user_id(
+    state: &TiraState,
+    user_id: i64,
+) -> Result<Vec<(String, bool)>> {
+    let result = sqlx::query!(
+        "SELECT event, email FROM notification_preferences WHERE user_id = $1",
+        user_id
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(result
+        .into_iter()
+        .map(|row| (row.event, row.email))
+        .collect())
+}
+
+/// DAO function for storing whether a user wants emails about an event.
+pub async fn upsert_notification_preference(
+    state: &TiraState,
+    user_id: i64,
+    event: &str,
+    email: bool,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO notification_preferences (user_id, event, email) VALUES ($1, $2, $3) ON CONFLICT (user_id, event) DO UPDATE SET email = excluded.email",
+        user_id,
+        event,
+        email
+    )
+    .execute(&state.pool)
+    .await?;
+    Ok(())
+}
+
+/// DAO function for retrieving which of the given users should be emailed about an event.
+///
+/// Skips archived users, users without an email address and users that turned off the event.
+pub async fn get_email_recipients(
+    state: &TiraState,
+    user_ids: Vec<i64>,
+    event: &str,
+) -> Result<Vec<User>> {
+    let users = sqlx::query_as!(
+        User,
+        r#"SELECT * FROM users
+        WHERE id IN (SELECT unnest($1::bigint[]))
+        AND NOT archived
+        AND email_address IS NOT NULL
+        AND COALESCE(
+            (SELECT email FROM notification_preferences WHERE user_id = users.id AND event = $2),
+            TRUE
+        )"#,
+        &user_ids,
+        event
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(users)
+}
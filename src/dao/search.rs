@@ -0,0 +1,101 @@
+use crate::{
+    dao::tickets::push_ticket_filters,
+    models::{Count, SearchHit, TicketFilter},
+    service::{
+        search::{MATCH_END, MATCH_START},
+        tickets::HTML_TAG_PATTERN,
+    },
+    TiraState,
+};
+use anyhow::Result;
+use sqlx::{Postgres, QueryBuilder};
+
+/// Pushes the FROM and WHERE clauses shared by searching and counting search hits.
+///
+/// Every ticket that matches, either itself or through one of its comments, appears once as
+/// `best` with its best ranking hit. `q` is the parsed search text.
+fn push_search_from<'a>(
+    query: &mut QueryBuilder<'a, Postgres>,
+    text: &'a str,
+    filter: &'a TicketFilter,
+) {
+    query.push(
+        " FROM (SELECT DISTINCT ON (ticket_id) ticket_id, comment_id, rank FROM (\
+         SELECT id AS ticket_id, NULL::BIGINT AS comment_id, ts_rank(search_vector, q) AS rank \
+         FROM tickets, websearch_to_tsquery('english', ",
+    );
+    query.push_bind(text);
+    query.push(
+        ") q WHERE search_vector @@ q \
+         UNION ALL \
+         SELECT ticket_id, id, ts_rank(search_vector, q) \
+         FROM comments, websearch_to_tsquery('english', ",
+    );
+    query.push_bind(text);
+    query.push(
+        ") q WHERE search_vector @@ q AND deleted IS NULL\
+         ) hits ORDER BY ticket_id, rank DESC, comment_id NULLS FIRST) best \
+         JOIN tickets ON tickets.id = best.ticket_id \
+         LEFT JOIN comments ON comments.id = best.comment_id \
+         CROSS JOIN websearch_to_tsquery('english', ",
+    );
+    query.push_bind(text);
+    query.push(") q WHERE 1=1");
+    push_ticket_filters(query, filter);
+}
+
+/// DAO function for counting the tickets that match a search.
+pub async fn count_search_hits(
+    state: &TiraState,
+    text: &str,
+    filter: &TicketFilter,
+) -> Result<i64> {
+    let mut query = QueryBuilder::new("SELECT COUNT(*) AS cnt");
+    push_search_from(&mut query, text, filter);
+
+    let count = query
+        .build_query_as::<Count>()
+        .fetch_one(&state.pool)
+        .await?;
+    Ok(count.cnt.unwrap_or(0))
+}
+
+/// DAO function for searching the subject and description of tickets and the content of comments.
+///
+/// Returns the matching tickets, best match first, with a snippet of the match. The snippet is
+/// neither escaped nor sanitized and has its matches between `MATCH_START` and `MATCH_END`.
+pub async fn search_tickets(
+    state: &TiraState,
+    text: &str,
+    filter: &TicketFilter,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<SearchHit>> {
+    let mut query = QueryBuilder::new(
+        "SELECT tickets.id, tickets.subject, tickets.category_id, tickets.priority, \
+         tickets.status, tickets.created, tickets.reporter_id, best.comment_id, best.rank, \
+         ts_headline('english', CASE WHEN best.comment_id IS NULL \
+         THEN tickets.subject || ' ' || regexp_replace(coalesce(tickets.description, ''), ",
+    );
+    query.push_bind(HTML_TAG_PATTERN);
+    query.push(", ' ', 'g') ELSE regexp_replace(comments.content, ");
+    query.push_bind(HTML_TAG_PATTERN);
+    query.push(", ' ', 'g') END, q, ");
+    query.push_bind(format!(
+        "StartSel={}, StopSel={}, MaxFragments=2",
+        MATCH_START, MATCH_END
+    ));
+    query.push(") AS snippet");
+    push_search_from(&mut query, text, filter);
+
+    query.push(" ORDER BY best.rank DESC, tickets.id DESC LIMIT ");
+    query.push_bind(limit);
+    query.push(" OFFSET ");
+    query.push_bind(offset);
+
+    let hits = query
+        .build_query_as::<SearchHit>()
+        .fetch_all(&state.pool)
+        .await?;
+    Ok(hits)
+}
@@ -3,7 +3,7 @@ use chrono::{Duration, Utc};
 use crate::TiraState;
 use std::env;
 
-/// DAO function for retrieving session by session_uuid.
+// DAO function for retrieving session by session_uuid.
 // pub async fn get_session_from_session_uuid(conn: &TiraDbConn, session_uuid: String) -> QueryResult<Session> {
 //     use crate::schema::sessions::dsl::*;
 
@@ -0,0 +1,47 @@
+use crate::{
+    models::{TicketEvent, TicketFieldChange},
+    TiraState,
+};
+use anyhow::Result;
+use sqlx::{Postgres, QueryBuilder, Transaction};
+
+/// DAO function for recording changes to the fields of a ticket.
+pub async fn create_ticket_events(
+    tx: &mut Transaction<'_, Postgres>,
+    ticket_id: i64,
+    actor_id: i64,
+    changes: &[TicketFieldChange],
+) -> Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let mut query = QueryBuilder::new(
+        "INSERT INTO ticket_events (ticket_id, actor_id, field, old_value, new_value) ",
+    );
+    query.push_values(changes, |mut row, change| {
+        row.push_bind(ticket_id)
+            .push_bind(actor_id)
+            .push_bind(change.field)
+            .push_bind(change.old_value.clone())
+            .push_bind(change.new_value.clone());
+    });
+
+    query.build().execute(&mut **tx).await?;
+    Ok(())
+}
+
+/// DAO function for retrieving the events of a ticket by ticket id.
+pub async fn get_ticket_events_by_ticket_id(
+    state: &TiraState,
+    ticket_id: i64,
+) -> Result<Vec<TicketEvent>> {
+    let events = sqlx::query_as!(
+        TicketEvent,
+        "SELECT * FROM ticket_events WHERE ticket_id = $1 ORDER BY changed, id",
+        ticket_id
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(events)
+}
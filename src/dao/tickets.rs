@@ -1,22 +1,31 @@
 use crate::{
+    dao::{assignments, attachments, categories, users, watchers},
     models::{
-        patch::UpdateTicket, Assignment, Comment, Count, CreateTicket, Ticket,
-        TicketWithoutDescription,
+        patch::UpdateTicket,
+        success::{
+            AssignmentResponse, AttachmentResponse, CommentResponse, ReactionCountResponse,
+            TicketResponse, TicketWithoutDescriptionResponse,
+        },
+        Assignment, Attachment, Category, Comment, Count, CreateTicket, SearchHit, Ticket,
+        TicketFilter, TicketWithReporterAsUser, TicketWithoutDescription, User,
     },
+    service::ServiceError,
     TiraState,
 };
-use anyhow::Result;
-use sqlx::QueryBuilder;
+use anyhow::{Context, Result};
+use chrono::NaiveTime;
+use sqlx::{Postgres, QueryBuilder, Transaction};
+use std::collections::HashMap;
 
 /// DAO function for creating an assignment by ticket id and assigner id.
 pub async fn create_assignment_by_ticket_id_and_assigner_id(
-    state: &TiraState,
+    tx: &mut Transaction<'_, Postgres>,
     assignee_id: i64,
     ticket_id: i64,
     assigner_id: i64,
 ) -> Result<i64> {
     let result =  sqlx::query!("INSERT INTO assignments (assignee_id, ticket_id, assigner_id, assigned) VALUES ($1, $2, $3, NOW()) RETURNING id",assignee_id,ticket_id, assigner_id)
-    .fetch_all(&state.pool).await?;
+    .fetch_all(&mut **tx).await?;
 
     let id = result.first().unwrap().id;
 
@@ -24,19 +33,31 @@ pub async fn create_assignment_by_ticket_id_and_assigner_id(
 }
 
 /// DAO function for creating a comment by ticket id.
+///
+/// `content` is the HTML the comment renders to and `content_source` what was written in
+/// `content_format`.
+#[allow(clippy::too_many_arguments)]
 pub async fn create_comment_by_ticket_id_and_commenter_id(
-    state: &TiraState,
+    tx: &mut Transaction<'_, Postgres>,
     content: &str,
+    content_source: &str,
+    content_format: &str,
+    search_text: &str,
     ticket_id: i64,
     commenter_id: i64,
+    parent_comment_id: Option<i64>,
 ) -> Result<i64> {
     let result = sqlx::query!(
-        "INSERT INTO comments (ticket_id, commenter_id, content) VALUES ($1, $2, $3) RETURNING id",
+        "INSERT INTO comments (ticket_id, commenter_id, content, search_vector, parent_comment_id, content_source, content_format) VALUES ($1, $2, $3, to_tsvector('english', $4), $5, $6, $7) RETURNING id",
         ticket_id,
         commenter_id,
         content,
+        search_text,
+        parent_comment_id,
+        content_source,
+        content_format,
     )
-    .fetch_one(&state.pool)
+    .fetch_one(&mut **tx)
     .await?;
 
     Ok(result.id)
@@ -44,46 +65,63 @@ pub async fn create_comment_by_ticket_id_and_commenter_id(
 
 /// DAO function for creating a ticket by reporter id and assigning those tickets.
 ///
+/// The reporter and the assignees start watching the ticket.
+/// The description of `ticket` is the HTML it renders to and `description_source` what was
+/// written. `description_text` is the description without HTML, which gets indexed for search.
+///
 /// Returns the id of the new ticket.
 pub async fn create_ticket_by_reporter_id(
-    state: &TiraState,
+    tx: &mut Transaction<'_, Postgres>,
     ticket: &CreateTicket,
+    description_source: Option<&str>,
+    description_text: &str,
     reporter_id: i64,
 ) -> Result<i64> {
-    // TODO: make a sql transaction
     let result =  sqlx::query!(
-        "INSERT INTO tickets (category_id, subject, description, status, priority, reporter_id) VALUES ($1,$2,$3,$4,$5,$6) RETURNING id", 
+        "INSERT INTO tickets (category_id, subject, description, status, priority, reporter_id, search_vector, description_source, content_format) VALUES ($1,$2,$3,$4,$5,$6, setweight(to_tsvector('english', $2), 'A') || setweight(to_tsvector('english', $7), 'B'), $8, $9) RETURNING id", 
         ticket.category_id,
         ticket.subject.clone(),
         ticket.description.clone(),
         ticket.status.clone(),
         ticket.priority.clone(),
         reporter_id,
+        description_text,
+        description_source,
+        ticket.content_format.as_deref().unwrap_or_default(),
     )
-     .fetch_one(&state.pool).await?;
+     .fetch_one(&mut **tx).await?;
 
     let ticket_id = result.id;
 
-    for assignee in &ticket.assignee_ids {
-        sqlx::query!(
-            "INSERT INTO assignments (ticket_id, assignee_id, assigner_id) VALUES ($1,$2,$3)",
-            ticket_id,
-            assignee,
-            reporter_id
-        )
-        .fetch_one(&state.pool)
+    assignments::create_assignments_by_ticket_id(tx, ticket_id, &ticket.assignee_ids, reporter_id)
         .await?;
-    }
 
-    // tx.commit().await?;
+    let mut watcher_ids = ticket.assignee_ids.clone();
+    watcher_ids.push(reporter_id);
+    watchers::create_watchers(tx, ticket_id, &watcher_ids).await?;
 
     Ok(result.id)
 }
 
-/// DAO function for retrieving assignments by ticket id.
+/// DAO function for retrieving the current assignments by ticket id.
 pub async fn get_assignments_by_ticket_id(
     state: &TiraState,
     ticket_id: i64,
+) -> Result<Vec<Assignment>> {
+    let assignments = sqlx::query_as!(
+        Assignment,
+        "SELECT * FROM assignments WHERE ticket_id = $1 AND unassigned IS NULL",
+        ticket_id
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(assignments)
+}
+
+/// DAO function for retrieving every assignment a ticket ever had, including ended ones.
+pub async fn get_all_assignments_by_ticket_id(
+    state: &TiraState,
+    ticket_id: i64,
 ) -> Result<Vec<Assignment>> {
     let assignments = sqlx::query_as!(
         Assignment,
@@ -99,7 +137,7 @@ pub async fn get_assignments_by_ticket_id(
 pub async fn get_comments_by_ticket_id(state: &TiraState, ticket_id: i64) -> Result<Vec<Comment>> {
     let comments = sqlx::query_as!(
         Comment,
-        "SELECT * FROM comments WHERE ticket_id = $1",
+        "SELECT id, ticket_id, commenter_id, content, content_source, content_format, commented, edited, deleted, parent_comment_id FROM comments WHERE ticket_id = $1 ORDER BY commented, id",
         ticket_id
     )
     .fetch_all(&state.pool)
@@ -109,9 +147,43 @@ pub async fn get_comments_by_ticket_id(state: &TiraState, ticket_id: i64) -> Res
 
 /// DAO function for retrieving a ticket by id.
 pub async fn get_ticket_by_id(state: &TiraState, ticket_id: i64) -> Result<Ticket> {
-    let ticket = sqlx::query_as!(Ticket, "SELECT * FROM tickets WHERE id = $1", ticket_id)
-        .fetch_one(&state.pool)
-        .await?;
+    let ticket = sqlx::query_as!(
+        Ticket,
+        "SELECT id, subject, description, description_source, content_format, category_id, priority, status, created, reporter_id FROM tickets WHERE id = $1",
+        ticket_id
+    )
+    .fetch_one(&state.pool)
+    .await?;
+    Ok(ticket)
+}
+
+/// DAO function for retrieving a ticket by id as part of a transaction.
+pub async fn get_ticket_by_id_in_transaction(
+    tx: &mut Transaction<'_, Postgres>,
+    ticket_id: i64,
+) -> Result<Ticket> {
+    let ticket = sqlx::query_as!(
+        Ticket,
+        "SELECT id, subject, description, description_source, content_format, category_id, priority, status, created, reporter_id FROM tickets WHERE id = $1",
+        ticket_id
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    Ok(ticket)
+}
+
+/// DAO function for retrieving a ticket by id and locking it until the transaction ends.
+pub async fn get_ticket_by_id_for_update(
+    tx: &mut Transaction<'_, Postgres>,
+    ticket_id: i64,
+) -> Result<Ticket> {
+    let ticket = sqlx::query_as!(
+        Ticket,
+        "SELECT id, subject, description, description_source, content_format, category_id, priority, status, created, reporter_id FROM tickets WHERE id = $1 FOR UPDATE",
+        ticket_id
+    )
+    .fetch_one(&mut **tx)
+    .await?;
     Ok(ticket)
 }
 
@@ -119,7 +191,7 @@ pub async fn get_ticket_by_id(state: &TiraState, ticket_id: i64) -> Result<Ticke
 pub async fn get_tickets_by_ids(state: &TiraState, ticket_ids: Vec<i64>) -> Result<Vec<Ticket>> {
     let tickets = sqlx::query_as!(
         Ticket,
-        "SELECT * FROM tickets WHERE id in (SELECT unnest($1::integer[]))",
+        "SELECT id, subject, description, description_source, content_format, category_id, priority, status, created, reporter_id FROM tickets WHERE id in (SELECT unnest($1::bigint[]))",
         &ticket_ids
     )
     .fetch_all(&state.pool)
@@ -127,63 +199,475 @@ pub async fn get_tickets_by_ids(state: &TiraState, ticket_ids: Vec<i64>) -> Resu
     Ok(tickets)
 }
 
+/// Maps a `sort_by` value to the column it sorts on.
+///
+/// Only columns in this list can be sorted on so user input never ends up in the query.
+fn ticket_sort_column(sort_by: &str) -> Option<&'static str> {
+    match sort_by {
+        "id" => Some("id"),
+        "subject" => Some("subject"),
+        "category" => Some("category_id"),
+        "priority" => Some("priority"),
+        "status" => Some("status"),
+        "created" => Some("created"),
+        "reporter" => Some("reporter_id"),
+        _ => None,
+    }
+}
+
+/// Pushes a ` and ...` clause onto `query` for every filter that is set.
+///
+/// Columns are qualified with `tickets.` so the filters also work when other tables are joined.
+pub fn push_ticket_filters(query: &mut QueryBuilder<'_, Postgres>, filter: &TicketFilter) {
+    if let Some(reporter_id) = filter.reporter_id {
+        query.push(" and tickets.reporter_id = ");
+        query.push_bind(reporter_id);
+    }
+    if let Some(open) = filter.open {
+        query.push(" and tickets.status IN (SELECT name FROM statuses WHERE open = ");
+        query.push_bind(open);
+        query.push(")");
+    }
+    if let Some(status) = filter.status.clone() {
+        query.push(" and tickets.status = ");
+        query.push_bind(status);
+    }
+    if let Some(priority) = filter.priority.clone() {
+        query.push(" and tickets.priority = ");
+        query.push_bind(priority);
+    }
+    if let Some(category_id) = filter.category_id {
+        query.push(" and tickets.category_id = ");
+        query.push_bind(category_id);
+    }
+    if let Some(assignee_id) = filter.assignee_id {
+        query.push(" and tickets.id IN (SELECT ticket_id FROM assignments WHERE unassigned IS NULL AND assignee_id = ");
+        query.push_bind(assignee_id);
+        query.push(")");
+    }
+    if let Some(created_after) = filter.created_after {
+        query.push(" and tickets.created >= ");
+        query.push_bind(created_after.and_time(NaiveTime::MIN));
+    }
+    if let Some(created_before) = filter.created_before {
+        query.push(" and tickets.created < ");
+        query.push_bind(created_before.and_time(NaiveTime::MIN));
+    }
+}
+
+/// DAO function for counting all tickets that match a filter.
+pub async fn count_tickets(state: &TiraState, filter: &TicketFilter) -> Result<i64> {
+    let mut query = QueryBuilder::new("SELECT COUNT(*) AS cnt FROM tickets WHERE 1=1");
+    push_ticket_filters(&mut query, filter);
+
+    let count = query
+        .build_query_as::<Count>()
+        .fetch_one(&state.pool)
+        .await?;
+    Ok(count.cnt.unwrap_or(0))
+}
+
 /// DAO function for retrieving all tickets.
 pub async fn get_tickets(
     state: &TiraState,
-    // limit: Option<i64>,
-    // offset: Option<i64>,
-    // filter_reporter_id: Option<i64>,
-    // filter_open: Option<bool>,
-    // sort_by: Option<String>,
-    // order_by: Option<String>,
+    filter: &TicketFilter,
+    limit: i64,
+    offset: i64,
+    sort_by: &str,
+    order_by: &str,
 ) -> Result<Vec<TicketWithoutDescription>> {
-    // TODO use query builder - right now ignore all the filter options
+    let sort_column = ticket_sort_column(sort_by)
+        .ok_or_else(|| ServiceError::BadRequest(format!("Cannot sort tickets by '{}'", sort_by)))?;
+    let order = match order_by.to_lowercase().as_str() {
+        "asc" => "ASC",
+        "desc" => "DESC",
+        _ => {
+            return Err(
+                ServiceError::BadRequest("Order must be 'asc' or 'desc'".to_string()).into(),
+            )
+        }
+    };
 
-    let tickets = sqlx::query_as!(
-        TicketWithoutDescription,
-        "SELECT id, subject, category_id, priority, status, created, reporter_id FROM tickets"
+    let mut query = QueryBuilder::new(
+        "SELECT id, subject, category_id, priority, status, created, reporter_id FROM tickets WHERE 1=1",
+    );
+    push_ticket_filters(&mut query, filter);
+
+    // Ties are broken by id so that paging through the results is stable
+    query.push(format!(
+        " ORDER BY {} {}, id {} LIMIT ",
+        sort_column, order, order
+    ));
+    query.push_bind(limit);
+    query.push(" OFFSET ");
+    query.push_bind(offset);
+
+    let tickets = query
+        .build_query_as::<TicketWithoutDescription>()
+        .fetch_all(&state.pool)
+        .await?;
+    Ok(tickets)
+}
+
+/// DAO function for re-indexing the subject and description of a ticket for search.
+///
+/// `description_text` is the description without HTML.
+pub async fn update_ticket_search_vector(
+    tx: &mut Transaction<'_, Postgres>,
+    ticket_id: i64,
+    subject: &str,
+    description_text: &str,
+) -> Result<u64> {
+    let result = sqlx::query!(
+        "UPDATE tickets SET search_vector = setweight(to_tsvector('english', $1), 'A') || setweight(to_tsvector('english', $2), 'B') WHERE id = $3",
+        subject,
+        description_text,
+        ticket_id
     )
-    .fetch_all(&state.pool)
+    .execute(&mut **tx)
     .await?;
-    Ok(tickets)
+    Ok(result.rows_affected())
 }
 
 /// DAO function for updating a ticket by id.
+///
+/// The description of `ticket` is the HTML it renders to and `description_source` what was
+/// written.
 pub async fn update_ticket_by_id(
-    state: &TiraState,
+    tx: &mut Transaction<'_, Postgres>,
     ticket: &UpdateTicket,
+    description_source: Option<&str>,
     ticket_id: i64,
 ) -> Result<u64> {
     let mut query = QueryBuilder::new("UPDATE TICKETS SET ");
+    let mut set = query.separated(", ");
     if let Some(category_id) = ticket.category_id {
-        query.push("category_id = ");
-        query.push_bind(category_id);
+        set.push("category_id = ")
+            .push_bind_unseparated(category_id);
     }
     if let Some(subject) = ticket.subject.clone() {
-        query.push("subject = ");
-        query.push_bind(subject);
+        set.push("subject = ").push_bind_unseparated(subject);
     }
     if let Some(description) = ticket.description.clone() {
-        query.push("description = ");
-        query.push_bind(description);
+        set.push("description = ")
+            .push_bind_unseparated(description);
+    }
+    if let Some(description_source) = description_source {
+        set.push("description_source = ")
+            .push_bind_unseparated(description_source.to_string());
+    }
+    if let Some(content_format) = ticket.content_format.clone() {
+        set.push("content_format = ")
+            .push_bind_unseparated(content_format);
     }
     if let Some(status) = ticket.status.clone() {
-        query.push("status = ");
-        query.push_bind(status);
+        set.push("status = ").push_bind_unseparated(status);
     }
     if let Some(priority) = ticket.priority.clone() {
-        query.push("priority = ");
-        query.push_bind(priority);
-    }
-    if let Some(assignee_ids) = ticket.assignee_ids.clone() {
-        query.push("assignee_ids = ");
-        query.push_bind(assignee_ids);
+        set.push("priority = ").push_bind_unseparated(priority);
     }
 
     query.push(" WHERE id = ");
     query.push_bind(ticket_id);
 
-    let result = query.build().execute(&state.pool).await?;
+    let result = query.build().execute(&mut **tx).await?;
 
     Ok(result.rows_affected())
 }
+
+/// Tickets whose reporter, category and assignees can be loaded by a [`TicketBatch`].
+pub trait BatchTicket {
+    fn id(&self) -> i64;
+    fn reporter_id(&self) -> i64;
+    fn category_id(&self) -> Option<i64>;
+}
+
+impl BatchTicket for Ticket {
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn reporter_id(&self) -> i64 {
+        self.reporter_id
+    }
+
+    fn category_id(&self) -> Option<i64> {
+        self.category_id
+    }
+}
+
+impl BatchTicket for TicketWithoutDescription {
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn reporter_id(&self) -> i64 {
+        self.reporter_id
+    }
+
+    fn category_id(&self) -> Option<i64> {
+        self.category_id
+    }
+}
+
+impl BatchTicket for SearchHit {
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn reporter_id(&self) -> i64 {
+        self.reporter_id
+    }
+
+    fn category_id(&self) -> Option<i64> {
+        self.category_id
+    }
+}
+
+/// The reporters, categories, assignees and attachments of a batch of tickets.
+///
+/// Everything is loaded up front with a fixed number of queries, no matter how many tickets
+/// are in the batch, and the responses are then built from memory.
+pub struct TicketBatch {
+    users: HashMap<i64, User>,
+    categories: HashMap<i64, Category>,
+    assignee_ids: HashMap<i64, Vec<i64>>,
+    attachments: HashMap<i64, Vec<Attachment>>,
+}
+
+impl TicketBatch {
+    /// Loads everything needed to build responses for `tickets`.
+    ///
+    /// `extra_user_ids` are loaded along with the reporters and assignees so that other users
+    /// on the response, like assigners or commenters, don't need a query of their own.
+    pub async fn load<T: BatchTicket>(
+        state: &TiraState,
+        tickets: &[T],
+        extra_user_ids: Vec<i64>,
+    ) -> Result<Self> {
+        let ticket_ids: Vec<_> = tickets.iter().map(BatchTicket::id).collect();
+        let ticket_attachments =
+            attachments::get_attachments_by_ticket_ids(state, &ticket_ids).await?;
+        let assignments = assignments::get_assignments_by_ticket_ids(state, ticket_ids).await?;
+        Self::from_rows(
+            state,
+            tickets,
+            extra_user_ids,
+            ticket_attachments,
+            assignments,
+        )
+        .await
+    }
+
+    /// Loads everything needed to build responses for `tickets` as part of a transaction, so that
+    /// the assignments and attachments it made are included.
+    ///
+    /// Users and categories are loaded outside of it, since tickets never change them.
+    pub async fn load_in_transaction<T: BatchTicket>(
+        state: &TiraState,
+        tx: &mut Transaction<'_, Postgres>,
+        tickets: &[T],
+        extra_user_ids: Vec<i64>,
+    ) -> Result<Self> {
+        let ticket_ids: Vec<_> = tickets.iter().map(BatchTicket::id).collect();
+        let ticket_attachments =
+            attachments::get_attachments_by_ticket_ids_in_transaction(tx, &ticket_ids).await?;
+        let assignments =
+            assignments::get_assignments_by_ticket_ids_in_transaction(tx, ticket_ids).await?;
+        Self::from_rows(
+            state,
+            tickets,
+            extra_user_ids,
+            ticket_attachments,
+            assignments,
+        )
+        .await
+    }
+
+    /// Groups the attachments and assignments of `tickets` and loads the users and categories
+    /// they refer to.
+    async fn from_rows<T: BatchTicket>(
+        state: &TiraState,
+        tickets: &[T],
+        extra_user_ids: Vec<i64>,
+        ticket_attachments: Vec<Attachment>,
+        assignments: Vec<Assignment>,
+    ) -> Result<Self> {
+        let mut assignee_ids: HashMap<i64, Vec<i64>> = HashMap::new();
+        let mut user_ids = extra_user_ids;
+        for assignment in &assignments {
+            assignee_ids
+                .entry(assignment.ticket_id)
+                .or_default()
+                .push(assignment.assignee_id);
+            user_ids.push(assignment.assignee_id);
+        }
+        user_ids.extend(tickets.iter().map(BatchTicket::reporter_id));
+        let mut attachments: HashMap<i64, Vec<Attachment>> = HashMap::new();
+        for attachment in ticket_attachments {
+            user_ids.push(attachment.owner_id);
+            attachments
+                .entry(attachment.ticket_id)
+                .or_default()
+                .push(attachment);
+        }
+        user_ids.sort_unstable();
+        user_ids.dedup();
+
+        let mut category_ids: Vec<_> = tickets
+            .iter()
+            .filter_map(BatchTicket::category_id)
+            .collect();
+        category_ids.sort_unstable();
+        category_ids.dedup();
+
+        let users = users::get_users_by_ids(state, user_ids)
+            .await?
+            .into_iter()
+            .map(|user| (user.id, user))
+            .collect();
+        let categories = categories::get_categories_by_ids(state, category_ids)
+            .await?
+            .into_iter()
+            .map(|category| (category.id, category))
+            .collect();
+
+        Ok(Self {
+            users,
+            categories,
+            assignee_ids,
+            attachments,
+        })
+    }
+
+    /// Returns a user that was loaded into the batch.
+    pub fn user(&self, user_id: i64) -> Result<User> {
+        self.users
+            .get(&user_id)
+            .cloned()
+            .with_context(|| format!("user {} was not loaded", user_id))
+    }
+
+    fn category(&self, category_id: Option<i64>) -> Result<Option<Category>> {
+        category_id
+            .map(|category_id| {
+                self.categories
+                    .get(&category_id)
+                    .cloned()
+                    .with_context(|| format!("category {} was not loaded", category_id))
+            })
+            .transpose()
+    }
+
+    fn assignees(&self, ticket_id: i64) -> Result<Vec<User>> {
+        self.assignee_ids
+            .get(&ticket_id)
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .map(|assignee_id| self.user(*assignee_id))
+            .collect()
+    }
+
+    /// Builds the responses for the attachments of a ticket in the batch, oldest first.
+    pub fn attachment_responses(&self, ticket_id: i64) -> Result<Vec<AttachmentResponse>> {
+        self.attachments
+            .get(&ticket_id)
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .map(|attachment| {
+                Ok(AttachmentResponse {
+                    id: attachment.id,
+                    comment_id: attachment.comment_id,
+                    owner: self.user(attachment.owner_id)?,
+                    file_name: attachment.file_name.clone(),
+                    content_type: attachment.content_type.clone(),
+                    size: attachment.size,
+                    checksum: attachment.checksum.clone(),
+                    uploaded: attachment.uploaded,
+                })
+            })
+            .collect()
+    }
+
+    /// Builds the response for a ticket in the batch.
+    pub fn ticket_response(&self, ticket: Ticket) -> Result<TicketResponse> {
+        Ok(TicketResponse {
+            id: ticket.id,
+            category: self.category(ticket.category_id)?,
+            reporter: self.user(ticket.reporter_id)?,
+            assignees: self.assignees(ticket.id)?,
+            attachments: self.attachment_responses(ticket.id)?,
+            subject: ticket.subject,
+            description: ticket.description,
+            description_source: ticket.description_source,
+            content_format: ticket.content_format,
+            priority: ticket.priority,
+            status: ticket.status,
+            created: ticket.created,
+        })
+    }
+
+    /// Builds the list response for a ticket in the batch.
+    pub fn ticket_without_description_response(
+        &self,
+        ticket: TicketWithoutDescription,
+    ) -> Result<TicketWithoutDescriptionResponse> {
+        Ok(TicketWithoutDescriptionResponse {
+            id: ticket.id,
+            category: self.category(ticket.category_id)?,
+            reporter: self.user(ticket.reporter_id)?,
+            assignees: self.assignees(ticket.id)?,
+            subject: ticket.subject,
+            priority: ticket.priority,
+            status: ticket.status,
+            created: ticket.created,
+        })
+    }
+
+    /// Builds the response for an assignment of a ticket in the batch.
+    ///
+    /// The assigner has to have been loaded as an extra user.
+    pub fn assignment_response(
+        &self,
+        assignment: Assignment,
+        ticket: &Ticket,
+    ) -> Result<AssignmentResponse> {
+        Ok(AssignmentResponse {
+            id: assignment.id,
+            ticket: TicketWithReporterAsUser {
+                id: ticket.id,
+                subject: ticket.subject.clone(),
+                description: ticket.description.clone(),
+                category_id: ticket.category_id,
+                priority: ticket.priority.clone(),
+                status: ticket.status.clone(),
+                created: ticket.created,
+                reporter: self.user(ticket.reporter_id)?,
+            },
+            assigner: self.user(assignment.assigner_id)?,
+            assigned: assignment.assigned,
+        })
+    }
+
+    /// Builds the response for a comment.
+    ///
+    /// The commenter has to have been loaded as an extra user.
+    pub fn comment_response(
+        &self,
+        comment: Comment,
+        reactions: Vec<ReactionCountResponse>,
+    ) -> Result<CommentResponse> {
+        Ok(CommentResponse {
+            id: comment.id,
+            commenter: self.user(comment.commenter_id)?,
+            content: comment.content,
+            content_source: comment.content_source,
+            content_format: comment.content_format,
+            commented: comment.commented,
+            edited: comment.edited,
+            deleted: comment.deleted,
+            parent_comment_id: comment.parent_comment_id,
+            reactions,
+        })
+    }
+}
@@ -1,5 +1,6 @@
 use crate::{
-    models::{patch::UpdateUser, Assignment, Login, User},
+    models::{patch::UpdateUser, Assignment, User},
+    service::security,
     TiraState,
 };
 use anyhow::Result;
@@ -15,9 +16,11 @@ pub async fn archive_user_by_id(state: &TiraState, user_id: i64) -> Result<u64>
 
 /// DAO function for creating a user.
 pub async fn create_user(state: &TiraState, user: User) -> Result<i64> {
-    let result =  sqlx::query!("INSERT INTO users (username, password, email_address, first_name, last_name, profile_picture_url) VALUES ($1,$2,$3,$4,$5,$6) RETURNING id",
+    let result =  sqlx::query!("INSERT INTO users (username, password, password_scheme, role, email_address, first_name, last_name, profile_picture_url) VALUES ($1,$2,$3,$4,$5,$6,$7,$8) RETURNING id",
     user.username,
     user.password,
+    user.password_scheme,
+    user.role,
     user.email_address,
     user.first_name,
     user.last_name,
@@ -27,14 +30,14 @@ pub async fn create_user(state: &TiraState, user: User) -> Result<i64> {
     Ok(result.id)
 }
 
-/// DAO function for retrieving all assignments for a user.
+/// DAO function for retrieving all current assignments for a user.
 pub async fn get_assignments_by_user_id(
     state: &TiraState,
     user_id: i64,
 ) -> Result<Vec<Assignment>> {
     let assignments = sqlx::query_as!(
         Assignment,
-        "SELECT * FROM assignments WHERE assignee_id = $1",
+        "SELECT * FROM assignments WHERE assignee_id = $1 AND unassigned IS NULL",
         user_id
     )
     .fetch_all(&state.pool)
@@ -54,7 +57,7 @@ pub async fn get_user_by_id(state: &TiraState, user_id: i64) -> Result<User> {
 pub async fn get_users_by_ids(state: &TiraState, user_ids: Vec<i64>) -> Result<Vec<User>> {
     let users = sqlx::query_as!(
         User,
-        "SELECT * FROM users WHERE id IN (SELECT unnest($1::integer[]))",
+        "SELECT * FROM users WHERE id IN (SELECT unnest($1::bigint[]))",
         &user_ids,
     )
     .fetch_all(&state.pool)
@@ -62,17 +65,26 @@ pub async fn get_users_by_ids(state: &TiraState, user_ids: Vec<i64>) -> Result<V
     Ok(users)
 }
 
-/// DAO function for retrieving a user by username and password_hash.
-pub async fn get_user_by_username_and_password(state: &TiraState, login: Login) -> Result<User> {
-    let users = sqlx::query_as!(
-        User,
-        "SELECT * FROM users WHERE username = $1 and password = $2",
-        login.username,
-        login.password,
+/// DAO function for retrieving a user by username.
+pub async fn get_user_by_username(state: &TiraState, username: &str) -> Result<User> {
+    let user = sqlx::query_as!(User, "SELECT * FROM users WHERE username = $1", username)
+        .fetch_one(&state.pool)
+        .await?;
+    Ok(user)
+}
+
+/// DAO function for retrieving the ids of the users that are not archived, by usernames.
+pub async fn get_active_user_ids_by_usernames(
+    state: &TiraState,
+    usernames: &[String],
+) -> Result<Vec<i64>> {
+    let result = sqlx::query!(
+        "SELECT id FROM users WHERE username = ANY($1) AND NOT archived",
+        usernames
     )
-    .fetch_one(&state.pool)
+    .fetch_all(&state.pool)
     .await?;
-    Ok(users)
+    Ok(result.into_iter().map(|row| row.id).collect())
 }
 
 /// DAO function for retrieving all users.
@@ -87,40 +99,59 @@ pub async fn get_users(state: &TiraState, filter_archived: Option<bool>) -> Resu
     Ok(users)
 }
 
+/// DAO function for replacing the password hash of a user by id.
+pub async fn update_password_by_id(
+    state: &TiraState,
+    user_id: i64,
+    password: &str,
+    password_scheme: &str,
+) -> Result<u64> {
+    let result = sqlx::query!(
+        "UPDATE users SET password = $1, password_scheme = $2 WHERE id = $3",
+        password,
+        password_scheme,
+        user_id
+    )
+    .execute(&state.pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
 /// DAO function for updating a user by id.
 pub async fn update_user_by_id(state: &TiraState, user: UpdateUser, user_id: i64) -> Result<u64> {
     let mut query = QueryBuilder::new("UPDATE users SET ");
+    let mut set = query.separated(", ");
 
     if let Some(username) = user.username {
-        query.push("username = ");
-        query.push_bind(username);
+        set.push("username = ").push_bind_unseparated(username);
     }
     if let Some(password) = user.password {
-        query.push("password = ");
-        query.push_bind(password);
+        set.push("password = ").push_bind_unseparated(password);
+        set.push("password_scheme = ")
+            .push_bind_unseparated(security::ARGON2ID_SCHEME);
     }
     if let Some(email_address) = user.email_address {
-        query.push("email_address = ");
-        query.push_bind(email_address);
+        set.push("email_address = ")
+            .push_bind_unseparated(email_address);
     }
     if let Some(first_name) = user.first_name {
-        query.push("first_name = ");
-        query.push_bind(first_name);
+        set.push("first_name = ").push_bind_unseparated(first_name);
     }
     if let Some(last_name) = user.last_name {
-        query.push("last_name = ");
-        query.push_bind(last_name);
+        set.push("last_name = ").push_bind_unseparated(last_name);
     }
     if let Some(profile_picture_url) = user.profile_picture_url {
-        query.push("profile_picture_url = ");
-        query.push_bind(profile_picture_url);
+        set.push("profile_picture_url = ")
+            .push_bind_unseparated(profile_picture_url);
     }
     if let Some(archived) = user.archived {
-        query.push("archived = ");
-        query.push_bind(archived);
+        set.push("archived = ").push_bind_unseparated(archived);
+    }
+    if let Some(role) = user.role {
+        set.push("role = ").push_bind_unseparated(role);
     }
 
-    query.push("WHERE id = ");
+    query.push(" WHERE id = ");
     query.push_bind(user_id);
 
     let result = query.build().execute(&state.pool).await?;
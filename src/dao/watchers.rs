@@ -0,0 +1,59 @@
+use crate::{models::User, TiraState};
+use anyhow::Result;
+use sqlx::{Postgres, Transaction};
+
+/// DAO function for making users watch a ticket.
+///
+/// Users that already watch the ticket are skipped.
+pub async fn create_watchers(
+    tx: &mut Transaction<'_, Postgres>,
+    ticket_id: i64,
+    user_ids: &[i64],
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO ticket_watchers (ticket_id, user_id) SELECT $1, unnest($2::bigint[]) ON CONFLICT DO NOTHING",
+        ticket_id,
+        user_ids
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// DAO function for making a user stop watching a ticket.
+pub async fn delete_watcher(state: &TiraState, ticket_id: i64, user_id: i64) -> Result<u64> {
+    let result = sqlx::query!(
+        "DELETE FROM ticket_watchers WHERE ticket_id = $1 AND user_id = $2",
+        ticket_id,
+        user_id
+    )
+    .execute(&state.pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// DAO function for retrieving the ids of the users watching a ticket.
+pub async fn get_watcher_ids_by_ticket_id(
+    tx: &mut Transaction<'_, Postgres>,
+    ticket_id: i64,
+) -> Result<Vec<i64>> {
+    let result = sqlx::query!(
+        "SELECT user_id FROM ticket_watchers WHERE ticket_id = $1",
+        ticket_id
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    Ok(result.into_iter().map(|row| row.user_id).collect())
+}
+
+/// DAO function for retrieving the users watching a ticket.
+pub async fn get_watchers_by_ticket_id(state: &TiraState, ticket_id: i64) -> Result<Vec<User>> {
+    let users = sqlx::query_as!(
+        User,
+        "SELECT users.* FROM users JOIN ticket_watchers ON ticket_watchers.user_id = users.id WHERE ticket_watchers.ticket_id = $1 ORDER BY ticket_watchers.created, users.id",
+        ticket_id
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(users)
+}
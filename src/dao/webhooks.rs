@@ -0,0 +1,229 @@
+use crate::{
+    models::{patch::UpdateWebhook, Count, CreateWebhook, Webhook, WebhookDelivery},
+    TiraState,
+};
+use anyhow::Result;
+use sqlx::{Postgres, QueryBuilder, Transaction};
+
+/// A delivery that is due, with where it goes.
+pub struct DueDelivery {
+    pub id: i64,
+    pub event: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+}
+
+/// DAO function for creating a webhook.
+pub async fn create_webhook(
+    state: &TiraState,
+    webhook: &CreateWebhook,
+    creator_id: i64,
+) -> Result<i64> {
+    let result = sqlx::query!(
+        "INSERT INTO webhooks (url, secret, events, creator_id) VALUES ($1, $2, $3, $4) RETURNING id",
+        webhook.url,
+        webhook.secret,
+        &webhook.events,
+        creator_id
+    )
+    .fetch_one(&state.pool)
+    .await?;
+    Ok(result.id)
+}
+
+/// DAO function for retrieving every webhook.
+pub async fn get_webhooks(state: &TiraState) -> Result<Vec<Webhook>> {
+    let webhooks = sqlx::query_as!(
+        Webhook,
+        "SELECT id, url, events, active, creator_id, created FROM webhooks ORDER BY id"
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(webhooks)
+}
+
+/// DAO function for retrieving a webhook by id.
+pub async fn get_webhook_by_id(state: &TiraState, id: i64) -> Result<Webhook> {
+    let webhook = sqlx::query_as!(
+        Webhook,
+        "SELECT id, url, events, active, creator_id, created FROM webhooks WHERE id = $1",
+        id
+    )
+    .fetch_one(&state.pool)
+    .await?;
+    Ok(webhook)
+}
+
+/// DAO function for updating a webhook by id.
+pub async fn update_webhook_by_id(
+    state: &TiraState,
+    webhook: &UpdateWebhook,
+    id: i64,
+) -> Result<u64> {
+    let result = sqlx::query!(
+        "UPDATE webhooks SET url = COALESCE($2, url), secret = COALESCE($3, secret), events = COALESCE($4, events), active = COALESCE($5, active) WHERE id = $1",
+        id,
+        webhook.url,
+        webhook.secret,
+        webhook.events.as_deref(),
+        webhook.active
+    )
+    .execute(&state.pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// DAO function for deleting a webhook by id, along with its deliveries.
+pub async fn delete_webhook_by_id(state: &TiraState, id: i64) -> Result<u64> {
+    let result = sqlx::query!("DELETE FROM webhooks WHERE id = $1", id)
+        .execute(&state.pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// DAO function for retrieving the ids of the active webhooks subscribed to an event.
+pub async fn get_webhook_ids_by_event(state: &TiraState, event: &str) -> Result<Vec<i64>> {
+    let result = sqlx::query!(
+        "SELECT id FROM webhooks WHERE active AND $1 = ANY(events)",
+        event
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(result.into_iter().map(|row| row.id).collect())
+}
+
+/// DAO function for queueing the delivery of an event to webhooks.
+pub async fn create_deliveries(
+    tx: &mut Transaction<'_, Postgres>,
+    webhook_ids: &[i64],
+    event: &str,
+    payload: &str,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO webhook_deliveries (webhook_id, event, payload) SELECT unnest($1::bigint[]), $2, $3",
+        webhook_ids,
+        event,
+        payload
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// DAO function for claiming the pending deliveries that are due.
+///
+/// The next attempt at the deliveries is moved `lease_secs` seconds ahead, so that other workers
+/// skip them until then.
+pub async fn claim_due_deliveries(
+    state: &TiraState,
+    pending_status: &str,
+    limit: i64,
+    lease_secs: i64,
+) -> Result<Vec<DueDelivery>> {
+    let deliveries = sqlx::query_as!(
+        DueDelivery,
+        r#"WITH claimed AS (UPDATE webhook_deliveries SET next_attempt = NOW() + $3::bigint * INTERVAL '1 second' WHERE id IN (SELECT id FROM webhook_deliveries WHERE status = $1 AND next_attempt <= NOW() ORDER BY next_attempt, id LIMIT $2 FOR UPDATE SKIP LOCKED) RETURNING id, webhook_id, event, payload, attempts) SELECT claimed.id AS "id!", claimed.event AS "event!", claimed.payload AS "payload!", claimed.attempts AS "attempts!", webhooks.url, webhooks.secret FROM claimed JOIN webhooks ON webhooks.id = claimed.webhook_id ORDER BY claimed.id"#,
+        pending_status,
+        limit,
+        lease_secs
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(deliveries)
+}
+
+/// DAO function for marking a delivery as delivered.
+pub async fn mark_delivery_delivered(
+    state: &TiraState,
+    id: i64,
+    delivered_status: &str,
+    response_status: Option<i32>,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE webhook_deliveries SET status = $2, attempts = attempts + 1, response_status = $3, last_error = NULL, delivered = NOW() WHERE id = $1",
+        id,
+        delivered_status,
+        response_status
+    )
+    .execute(&state.pool)
+    .await?;
+    Ok(())
+}
+
+/// DAO function for recording a failed attempt at a delivery.
+///
+/// The delivery is moved to `status` and tried again in `retry_in_secs` seconds if it is still
+/// pending.
+pub async fn mark_delivery_failed(
+    state: &TiraState,
+    id: i64,
+    status: &str,
+    response_status: Option<i32>,
+    error: &str,
+    retry_in_secs: i64,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE webhook_deliveries SET status = $2, attempts = attempts + 1, response_status = $3, last_error = $4, next_attempt = NOW() + $5::bigint * INTERVAL '1 second' WHERE id = $1",
+        id,
+        status,
+        response_status,
+        error,
+        retry_in_secs
+    )
+    .execute(&state.pool)
+    .await?;
+    Ok(())
+}
+
+fn push_delivery_filter(
+    query: &mut QueryBuilder<'_, Postgres>,
+    webhook_id: i64,
+    status: Option<String>,
+) {
+    query.push(" WHERE webhook_id = ");
+    query.push_bind(webhook_id);
+    if let Some(status) = status {
+        query.push(" and status = ");
+        query.push_bind(status);
+    }
+}
+
+/// DAO function for counting the deliveries of a webhook.
+pub async fn count_deliveries_by_webhook_id(
+    state: &TiraState,
+    webhook_id: i64,
+    status: Option<String>,
+) -> Result<i64> {
+    let mut query = QueryBuilder::new("SELECT COUNT(*) AS cnt FROM webhook_deliveries");
+    push_delivery_filter(&mut query, webhook_id, status);
+
+    let count = query
+        .build_query_as::<Count>()
+        .fetch_one(&state.pool)
+        .await?;
+    Ok(count.cnt.unwrap_or(0))
+}
+
+/// DAO function for retrieving the deliveries of a webhook, newest first.
+pub async fn get_deliveries_by_webhook_id(
+    state: &TiraState,
+    webhook_id: i64,
+    status: Option<String>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<WebhookDelivery>> {
+    let mut query = QueryBuilder::new("SELECT * FROM webhook_deliveries");
+    push_delivery_filter(&mut query, webhook_id, status);
+    query.push(" ORDER BY created DESC, id DESC LIMIT ");
+    query.push_bind(limit);
+    query.push(" OFFSET ");
+    query.push_bind(offset);
+
+    let deliveries = query
+        .build_query_as::<WebhookDelivery>()
+        .fetch_all(&state.pool)
+        .await?;
+    Ok(deliveries)
+}
@@ -0,0 +1,158 @@
+use crate::{
+    models::{patch::UpdateTicketStatus, StatusTransition, TicketPriority, TicketStatus},
+    TiraState,
+};
+use anyhow::Result;
+use sqlx::{Postgres, QueryBuilder, Transaction};
+
+/// DAO function for creating a status.
+pub async fn create_status(state: &TiraState, status: &TicketStatus) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO statuses (name, open, position) VALUES ($1, $2, $3)",
+        status.name,
+        status.open,
+        status.position
+    )
+    .execute(&state.pool)
+    .await?;
+    Ok(())
+}
+
+/// DAO function for retrieving all statuses.
+pub async fn get_statuses(state: &TiraState) -> Result<Vec<TicketStatus>> {
+    let statuses = sqlx::query_as!(
+        TicketStatus,
+        "SELECT name, open, position FROM statuses ORDER BY position, name"
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(statuses)
+}
+
+/// DAO function for updating a status by name.
+pub async fn update_status_by_name(
+    state: &TiraState,
+    name: &str,
+    status: &UpdateTicketStatus,
+) -> Result<u64> {
+    let result = sqlx::query!(
+        "UPDATE statuses SET open = COALESCE($2, open), position = COALESCE($3, position) WHERE name = $1",
+        name,
+        status.open,
+        status.position
+    )
+    .execute(&state.pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// DAO function for creating a priority.
+pub async fn create_priority(state: &TiraState, priority: &TicketPriority) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO priorities (name, position) VALUES ($1, $2)",
+        priority.name,
+        priority.position
+    )
+    .execute(&state.pool)
+    .await?;
+    Ok(())
+}
+
+/// DAO function for retrieving all priorities.
+pub async fn get_priorities(state: &TiraState) -> Result<Vec<TicketPriority>> {
+    let priorities = sqlx::query_as!(
+        TicketPriority,
+        "SELECT name, position FROM priorities ORDER BY position, name"
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(priorities)
+}
+
+/// DAO function for checking whether a priority exists.
+pub async fn priority_exists(tx: &mut Transaction<'_, Postgres>, name: &str) -> Result<bool> {
+    let result = sqlx::query!(
+        "SELECT EXISTS (SELECT 1 FROM priorities WHERE name = $1) AS \"exists!\"",
+        name
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    Ok(result.exists)
+}
+
+/// DAO function for retrieving the transitions of a category's own workflow.
+///
+/// A `category_id` of `None` retrieves the default workflow.
+pub async fn get_status_transitions_by_category_id(
+    state: &TiraState,
+    category_id: Option<i64>,
+) -> Result<Vec<StatusTransition>> {
+    let transitions = sqlx::query_as!(
+        StatusTransition,
+        "SELECT from_status, to_status FROM status_transitions WHERE category_id IS NOT DISTINCT FROM $1 ORDER BY id",
+        category_id
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(transitions)
+}
+
+/// DAO function for replacing the transitions of a category's own workflow.
+///
+/// A `category_id` of `None` replaces the default workflow.
+pub async fn replace_status_transitions_by_category_id(
+    tx: &mut Transaction<'_, Postgres>,
+    category_id: Option<i64>,
+    transitions: &[StatusTransition],
+) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM status_transitions WHERE category_id IS NOT DISTINCT FROM $1",
+        category_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    if transitions.is_empty() {
+        return Ok(());
+    }
+
+    let mut query =
+        QueryBuilder::new("INSERT INTO status_transitions (category_id, from_status, to_status) ");
+    query.push_values(transitions, |mut row, transition| {
+        row.push_bind(category_id)
+            .push_bind(transition.from_status.clone())
+            .push_bind(transition.to_status.clone());
+    });
+
+    query.build().execute(&mut **tx).await?;
+    Ok(())
+}
+
+/// DAO function for checking whether the workflow of a category allows a change of status.
+///
+/// Categories without transitions of their own use the default workflow.
+/// A `from_status` of `None` checks whether new tickets can start in `to_status`.
+pub async fn is_transition_allowed(
+    tx: &mut Transaction<'_, Postgres>,
+    category_id: Option<i64>,
+    from_status: Option<&str>,
+    to_status: &str,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"SELECT EXISTS (
+            SELECT 1 FROM status_transitions
+            WHERE category_id IS NOT DISTINCT FROM (
+                CASE WHEN EXISTS (SELECT 1 FROM status_transitions WHERE category_id = $1)
+                THEN $1 END
+            )
+            AND from_status IS NOT DISTINCT FROM $2
+            AND to_status = $3
+        ) AS "allowed!""#,
+        category_id,
+        from_status,
+        to_status
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    Ok(result.allowed)
+}
@@ -1,9 +1,20 @@
 use crate::controller::authentication;
-use crate::service::emails::handle_emails;
-use crate::service::emails::Email;
+use crate::service::attachments::MAX_ATTACHMENT_BYTES;
+use crate::service::blob_store::{connect_blob_store, BlobStore, BlobStoreConfig, BlobStoreKind};
+use crate::service::digests::run_digest_worker;
+use crate::service::email_templates::EmailTemplates;
+use crate::service::email_transport::{EmailConfig, EmailSender, EmailTransportKind};
+use crate::service::emails::run_outbox_worker;
+use crate::service::html::{resanitize_stored_html, HtmlSanitizer};
+use crate::service::inbound_emails::{run_maildir_poller, ReplyAddresses};
+use crate::service::live_events::{start_event_listener, EventHub};
+use crate::service::webhooks::{delivery_lease, run_webhook_worker};
+use crate::service::RetryConfig;
 use axum::body::Body;
 use axum::body::Bytes;
+use axum::extract::DefaultBodyLimit;
 use axum::extract::Request;
+use axum::http::header::CONTENT_TYPE;
 use axum::http::StatusCode;
 use axum::middleware;
 use axum::middleware::Next;
@@ -18,8 +29,9 @@ use clap::Parser;
 use dotenv::dotenv;
 use http_body_util::BodyExt;
 use log::info;
-use std::sync::mpsc;
-use std::thread;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tower_http::cors::Any;
 use tower_http::cors::CorsLayer;
@@ -28,13 +40,39 @@ mod dao;
 mod models;
 use std::process;
 mod service;
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use sqlx::{postgres::PgPoolOptions, PgPool};
 
 #[derive(Clone)]
 pub struct TiraState {
     pool: PgPool,
-    email_tx: mpsc::SyncSender<Email>,
+    argon2_params: argon2::Params,
+    email_templates: Arc<EmailTemplates>,
+    html_sanitizer: Arc<HtmlSanitizer>,
+    blob_store: Arc<dyn BlobStore>,
+    reply_addresses: Option<Arc<ReplyAddresses>>,
+    inbound_email_token: Option<Arc<str>>,
+    events: EventHub,
+}
+
+#[cfg(test)]
+impl TiraState {
+    /// Creates a state for tests, with the built-in email templates and HTML policy and files
+    /// kept in memory.
+    pub fn for_tests(pool: PgPool) -> Self {
+        Self {
+            pool,
+            argon2_params: argon2::Params::default(),
+            email_templates: Arc::new(
+                EmailTemplates::load("http://localhost:3000/tickets", None).unwrap(),
+            ),
+            html_sanitizer: Arc::new(HtmlSanitizer::load(None).unwrap()),
+            blob_store: Arc::new(service::blob_store::MemoryBlobStore::default()),
+            reply_addresses: None,
+            inbound_email_token: None,
+            events: EventHub::new(),
+        }
+    }
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -44,6 +82,129 @@ pub struct Args {
     database_url: String,
     #[clap(short, long, env, default_value_t = 8000)]
     port: u16,
+    /// Memory cost of password hashing in KiB
+    #[clap(long, env, default_value_t = argon2::Params::DEFAULT_M_COST)]
+    argon2_memory_kib: u32,
+    /// Number of passes password hashing makes over memory
+    #[clap(long, env, default_value_t = argon2::Params::DEFAULT_T_COST)]
+    argon2_iterations: u32,
+    /// Degree of parallelism of password hashing
+    #[clap(long, env, default_value_t = argon2::Params::DEFAULT_P_COST)]
+    argon2_parallelism: u32,
+    /// How emails are sent
+    #[clap(long, env, value_enum, default_value_t = EmailTransportKind::Smtp)]
+    email_transport: EmailTransportKind,
+    /// Sender address of emails, defaults to <TIRA_EMAIL_USERNAME>@<TIRA_EMAIL_DOMAIN>
+    #[clap(long, env = "TIRA_EMAIL_FROM")]
+    email_from: Option<String>,
+    #[clap(long, env = "TIRA_EMAIL_USERNAME")]
+    email_username: Option<String>,
+    #[clap(long, env = "TIRA_EMAIL_PASSWORD", hide_env_values = true)]
+    email_password: Option<String>,
+    #[clap(long, env = "TIRA_EMAIL_DOMAIN")]
+    email_domain: Option<String>,
+    /// Subdomain of the email domain the SMTP server runs on, if no SMTP host is given
+    #[clap(long, env = "TIRA_EMAIL_SUBDOMAIN")]
+    email_subdomain: Option<String>,
+    #[clap(long, env = "TIRA_EMAIL_SMTP_HOST")]
+    email_smtp_host: Option<String>,
+    #[clap(long, env = "TIRA_EMAIL_SMTP_PORT")]
+    email_smtp_port: Option<u16>,
+    /// Directory the file email transport writes .eml files to
+    #[clap(long, env = "TIRA_EMAIL_DIR")]
+    email_dir: Option<PathBuf>,
+    /// Base of the ticket links in emails, followed by /<ticket id>
+    #[clap(long, env = "TIRA_EMAIL_TICKET_LINK")]
+    email_ticket_link: String,
+    /// Directory with email templates that replace the built-in ones of the same name
+    #[clap(long, env = "TIRA_EMAIL_TEMPLATE_DIR")]
+    email_template_dir: Option<PathBuf>,
+    /// JSON file with the tags, attributes and URL schemes that descriptions and comments may
+    /// contain, defaults to what ammonia allows by default
+    #[clap(long, env = "TIRA_HTML_POLICY_FILE")]
+    html_policy_file: Option<PathBuf>,
+    /// Sanitize the descriptions and comments stored so far with the current HTML policy, then
+    /// exit
+    #[clap(long)]
+    resanitize_html: bool,
+    /// Where images and attachments are stored
+    #[clap(long, env = "TIRA_STORAGE_BACKEND", value_enum, default_value_t = BlobStoreKind::S3)]
+    storage_backend: BlobStoreKind,
+    /// S3 bucket images and attachments are stored in
+    #[clap(long, env = "IMAGE_BUCKET_NAME")]
+    storage_bucket: Option<String>,
+    /// Endpoint of an S3 stand-in like MinIO, defaults to AWS
+    #[clap(long, env = "IMAGE_ENDPOINT_URI")]
+    storage_endpoint: Option<String>,
+    /// Region of the S3 bucket, defaults to the AWS region of the environment
+    #[clap(long, env = "TIRA_STORAGE_REGION")]
+    storage_region: Option<String>,
+    /// Directory the local storage backend keeps files in
+    #[clap(long, env = "TIRA_STORAGE_DIR")]
+    storage_dir: Option<PathBuf>,
+    /// Address replies to notification emails go to, enables reply-by-email when set
+    #[clap(
+        long,
+        env = "TIRA_EMAIL_REPLY_ADDRESS",
+        requires = "email_reply_secret"
+    )]
+    email_reply_address: Option<String>,
+    /// Secret the reply addresses are signed with
+    #[clap(
+        long,
+        env = "TIRA_EMAIL_REPLY_SECRET",
+        hide_env_values = true,
+        requires = "email_reply_address"
+    )]
+    email_reply_secret: Option<String>,
+    /// Days a reply address can be replied to after the email with it was sent
+    #[clap(long, env, default_value_t = 30)]
+    email_reply_max_age_days: i64,
+    /// Token the MTA sends in the X-Tira-Inbound-Token header when posting replies to
+    /// /inbound-emails, which is disabled without it
+    #[clap(
+        long,
+        env = "TIRA_INBOUND_EMAIL_TOKEN",
+        hide_env_values = true,
+        requires = "email_reply_address"
+    )]
+    inbound_email_token: Option<String>,
+    /// Maildir that replies are delivered to, polled for new replies
+    #[clap(long, env = "TIRA_INBOUND_MAILDIR", requires = "email_reply_address")]
+    inbound_maildir: Option<PathBuf>,
+    /// Attempts at delivering to a webhook before the delivery is dead-lettered
+    #[clap(long, env, default_value_t = 8)]
+    webhook_max_attempts: i32,
+    /// Seconds before the first retry of a failed webhook delivery, doubling with every attempt
+    #[clap(long, env, default_value_t = 30)]
+    webhook_retry_base_secs: u64,
+    /// Most seconds between two attempts at delivering to a webhook
+    #[clap(long, env, default_value_t = 6 * 60 * 60)]
+    webhook_retry_max_secs: u64,
+    /// Seconds between checks for new webhook deliveries
+    #[clap(long, env, default_value_t = 5)]
+    webhook_poll_interval_secs: u64,
+    /// Seconds to wait for a webhook to respond
+    #[clap(long, env, default_value_t = 10)]
+    webhook_timeout_secs: u64,
+    /// Seconds between checks for new replies in the maildir
+    #[clap(long, env, default_value_t = 10)]
+    inbound_maildir_poll_interval_secs: u64,
+    /// Seconds between checks for digests that are due
+    #[clap(long, env, default_value_t = 60)]
+    digest_check_interval_secs: u64,
+    /// Attempts at sending an email before it is dead-lettered
+    #[clap(long, env, default_value_t = 8)]
+    email_max_attempts: i32,
+    /// Seconds before the first retry of a failed email, doubling with every attempt
+    #[clap(long, env, default_value_t = 30)]
+    email_retry_base_secs: u64,
+    /// Most seconds between two attempts at sending an email
+    #[clap(long, env, default_value_t = 6 * 60 * 60)]
+    email_retry_max_secs: u64,
+    /// Seconds between checks for new emails in the outbox
+    #[clap(long, env, default_value_t = 5)]
+    email_poll_interval_secs: u64,
 }
 
 // The point where the program first starts
@@ -62,20 +223,137 @@ async fn main() -> Result<()> {
 
     let args = Args::parse();
 
-    info!("setting up email handler");
-    let (email_tx, email_rx) = mpsc::sync_channel(512);
-    // Listen for emails on the email queue
-    thread::spawn(move || {
-        handle_emails(email_rx);
-    });
+    let argon2_params = argon2::Params::new(
+        args.argon2_memory_kib,
+        args.argon2_iterations,
+        args.argon2_parallelism,
+        None,
+    )
+    .map_err(|e| anyhow!("Invalid password hashing parameters: {}", e))?;
+
+    info!("setting up email transport");
+    let email_config = EmailConfig {
+        transport: args.email_transport,
+        from: args.email_from.clone(),
+        username: args.email_username.clone(),
+        password: args.email_password.clone(),
+        domain: args.email_domain.clone(),
+        subdomain: args.email_subdomain.clone(),
+        smtp_host: args.email_smtp_host.clone(),
+        smtp_port: args.email_smtp_port,
+        dir: args.email_dir.clone(),
+    };
+    let email_sender =
+        Arc::new(EmailSender::from_config(&email_config).context("Invalid email configuration")?);
+
+    info!("loading email templates");
+    let email_templates = Arc::new(EmailTemplates::load(
+        &args.email_ticket_link,
+        args.email_template_dir.as_deref(),
+    )?);
+
+    let html_sanitizer = Arc::new(HtmlSanitizer::load(args.html_policy_file.as_deref())?);
+
+    info!("setting up file storage");
+    let blob_store_config = BlobStoreConfig {
+        kind: args.storage_backend,
+        bucket: args.storage_bucket.clone(),
+        endpoint: args.storage_endpoint.clone(),
+        region: args.storage_region.clone(),
+        dir: args.storage_dir.clone(),
+    };
+    let blob_store = connect_blob_store(&blob_store_config)
+        .await
+        .context("Invalid storage configuration")?;
+
+    let reply_addresses = match (&args.email_reply_address, &args.email_reply_secret) {
+        (Some(address), Some(secret)) => Some(Arc::new(
+            ReplyAddresses::new(address, secret, args.email_reply_max_age_days)
+                .context("Invalid reply-by-email configuration")?,
+        )),
+        _ => None,
+    };
+    if args
+        .inbound_email_token
+        .as_ref()
+        .is_some_and(|token| token.len() < 16)
+    {
+        return Err(anyhow!(
+            "The inbound email token has to be at least 16 characters long"
+        ));
+    }
+    let inbound_email_token = args.inbound_email_token.as_deref().map(Arc::from);
 
     info!("connecting to the database");
     let state = TiraState {
-        email_tx,
         pool: PgPoolOptions::new().connect(&args.database_url).await?,
+        argon2_params,
+        email_templates,
+        html_sanitizer,
+        blob_store,
+        reply_addresses,
+        inbound_email_token,
+        events: EventHub::new(),
     };
     info!("successfully to the database");
 
+    if args.resanitize_html {
+        info!("re-sanitizing stored HTML");
+        let changed = resanitize_stored_html(&state).await?;
+        info!("re-sanitized stored HTML, {} rows changed", changed);
+        return Ok(());
+    }
+
+    info!("setting up event listener");
+    start_event_listener(&state.pool, &state.events).await;
+
+    info!("setting up email outbox worker");
+    let outbox_config = RetryConfig {
+        max_attempts: args.email_max_attempts,
+        retry_base: Duration::from_secs(args.email_retry_base_secs),
+        retry_max: Duration::from_secs(args.email_retry_max_secs),
+        poll_interval: Duration::from_secs(args.email_poll_interval_secs),
+        // Sending a batch takes far less, SMTP gives up on a server after a minute
+        lease: Duration::from_secs(15 * 60),
+    };
+    tokio::spawn(run_outbox_worker(
+        state.clone(),
+        email_sender,
+        outbox_config,
+    ));
+
+    info!("setting up digest worker");
+    tokio::spawn(run_digest_worker(
+        state.clone(),
+        Duration::from_secs(args.digest_check_interval_secs),
+    ));
+
+    info!("setting up webhook worker");
+    let webhook_client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(args.webhook_timeout_secs))
+        .build()?;
+    let webhook_config = RetryConfig {
+        max_attempts: args.webhook_max_attempts,
+        retry_base: Duration::from_secs(args.webhook_retry_base_secs),
+        retry_max: Duration::from_secs(args.webhook_retry_max_secs),
+        poll_interval: Duration::from_secs(args.webhook_poll_interval_secs),
+        lease: delivery_lease(Duration::from_secs(args.webhook_timeout_secs)),
+    };
+    tokio::spawn(run_webhook_worker(
+        state.clone(),
+        webhook_client,
+        webhook_config,
+    ));
+
+    if let Some(maildir) = args.inbound_maildir.clone() {
+        info!("setting up maildir poller");
+        tokio::spawn(run_maildir_poller(
+            state.clone(),
+            maildir,
+            Duration::from_secs(args.inbound_maildir_poll_interval_secs),
+        ));
+    }
+
     let cors = CorsLayer::new()
         .allow_methods(Any)
         .allow_origin(Any)
@@ -85,6 +363,10 @@ async fn main() -> Result<()> {
     let no_auth_routes = Router::new()
         .route("/login", post(controller::sessions::login_endpoint))
         .route("/health", get(controller::health))
+        .route(
+            "/inbound-emails",
+            post(controller::emails::receive_email_endpoint),
+        )
         .layer(cors.clone())
         .layer(middleware::from_fn(print_request_body))
         .with_state(state.clone());
@@ -102,25 +384,62 @@ async fn main() -> Result<()> {
                 .get(controller::categories::get_categories_endpoint),
         )
         .route(
-            "/categories/:category_id",
+            "/categories/{category_id}",
             get(controller::categories::get_category_by_id_endpoint),
         )
         .route(
-            "/comments/:comment_id",
-            patch(controller::comments::patch_comment_by_id_endpoint),
+            "/categories/{category_id}/workflow",
+            get(controller::workflows::get_workflow_by_category_id_endpoint)
+                .put(controller::workflows::put_workflow_by_category_id_endpoint)
+                .delete(controller::workflows::delete_workflow_by_category_id_endpoint),
+        )
+        .route(
+            "/comments/{comment_id}",
+            patch(controller::comments::patch_comment_by_id_endpoint)
+                .delete(controller::comments::delete_comment_by_id_endpoint),
+        )
+        .route(
+            "/comments/{comment_id}/reactions/{emoji}",
+            post(controller::comments::create_reaction_endpoint)
+                .delete(controller::comments::delete_reaction_endpoint),
         )
         .route(
-            "/images/:file_name",
+            "/comments/{comment_id}/revisions",
+            get(controller::comments::get_revisions_by_comment_id_endpoint),
+        )
+        .route("/events", get(controller::events::get_events_endpoint))
+        .route(
+            "/images/{file_name}",
             post(controller::images::upload_image_endpoint)
                 .get(controller::images::retrieve_image_endpoint),
         )
         .route("/logout", post(controller::sessions::logout_endpoint))
+        .route("/outbox", get(controller::emails::get_outbox_endpoint))
+        .route(
+            "/outbox/{email_id}/retry",
+            post(controller::emails::retry_outbox_email_endpoint),
+        )
+        .route(
+            "/priorities",
+            post(controller::workflows::create_priority_endpoint)
+                .get(controller::workflows::get_priorities_endpoint),
+        )
+        .route("/search", get(controller::search::search_endpoint))
+        .route(
+            "/statuses",
+            post(controller::workflows::create_status_endpoint)
+                .get(controller::workflows::get_statuses_endpoint),
+        )
+        .route(
+            "/statuses/{name}",
+            patch(controller::workflows::patch_status_by_name_endpoint),
+        )
         .route(
-            "/tickets/:ticket_id/assignments",
+            "/tickets/{ticket_id}/assignments",
             post(controller::tickets::create_assignment_by_ticket_id_endpoint),
         )
         .route(
-            "/tickets/:ticket_id/comments",
+            "/tickets/{ticket_id}/comments",
             post(controller::tickets::create_comment_by_ticket_id_endpoint)
                 .get(controller::tickets::get_comments_by_ticket_id_endpoint),
         )
@@ -130,16 +449,45 @@ async fn main() -> Result<()> {
                 .get(controller::tickets::get_tickets_endpoint),
         )
         .route(
-            "/tickets/:ticket_id/assignments",
+            "/tickets/{ticket_id}/assignments",
             get(controller::tickets::get_assignments_by_ticket_id_endpoint),
         )
         .route(
-            "/tickets/:ticket_id",
+            "/tickets/{ticket_id}/attachments",
+            post(controller::attachments::create_attachment_endpoint)
+                .get(controller::attachments::get_attachments_by_ticket_id_endpoint)
+                // Leave room for the multipart boundaries and headers around the file
+                .layer(DefaultBodyLimit::max(MAX_ATTACHMENT_BYTES + 64 * 1024)),
+        )
+        .route(
+            "/tickets/{ticket_id}/attachments/{attachment_id}",
+            get(controller::attachments::get_attachment_by_id_endpoint)
+                .delete(controller::attachments::delete_attachment_by_id_endpoint),
+        )
+        .route(
+            "/tickets/{ticket_id}/assignments/{assignee_id}",
+            delete(controller::tickets::delete_assignment_by_ticket_id_and_assignee_id_endpoint),
+        )
+        .route(
+            "/tickets/{ticket_id}/watch",
+            post(controller::tickets::watch_ticket_endpoint)
+                .delete(controller::tickets::unwatch_ticket_endpoint),
+        )
+        .route(
+            "/tickets/{ticket_id}/watchers",
+            get(controller::tickets::get_watchers_by_ticket_id_endpoint),
+        )
+        .route(
+            "/tickets/{ticket_id}/history",
+            get(controller::tickets::get_history_by_ticket_id_endpoint),
+        )
+        .route(
+            "/tickets/{ticket_id}",
             get(controller::tickets::get_ticket_by_id_endpoint)
                 .patch(controller::tickets::patch_ticket_by_id_endpoint),
         )
         .route(
-            "/users/:user_id",
+            "/users/{user_id}",
             delete(controller::users::archive_user_by_id_endpoint)
                 .get(controller::users::get_user_by_id_endpoint)
                 .patch(controller::users::patch_user_by_id_endpoint),
@@ -150,13 +498,42 @@ async fn main() -> Result<()> {
                 .get(controller::users::get_users_endpoint),
         )
         .route(
-            "/users/:user_id/assignments",
+            "/webhooks",
+            post(controller::webhooks::create_webhook_endpoint)
+                .get(controller::webhooks::get_webhooks_endpoint),
+        )
+        .route(
+            "/webhooks/{webhook_id}",
+            get(controller::webhooks::get_webhook_by_id_endpoint)
+                .patch(controller::webhooks::patch_webhook_by_id_endpoint)
+                .delete(controller::webhooks::delete_webhook_by_id_endpoint),
+        )
+        .route(
+            "/webhooks/{webhook_id}/deliveries",
+            get(controller::webhooks::get_deliveries_by_webhook_id_endpoint),
+        )
+        .route(
+            "/workflow",
+            get(controller::workflows::get_default_workflow_endpoint)
+                .put(controller::workflows::put_default_workflow_endpoint),
+        )
+        .route(
+            "/users/{user_id}/assignments",
             get(controller::users::get_assignments_by_user_id_endpoint),
         )
         .route(
             "/users/current",
             get(controller::users::get_current_user_endpoint),
         )
+        .route(
+            "/users/current/mentions",
+            get(controller::users::get_mentions_endpoint),
+        )
+        .route(
+            "/users/current/notification-preferences",
+            get(controller::users::get_notification_preferences_endpoint)
+                .patch(controller::users::patch_notification_preferences_endpoint),
+        )
         .layer(cors)
         .layer(middleware::from_fn(print_request_body))
         .layer(middleware::from_fn_with_state(
@@ -179,11 +556,31 @@ async fn main() -> Result<()> {
 
 // middleware that shows how to consume the request body upfront
 async fn print_request_body(request: Request, next: Next) -> Result<impl IntoResponse, Response> {
+    if !logs_body(&request) {
+        info!(
+            "Request: {} {} (body not logged)",
+            request.method(),
+            request.uri()
+        );
+        return Ok(next.run(request).await);
+    }
     info!("Request: {:?}", request);
     let request = buffer_request_body(request).await?;
     Ok(next.run(request).await)
 }
 
+// file uploads are passed on as they stream in, so that the body limit of their route applies
+// and the files don't end up in the logs, and neither do the private emails that the MTA passes on
+fn logs_body(request: &Request) -> bool {
+    let is_multipart = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.to_ascii_lowercase().starts_with("multipart/"));
+    let path = request.uri().path();
+    !is_multipart && !path.contains("/attachments") && path != "/inbound-emails"
+}
+
 // the trick is to take the request apart, buffer the body, do what you need to do, then put
 // the request back together
 async fn buffer_request_body(request: Request) -> Result<Request, Response> {
@@ -203,8 +600,52 @@ async fn buffer_request_body(request: Request) -> Result<Request, Response> {
 }
 
 fn do_thing_with_request_body(uri: &str, bytes: Bytes) {
-    let body = String::from_utf8_lossy(&bytes);
-    if !body.is_empty() {
-        info!("body for {}: {}", uri, String::from_utf8_lossy(&bytes));
+    if !bytes.is_empty() {
+        info!("body for {}: {}", uri, redacted_body(&bytes));
+    }
+}
+
+// logins and user changes carry passwords, which have no business in the logs
+fn redacted_body(bytes: &[u8]) -> String {
+    match serde_json::from_slice::<serde_json::Value>(bytes) {
+        Ok(mut json) => {
+            redact_passwords(&mut json);
+            json.to_string()
+        }
+        Err(_) => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+fn redact_passwords(json: &mut serde_json::Value) {
+    match json {
+        serde_json::Value::Object(fields) => {
+            for (name, value) in fields.iter_mut() {
+                if name == "password" {
+                    *value = serde_json::Value::from("[redacted]");
+                } else {
+                    redact_passwords(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact_passwords),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwords_are_redacted_from_logged_bodies() {
+        let body = redacted_body(br#"{"username":"user1","password":"hunter2"}"#);
+        assert!(!body.contains("hunter2"));
+        assert!(body.contains(r#""password":"[redacted]""#));
+        assert!(body.contains(r#""username":"user1""#));
+
+        let nested = redacted_body(br#"[{"user":{"password":"hunter2"}}]"#);
+        assert!(!nested.contains("hunter2"));
+
+        assert_eq!(redacted_body(b"not json"), "not json");
     }
 }
@@ -1,7 +1,8 @@
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
 
+pub mod error;
 pub mod patch;
 pub mod success;
 
@@ -17,9 +18,17 @@ pub struct User {
     pub profile_picture_url: Option<String>,
     pub created: NaiveDateTime,
     pub archived: bool,
+    #[serde(skip_serializing, default)]
+    pub password_scheme: String,
+    #[serde(default = "default_role")]
+    pub role: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
+fn default_role() -> String {
+    crate::service::permissions::MEMBER_ROLE.to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
 pub struct Category {
     pub id: i64,
     pub name: String,
@@ -29,15 +38,56 @@ pub struct Category {
     pub archived: bool,
 }
 
+/// A status that tickets can be in.
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct TicketStatus {
+    pub name: String,
+    /// Whether tickets in this status still need work.
+    #[serde(default = "default_open")]
+    pub open: bool,
+    #[serde(default)]
+    pub position: i32,
+}
+
+fn default_open() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct TicketPriority {
+    pub name: String,
+    #[serde(default)]
+    pub position: i32,
+}
+
+/// A change of status that a workflow allows.
+#[derive(Debug, Serialize, Deserialize, FromRow, PartialEq, Eq, Hash)]
+pub struct StatusTransition {
+    /// The status the ticket is in, or `None` for new tickets.
+    pub from_status: Option<String>,
+    pub to_status: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Workflow {
+    pub transitions: Vec<StatusTransition>,
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct CreateComment {
     pub content: String,
+    /// 'html' or 'markdown', defaults to 'html'.
+    pub content_format: Option<String>,
+    /// The comment this one replies to, on the same ticket.
+    pub parent_comment_id: Option<i64>,
 }
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct CreateTicket {
     pub category_id: Option<i64>,
     pub subject: String,
     pub description: Option<String>,
+    /// 'html' or 'markdown', defaults to 'html'.
+    pub content_format: Option<String>,
     pub status: String,
     pub priority: String,
     pub assignee_ids: Vec<i64>,
@@ -47,7 +97,10 @@ pub struct CreateTicket {
 pub struct Ticket {
     pub id: i64,
     pub subject: String,
+    /// The sanitized HTML the description renders to.
     pub description: Option<String>,
+    pub description_source: Option<String>,
+    pub content_format: String,
     pub category_id: Option<i64>,
     pub priority: String,
     pub status: String,
@@ -83,6 +136,40 @@ pub struct TicketWithoutDescription {
     pub reporter_id: i64,
 }
 
+/// Filters that can be applied when listing tickets.
+///
+/// Every field is optional and the filters that are set are combined with AND.
+#[derive(Debug, Default, Deserialize)]
+pub struct TicketFilter {
+    #[serde(rename = "reporter")]
+    pub reporter_id: Option<i64>,
+    pub open: Option<bool>,
+    pub status: Option<String>,
+    pub priority: Option<String>,
+    #[serde(rename = "category")]
+    pub category_id: Option<i64>,
+    #[serde(rename = "assignee")]
+    pub assignee_id: Option<i64>,
+    pub created_after: Option<NaiveDate>,
+    pub created_before: Option<NaiveDate>,
+}
+
+/// A ticket that matched a search.
+#[derive(Debug, FromRow)]
+pub struct SearchHit {
+    pub id: i64,
+    pub subject: String,
+    pub category_id: Option<i64>,
+    pub priority: String,
+    pub status: String,
+    pub created: NaiveDateTime,
+    pub reporter_id: i64,
+    /// The comment with the best match, if that was better than the ticket itself.
+    pub comment_id: Option<i64>,
+    pub rank: f32,
+    pub snippet: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct Assignment {
     pub id: i64,
@@ -90,6 +177,28 @@ pub struct Assignment {
     pub assignee_id: i64,
     pub assigner_id: i64,
     pub assigned: NaiveDateTime,
+    /// When the assignee was removed from the ticket, if they have been.
+    pub unassigned: Option<NaiveDateTime>,
+    pub unassigner_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct TicketEvent {
+    pub id: i64,
+    pub ticket_id: i64,
+    pub actor_id: i64,
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub changed: NaiveDateTime,
+}
+
+/// A change to one field of a ticket that gets recorded as a [`TicketEvent`].
+#[derive(Debug)]
+pub struct TicketFieldChange {
+    pub field: &'static str,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -97,8 +206,167 @@ pub struct Comment {
     pub id: i64,
     pub ticket_id: i64,
     pub commenter_id: i64,
+    /// The sanitized HTML the comment renders to.
+    pub content: String,
+    pub content_source: String,
+    pub content_format: String,
+    pub commented: NaiveDateTime,
+    pub edited: Option<NaiveDateTime>,
+    pub deleted: Option<NaiveDateTime>,
+    pub parent_comment_id: Option<i64>,
+}
+
+/// The content a comment had before an edit by `editor_id`.
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct CommentRevision {
+    pub id: i64,
+    pub comment_id: i64,
+    pub content: String,
+    pub content_source: String,
+    pub content_format: String,
+    pub editor_id: i64,
+    pub revised: NaiveDateTime,
+}
+
+/// A file being uploaded to a ticket.
+#[derive(Debug)]
+pub struct CreateAttachment {
+    /// The comment on the ticket the file belongs to, if any.
+    pub comment_id: Option<i64>,
+    pub file_name: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+/// A file uploaded to a ticket or one of its comments.
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct Attachment {
+    pub id: i64,
+    pub ticket_id: i64,
+    pub comment_id: Option<i64>,
+    pub owner_id: i64,
+    pub file_name: String,
+    pub content_type: String,
+    pub size: i64,
+    /// SHA-256 of the content, in hex.
+    pub checksum: String,
+    /// Where the content is in the image storage.
+    pub storage_key: String,
+    pub uploaded: NaiveDateTime,
+}
+
+/// A comment that mentioned a user.
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct Mention {
+    pub comment_id: i64,
+    pub ticket_id: i64,
+    pub ticket_subject: String,
+    pub commenter_id: i64,
     pub content: String,
     pub commented: NaiveDateTime,
+    pub mentioned: NaiveDateTime,
+}
+
+/// Which events on watched tickets a user gets emailed about.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotificationPreferences {
+    pub created: bool,
+    pub commented: bool,
+    pub assigned: bool,
+    pub status_changed: bool,
+    pub mentioned: bool,
+    /// `off` to get every notification as its own email, or `daily` or `weekly` to get them
+    /// collected in one digest email.
+    pub digest: String,
+}
+
+/// A notification waiting to be sent in a user's next digest.
+#[derive(Debug, FromRow)]
+pub struct DigestItem {
+    pub id: i64,
+    pub ticket_id: i64,
+    pub ticket_subject: String,
+    pub body: String,
+    pub text_body: Option<String>,
+    pub created: NaiveDateTime,
+}
+
+/// An email waiting in, or sent from, the outbox.
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct OutboxEmail {
+    pub id: i64,
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+    pub text_body: Option<String>,
+    pub reply_to: Option<String>,
+    /// `pending`, `sent` or `dead` once it failed too many times.
+    pub status: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub next_attempt: NaiveDateTime,
+    pub created: NaiveDateTime,
+    pub sent: Option<NaiveDateTime>,
+}
+
+/// Something that happened to a ticket, pushed to the clients following `/events`.
+///
+/// Only ids are sent along so that it fits in a Postgres notification.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveEvent {
+    pub event: String,
+    pub ticket_id: i64,
+    pub category_id: Option<i64>,
+    pub actor_id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assignee_id: Option<i64>,
+}
+
+/// Which live events a client wants, combined with AND.
+#[derive(Debug, Default, Deserialize)]
+pub struct LiveEventFilter {
+    pub ticket_id: Option<i64>,
+    pub category_id: Option<i64>,
+}
+
+/// A URL that gets told about events on tickets.
+///
+/// The secret the requests are signed with is never read back.
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    pub events: Vec<String>,
+    pub active: bool,
+    pub creator_id: i64,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateWebhook {
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<String>,
+}
+
+/// A request made, or to be made, to a webhook.
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    pub payload: String,
+    /// `pending`, `delivered` or `dead` once it failed too many times.
+    pub status: String,
+    pub attempts: i32,
+    /// HTTP status code of the last response, if there was one.
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub next_attempt: NaiveDateTime,
+    pub created: NaiveDateTime,
+    pub delivered: Option<NaiveDateTime>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
@@ -120,8 +388,3 @@ pub struct Login {
 pub struct Count {
     pub cnt: Option<i64>,
 }
-
-#[derive(sqlx::FromRow)]
-pub struct ReturningId {
-    pub id: i64,
-}
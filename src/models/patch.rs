@@ -5,11 +5,37 @@ pub struct UpdateTicket {
     pub category_id: Option<i64>,
     pub subject: Option<String>,
     pub description: Option<String>,
+    /// 'html' or 'markdown', defaults to the format the description was written in.
+    pub content_format: Option<String>,
     pub status: Option<String>,
     pub priority: Option<String>,
     pub assignee_ids: Option<Vec<i64>>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateTicketStatus {
+    pub open: Option<bool>,
+    pub position: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateNotificationPreferences {
+    pub created: Option<bool>,
+    pub commented: Option<bool>,
+    pub assigned: Option<bool>,
+    pub status_changed: Option<bool>,
+    pub mentioned: Option<bool>,
+    pub digest: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateWebhook {
+    pub url: Option<String>,
+    pub secret: Option<String>,
+    pub events: Option<Vec<String>>,
+    pub active: Option<bool>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateUser {
     pub username: Option<String>,
@@ -19,9 +45,12 @@ pub struct UpdateUser {
     pub last_name: Option<String>,
     pub profile_picture_url: Option<String>,
     pub archived: Option<bool>,
+    pub role: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateComment {
     pub content: String,
+    /// 'html' or 'markdown', defaults to the format the comment was written in.
+    pub content_format: Option<String>,
 }
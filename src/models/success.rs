@@ -1,5 +1,5 @@
 use super::TicketWithReporterAsUser;
-use crate::models::{Category, User};
+use crate::models::{Category, StatusTransition, User};
 use chrono::NaiveDateTime;
 use serde::Serialize;
 
@@ -15,29 +15,74 @@ pub struct AlteredResourceResponse {
 }
 
 #[derive(Serialize)]
-pub struct EditTicketResponse {
+pub struct CommentResponse {
     pub id: i64,
+    pub commenter: User,
+    /// The sanitized HTML the comment renders to.
+    pub content: String,
+    pub content_source: String,
+    pub content_format: String,
+    pub commented: NaiveDateTime,
+    pub edited: Option<NaiveDateTime>,
+    pub deleted: Option<NaiveDateTime>,
+    pub parent_comment_id: Option<i64>,
+    pub reactions: Vec<ReactionCountResponse>,
 }
 
 #[derive(Serialize)]
-pub struct CommentResponse {
+pub struct AttachmentResponse {
     pub id: i64,
+    pub comment_id: Option<i64>,
+    pub owner: User,
+    pub file_name: String,
+    pub content_type: String,
+    pub size: i64,
+    pub checksum: String,
+    pub uploaded: NaiveDateTime,
+}
+
+#[derive(Serialize)]
+pub struct MentionResponse {
+    pub comment_id: i64,
+    pub ticket_id: i64,
+    pub ticket_subject: String,
     pub commenter: User,
     pub content: String,
     pub commented: NaiveDateTime,
+    pub mentioned: NaiveDateTime,
+}
+
+/// How many users reacted to a comment with an emoji.
+#[derive(Serialize, Clone)]
+pub struct ReactionCountResponse {
+    pub emoji: String,
+    pub count: i64,
+    /// Whether the current user is one of them.
+    pub reacted: bool,
 }
 
 #[derive(Serialize)]
 pub struct TicketResponse {
     pub id: i64,
     pub subject: String,
+    /// The sanitized HTML the description renders to.
     pub description: Option<String>,
+    pub description_source: Option<String>,
+    pub content_format: String,
     pub category: Option<Category>,
     pub priority: String,
     pub status: String,
     pub created: NaiveDateTime,
     pub reporter: User,
     pub assignees: Vec<User>,
+    pub attachments: Vec<AttachmentResponse>,
+}
+
+#[derive(Serialize)]
+pub struct WorkflowResponse {
+    /// The category the workflow belongs to, or `None` for the default workflow.
+    pub category_id: Option<i64>,
+    pub transitions: Vec<StatusTransition>,
 }
 
 #[derive(Serialize)]
@@ -58,6 +103,14 @@ pub struct TicketWithoutDescriptionResponse {
     pub assignees: Vec<User>,
 }
 
+#[derive(Serialize)]
+pub struct SearchResultResponse {
+    pub ticket: TicketWithoutDescriptionResponse,
+    pub comment_id: Option<i64>,
+    pub rank: f32,
+    pub snippet: String,
+}
+
 #[derive(Serialize)]
 pub struct AssignmentResponse {
     pub id: i64,
@@ -65,3 +118,35 @@ pub struct AssignmentResponse {
     pub assigner: User,
     pub assigned: NaiveDateTime,
 }
+
+/// An entry in the history of a ticket.
+#[derive(Serialize)]
+pub struct HistoryEntryResponse {
+    pub timestamp: NaiveDateTime,
+    pub actor: User,
+    #[serde(flatten)]
+    pub event: HistoryEvent,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HistoryEvent {
+    Created,
+    FieldChanged {
+        field: String,
+        old_value: Option<String>,
+        new_value: Option<String>,
+    },
+    Commented {
+        comment_id: i64,
+        content: String,
+    },
+    Assigned {
+        assignment_id: i64,
+        assignee: User,
+    },
+    Unassigned {
+        assignment_id: i64,
+        assignee: User,
+    },
+}
@@ -0,0 +1,147 @@
+use crate::{
+    dao,
+    models::{Attachment, CreateAttachment},
+    service::{self, images, ServiceError},
+    TiraState,
+};
+use anyhow::Result;
+use log::error;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// The largest file that can be attached, in bytes.
+pub const MAX_ATTACHMENT_BYTES: usize = 25 * 1024 * 1024;
+
+/// What an attachment is served as when the upload did not say.
+pub const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Where attachments are kept in the image storage.
+const STORAGE_PREFIX: &str = "attachments";
+
+/// Returns the name of an uploaded file without the directories some browsers send along.
+fn base_file_name(file_name: &str) -> &str {
+    file_name.rsplit(['/', '\\']).next().unwrap_or_default()
+}
+
+/// Service function for uploading a file to a ticket by owner id.
+///
+/// The file is stored under a new key in the image storage, so that files with the same name
+/// never overwrite each other, and is only recorded once it is stored. Content whose attachment
+/// cannot be recorded is deleted again. An attachment of a comment names the comment, which has to
+/// be on the same ticket and not deleted.
+pub async fn create_attachment(
+    state: &TiraState,
+    mut attachment: CreateAttachment,
+    ticket_id: i64,
+    owner_id: i64,
+) -> Result<i64> {
+    attachment.file_name = base_file_name(attachment.file_name.trim()).to_string();
+    if attachment.file_name.is_empty() {
+        return Err(ServiceError::invalid_field("file", "File needs a name").into());
+    }
+    if attachment.data.is_empty() {
+        return Err(ServiceError::invalid_field("file", "File cannot be empty").into());
+    }
+    if attachment.data.len() > MAX_ATTACHMENT_BYTES {
+        let message = format!("File cannot be larger than {} bytes", MAX_ATTACHMENT_BYTES);
+        return Err(ServiceError::invalid_field("file", &message).into());
+    }
+
+    dao::tickets::get_ticket_by_id(state, ticket_id).await?;
+    if let Some(comment_id) = attachment.comment_id {
+        let comment = match dao::comments::get_comment_by_id(state, comment_id).await {
+            Err(e) if matches!(e.downcast_ref(), Some(sqlx::Error::RowNotFound)) => {
+                return Err(ServiceError::invalid_field("comment_id", "Comment not found").into())
+            }
+            comment => comment?,
+        };
+        if comment.ticket_id != ticket_id {
+            return Err(ServiceError::invalid_field(
+                "comment_id",
+                "Can only attach files to comments on the same ticket",
+            )
+            .into());
+        }
+        if comment.deleted.is_some() {
+            return Err(ServiceError::invalid_field(
+                "comment_id",
+                "Cannot attach files to a deleted comment",
+            )
+            .into());
+        }
+    }
+
+    let checksum = hex::encode(Sha256::digest(&attachment.data));
+    let storage_key = format!("{}/{}", STORAGE_PREFIX, Uuid::new_v4());
+
+    let mut tx = state.pool.begin().await?;
+    let id = dao::attachments::create_attachment(
+        &mut tx,
+        &attachment,
+        ticket_id,
+        owner_id,
+        &checksum,
+        &storage_key,
+    )
+    .await?;
+    // An upload that timed out may still have been stored, so it is cleaned up like a failed commit
+    let stored = async {
+        images::upload_image(state, &storage_key, attachment.data).await?;
+        tx.commit().await?;
+        anyhow::Ok(())
+    }
+    .await;
+    if let Err(e) = stored {
+        if let Err(delete_error) = images::delete_image(state, &storage_key).await {
+            error!(
+                "Could not delete {} after its attachment failed to be recorded, it is orphaned \
+                 in the storage: {:#}",
+                storage_key, delete_error
+            );
+        }
+        return Err(e);
+    }
+
+    Ok(id)
+}
+
+/// Service function for retrieving an attachment of a ticket by id.
+pub async fn get_attachment_by_id(
+    state: &TiraState,
+    ticket_id: i64,
+    attachment_id: i64,
+) -> Result<Attachment> {
+    let attachment = dao::attachments::get_attachment_by_id(state, attachment_id).await?;
+    if attachment.ticket_id != ticket_id {
+        return Err(ServiceError::NotFound("Attachment not found".to_string()).into());
+    }
+    Ok(attachment)
+}
+
+/// Service function for loading the content of an attachment from the image storage.
+pub async fn load_attachment(state: &TiraState, attachment: &Attachment) -> Result<Vec<u8>> {
+    images::load_image(state, &attachment.storage_key).await
+}
+
+/// Service function for deleting an attachment of a ticket by id, along with its content.
+///
+/// The attachment is deleted first, so content that cannot be deleted is only left behind in the
+/// image storage, and logged as orphaned so that it can be removed by hand.
+pub async fn delete_attachment_by_id(
+    state: &TiraState,
+    ticket_id: i64,
+    attachment_id: i64,
+) -> Result<()> {
+    let attachment = get_attachment_by_id(state, ticket_id, attachment_id).await?;
+
+    let attachments_deleted =
+        dao::attachments::delete_attachment_by_id(state, attachment_id).await?;
+    service::check_only_one_row_changed(attachments_deleted)?;
+    if let Err(e) = images::delete_image(state, &attachment.storage_key).await {
+        error!(
+            "Could not delete {} of deleted attachment {}, it is orphaned in the storage: {:#}",
+            attachment.storage_key, attachment_id, e
+        );
+    }
+    Ok(())
+}
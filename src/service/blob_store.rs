@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use aws_sdk_s3::types::{ByteStream, SdkError};
+use aws_sdk_s3::{Client, Endpoint, Region};
+use clap::ValueEnum;
+use log::info;
+use tokio::fs;
+
+use crate::service::ServiceError;
+
+/// What the methods of a [`BlobStore`] return.
+pub type BlobFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// Somewhere the content of images and attachments is kept, by key.
+///
+/// Keys are relative paths like `attachments/<uuid>`.
+pub trait BlobStore: Send + Sync {
+    /// Stores `data` under `key`, replacing whatever was there.
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BlobFuture<'a, ()>;
+
+    /// Returns what is stored under `key`, or a not found error if there is nothing.
+    fn get<'a>(&'a self, key: &'a str) -> BlobFuture<'a, Vec<u8>>;
+
+    /// Removes what is stored under `key`. Removing a key with nothing under it is not an error.
+    fn delete<'a>(&'a self, key: &'a str) -> BlobFuture<'a, ()>;
+}
+
+fn not_found(key: &str) -> anyhow::Error {
+    ServiceError::NotFound(format!("Nothing stored as {}", key)).into()
+}
+
+/// Keeps blobs in an S3 bucket, or a bucket of an S3 stand-in like MinIO.
+pub struct S3BlobStore {
+    client: Client,
+    bucket: String,
+}
+
+impl S3BlobStore {
+    /// Creates a client for `bucket` with the credentials and region of the environment.
+    ///
+    /// `endpoint` replaces the AWS endpoint, for S3 stand-ins.
+    pub async fn new(bucket: &str, endpoint: Option<&str>, region: Option<&str>) -> Result<Self> {
+        let shared_config = aws_config::load_from_env().await;
+        let mut config = aws_sdk_s3::config::Builder::from(&shared_config);
+        if let Some(endpoint) = endpoint {
+            let uri = endpoint
+                .parse()
+                .with_context(|| format!("Invalid storage endpoint {}", endpoint))?;
+            config = config.endpoint_resolver(Endpoint::immutable(uri));
+        }
+        if let Some(region) = region {
+            config = config.region(Region::new(region.to_string()));
+        }
+
+        Ok(Self {
+            client: Client::from_conf(config.build()),
+            bucket: bucket.to_string(),
+        })
+    }
+}
+
+impl BlobStore for S3BlobStore {
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BlobFuture<'a, ()> {
+        Box::pin(async move {
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .body(ByteStream::from(data))
+                .send()
+                .await
+                .with_context(|| format!("Could not store {} in S3", key))?;
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BlobFuture<'a, Vec<u8>> {
+        Box::pin(async move {
+            let response = match self
+                .client
+                .get_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .send()
+                .await
+            {
+                Err(SdkError::ServiceError { err, .. }) if err.is_no_such_key() => {
+                    return Err(not_found(key))
+                }
+                response => response.with_context(|| format!("Could not load {} from S3", key))?,
+            };
+
+            let data = response
+                .body
+                .collect()
+                .await
+                .with_context(|| format!("Could not load {} from S3", key))?;
+            Ok(data.into_bytes().to_vec())
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BlobFuture<'a, ()> {
+        Box::pin(async move {
+            self.client
+                .delete_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .send()
+                .await
+                .with_context(|| format!("Could not delete {} from S3", key))?;
+            Ok(())
+        })
+    }
+}
+
+/// Keeps every blob in its own file under a directory.
+pub struct LocalBlobStore {
+    dir: PathBuf,
+}
+
+impl LocalBlobStore {
+    /// Creates the store, creating `dir` if it does not exist yet.
+    pub fn new(dir: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Could not create storage directory {}", dir.display()))?;
+        Ok(Self { dir })
+    }
+
+    /// Returns the file a key is stored in, refusing keys that lead out of the directory.
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        let relative = Path::new(key);
+        let is_inside = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if key.is_empty() || !is_inside {
+            return Err(ServiceError::BadRequest(format!("Invalid storage key {}", key)).into());
+        }
+        Ok(self.dir.join(relative))
+    }
+}
+
+impl BlobStore for LocalBlobStore {
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BlobFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .await
+                    .with_context(|| format!("Could not create {}", parent.display()))?;
+            }
+
+            // Written next to the blob first, so that readers never see half of it
+            let partial_path = path.with_file_name(format!("{}.partial", uuid::Uuid::new_v4()));
+            fs::write(&partial_path, data)
+                .await
+                .with_context(|| format!("Could not write {}", partial_path.display()))?;
+            fs::rename(&partial_path, &path)
+                .await
+                .with_context(|| format!("Could not write {}", path.display()))?;
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BlobFuture<'a, Vec<u8>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            match fs::read(&path).await {
+                Ok(data) => Ok(data),
+                Err(e) if e.kind() == ErrorKind::NotFound => Err(not_found(key)),
+                Err(e) => Err(e).with_context(|| format!("Could not read {}", path.display())),
+            }
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BlobFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            match fs::remove_file(&path).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    Err(e).with_context(|| format!("Could not delete {}", path.display()))
+                }
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+/// Keeps every blob in memory, losing them when the program ends.
+#[derive(Default)]
+pub struct MemoryBlobStore {
+    blobs: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl BlobStore for MemoryBlobStore {
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BlobFuture<'a, ()> {
+        self.blobs.lock().unwrap().insert(key.to_string(), data);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BlobFuture<'a, Vec<u8>> {
+        let data = self.blobs.lock().unwrap().get(key).cloned();
+        Box::pin(async move { data.ok_or_else(|| not_found(key)) })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BlobFuture<'a, ()> {
+        self.blobs.lock().unwrap().remove(key);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Which [`BlobStore`] to keep images and attachments in.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum BlobStoreKind {
+    /// An S3 bucket, or a bucket of an S3 stand-in
+    S3,
+    /// Files in a directory
+    Local,
+    /// Kept in memory
+    Memory,
+}
+
+/// Settings for storing images and attachments.
+#[derive(Debug, Clone)]
+pub struct BlobStoreConfig {
+    pub kind: BlobStoreKind,
+    pub bucket: Option<String>,
+    /// Endpoint of an S3 stand-in, defaults to AWS.
+    pub endpoint: Option<String>,
+    /// Region of the bucket, defaults to the region of the environment.
+    pub region: Option<String>,
+    /// Directory the local store keeps files in.
+    pub dir: Option<PathBuf>,
+}
+
+/// Checks the storage settings and sets up the store they select.
+pub async fn connect_blob_store(config: &BlobStoreConfig) -> Result<Arc<dyn BlobStore>> {
+    let store: Arc<dyn BlobStore> = match config.kind {
+        BlobStoreKind::S3 => {
+            let bucket = config
+                .bucket
+                .as_deref()
+                .ok_or_else(|| anyhow!("IMAGE_BUCKET_NAME has to be set to store files in S3"))?;
+            info!("storing files in the S3 bucket {}", bucket);
+            Arc::new(
+                S3BlobStore::new(bucket, config.endpoint.as_deref(), config.region.as_deref())
+                    .await?,
+            )
+        }
+        BlobStoreKind::Local => {
+            let dir = config.dir.clone().ok_or_else(|| {
+                anyhow!("TIRA_STORAGE_DIR has to be set to store files in a directory")
+            })?;
+            info!("storing files in {}", dir.display());
+            Arc::new(LocalBlobStore::new(dir)?)
+        }
+        BlobStoreKind::Memory => {
+            info!("storing files in memory");
+            Arc::new(MemoryBlobStore::default())
+        }
+    };
+    Ok(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn assert_round_trip(store: &dyn BlobStore) {
+        let key = "attachments/first";
+        store.put(key, b"first version".to_vec()).await.unwrap();
+        assert_eq!(store.get(key).await.unwrap(), b"first version");
+
+        store.put(key, b"second version".to_vec()).await.unwrap();
+        assert_eq!(store.get(key).await.unwrap(), b"second version");
+
+        store.delete(key).await.unwrap();
+        let missing = store.get(key).await.unwrap_err();
+        assert!(matches!(
+            missing.downcast_ref(),
+            Some(ServiceError::NotFound(_))
+        ));
+
+        // Deleting again is not an error
+        store.delete(key).await.unwrap();
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("tira-blobs-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn memory_store_round_trips() {
+        assert_round_trip(&MemoryBlobStore::default()).await;
+    }
+
+    #[tokio::test]
+    async fn local_store_round_trips() {
+        let dir = temp_dir();
+        let store = LocalBlobStore::new(dir.clone()).unwrap();
+        assert_round_trip(&store).await;
+
+        // Nothing is left behind but the directory of the key
+        let leftovers = std::fs::read_dir(dir.join("attachments")).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn local_store_keys_stay_inside_the_directory() {
+        let dir = temp_dir();
+        let store = LocalBlobStore::new(dir.clone()).unwrap();
+        assert_eq!(
+            store.path("attachments/abc").unwrap(),
+            dir.join("attachments/abc")
+        );
+
+        for key in [
+            "",
+            "..",
+            "../outside",
+            "attachments/../../outside",
+            "/etc/passwd",
+            "./attachments/abc",
+        ] {
+            let error = store.path(key).unwrap_err();
+            assert!(
+                matches!(error.downcast_ref(), Some(ServiceError::BadRequest(_))),
+                "{} should be rejected",
+                key
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
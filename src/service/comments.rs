@@ -1,12 +1,307 @@
-use crate::{dao, models::patch::UpdateComment, service, TiraState};
+use crate::{
+    dao,
+    models::{
+        patch::UpdateComment, success::ReactionCountResponse, Comment, CommentRevision, Mention,
+    },
+    service::{
+        self, html,
+        notifications::{self, Notification},
+        tickets::strip_html_tags,
+        webhooks::{self, WebhookEvent},
+        ServiceError,
+    },
+    TiraState,
+};
 use anyhow::Result;
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// Matches an @username mention, but not the @ in an email address.
+static MENTION_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?:^|[^\w@.])@([\w.\-]+)").unwrap());
+
+/// Longest emoji that can be reacted with, in characters, which leaves room for skin tones and
+/// emojis joined together.
+const MAX_EMOJI_CHARS: usize = 10;
+
+/// What deleted comments show instead of their content.
+pub const DELETED_COMMENT_PLACEHOLDER: &str = "<p><em>This comment was deleted.</em></p>";
+
+/// Replaces the content of a deleted comment with the placeholder.
+pub fn hide_deleted_content(mut comment: Comment) -> Comment {
+    if comment.deleted.is_some() {
+        comment.content = DELETED_COMMENT_PLACEHOLDER.to_string();
+        comment.content_source = DELETED_COMMENT_PLACEHOLDER.to_string();
+        comment.content_format = html::HTML_FORMAT.to_string();
+    }
+    comment
+}
+
+/// Returns the usernames mentioned as @username in the text of a comment, sorted and without
+/// duplicates.
+fn get_mentioned_usernames(text: &str) -> Vec<String> {
+    let mut usernames: Vec<String> = MENTION_REGEX
+        .captures_iter(text)
+        // A mention at the end of a sentence is followed by a full stop
+        .map(|captures| captures[1].trim_end_matches(['.', '-']).to_string())
+        .filter(|username| !username.is_empty())
+        .collect();
+    usernames.sort();
+    usernames.dedup();
+    usernames
+}
+
+/// Returns the ids of the active users mentioned as @username in the text of a comment.
+pub async fn get_mentioned_user_ids(state: &TiraState, text: &str) -> Result<Vec<i64>> {
+    let usernames = get_mentioned_usernames(text);
+    if usernames.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    dao::users::get_active_user_ids_by_usernames(state, &usernames).await
+}
+
+/// Service function for retrieving a comment by id.
+pub async fn get_comment_by_id(state: &TiraState, comment_id: i64) -> Result<Comment> {
+    let comment = dao::comments::get_comment_by_id(state, comment_id).await?;
+    Ok(hide_deleted_content(comment))
+}
 
 /// Service function for updating a comment by id.
+///
+/// The new content is rendered to sanitized HTML, in the format the comment was written in unless
+/// another one is given, and the previous content is kept as a revision. The webhooks get
+/// told about the edit by `editor_id`. Users newly mentioned as @username start watching the
+/// ticket and get told about the mention. Deleted comments cannot be edited.
 pub async fn update_comment_by_id(
     state: &TiraState,
     comment: UpdateComment,
     comment_id: i64,
+    editor_id: i64,
 ) -> Result<()> {
-    let comments_updated = dao::comments::update_comment_by_id(state, comment, comment_id).await?;
-    service::check_only_one_row_changed(comments_updated)
+    let mut tx = state.pool.begin().await?;
+    let old_comment = dao::comments::get_comment_by_id_for_update(&mut tx, comment_id).await?;
+    if old_comment.deleted.is_some() {
+        return Err(ServiceError::NotFound("Comment was deleted".to_string()).into());
+    }
+
+    let content_format = comment
+        .content_format
+        .unwrap_or_else(|| old_comment.content_format.clone());
+    html::check_content_format(&content_format)?;
+    let (content_source, content) = state
+        .html_sanitizer
+        .render(&content_format, &comment.content);
+    let content_without_tags = strip_html_tags(&content);
+    if content_without_tags.trim().is_empty() {
+        return Err(ServiceError::invalid_field("content", "Comment cannot be blank!").into());
+    }
+    if old_comment.content_source == content_source && old_comment.content_format == content_format
+    {
+        return Ok(());
+    }
+
+    let mentioned_ids = get_mentioned_user_ids(state, &content_without_tags).await?;
+
+    dao::comments::create_comment_revision(&mut tx, &old_comment, editor_id).await?;
+    let comments_updated = dao::comments::update_comment_by_id(
+        &mut tx,
+        &content,
+        &content_source,
+        &content_format,
+        &content_without_tags,
+        comment_id,
+    )
+    .await?;
+    service::check_only_one_row_changed(comments_updated)?;
+    let new_mentioned_ids =
+        dao::comments::replace_mentions(&mut tx, comment_id, &mentioned_ids).await?;
+    dao::watchers::create_watchers(&mut tx, old_comment.ticket_id, &new_mentioned_ids).await?;
+
+    let ticket =
+        dao::tickets::get_ticket_by_id_in_transaction(&mut tx, old_comment.ticket_id).await?;
+    notifications::notify(
+        state,
+        &mut tx,
+        editor_id,
+        &ticket,
+        Notification::Mentioned {
+            content: &content,
+            mentioned_ids: &new_mentioned_ids,
+        },
+    )
+    .await?;
+    webhooks::trigger(
+        state,
+        &mut tx,
+        editor_id,
+        old_comment.ticket_id,
+        WebhookEvent::CommentEdited { comment_id },
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Service function for deleting a comment by id.
+///
+/// The comment stays in the ticket's comments, with a placeholder instead of its content.
+pub async fn delete_comment_by_id(state: &TiraState, comment_id: i64) -> Result<()> {
+    let comments_deleted = dao::comments::delete_comment_by_id(state, comment_id).await?;
+    service::check_only_one_row_changed(comments_deleted)
+}
+
+/// Service function for retrieving the revisions of a comment, oldest first.
+pub async fn get_revisions_by_comment_id(
+    state: &TiraState,
+    comment_id: i64,
+) -> Result<Vec<CommentRevision>> {
+    let revisions = dao::comments::get_revisions_by_comment_id(state, comment_id).await?;
+    Ok(revisions)
+}
+
+/// Checks that a reaction is an emoji rather than arbitrary text.
+fn check_emoji(emoji: &str) -> Result<()> {
+    let is_emoji = !emoji.is_empty()
+        && emoji.chars().count() <= MAX_EMOJI_CHARS
+        && emoji
+            .chars()
+            .all(|c| !c.is_ascii() && !c.is_whitespace() && !c.is_control());
+    if is_emoji {
+        Ok(())
+    } else {
+        Err(ServiceError::invalid_field("emoji", "Reactions have to be an emoji").into())
+    }
+}
+
+/// Service function for retrieving the reactions to comments per emoji, by comment id.
+///
+/// `reacted` tells whether `user_id` reacted with the emoji.
+pub async fn get_reactions_by_comment_ids(
+    state: &TiraState,
+    comment_ids: &[i64],
+    user_id: i64,
+) -> Result<HashMap<i64, Vec<ReactionCountResponse>>> {
+    let counts =
+        dao::comments::get_reaction_counts_by_comment_ids(state, comment_ids, user_id).await?;
+
+    let mut reactions: HashMap<i64, Vec<ReactionCountResponse>> = HashMap::new();
+    for count in counts {
+        reactions
+            .entry(count.comment_id)
+            .or_default()
+            .push(ReactionCountResponse {
+                emoji: count.emoji,
+                count: count.count,
+                reacted: count.reacted,
+            });
+    }
+    Ok(reactions)
+}
+
+/// Service function for retrieving the reactions to a comment per emoji.
+async fn get_reactions_by_comment_id(
+    state: &TiraState,
+    comment_id: i64,
+    user_id: i64,
+) -> Result<Vec<ReactionCountResponse>> {
+    let mut reactions = get_reactions_by_comment_ids(state, &[comment_id], user_id).await?;
+    Ok(reactions.remove(&comment_id).unwrap_or_default())
+}
+
+/// Service function for reacting to a comment with an emoji.
+///
+/// Returns the reactions to the comment afterwards. Deleted comments cannot be reacted to.
+pub async fn create_reaction(
+    state: &TiraState,
+    comment_id: i64,
+    user_id: i64,
+    emoji: &str,
+) -> Result<Vec<ReactionCountResponse>> {
+    check_emoji(emoji)?;
+    let comment = dao::comments::get_comment_by_id(state, comment_id).await?;
+    if comment.deleted.is_some() {
+        return Err(ServiceError::NotFound("Comment was deleted".to_string()).into());
+    }
+
+    dao::comments::create_reaction(state, comment_id, user_id, emoji).await?;
+    get_reactions_by_comment_id(state, comment_id, user_id).await
+}
+
+/// Service function for taking back a reaction to a comment.
+///
+/// Returns the reactions to the comment afterwards.
+pub async fn delete_reaction(
+    state: &TiraState,
+    comment_id: i64,
+    user_id: i64,
+    emoji: &str,
+) -> Result<Vec<ReactionCountResponse>> {
+    let reactions_deleted =
+        dao::comments::delete_reaction(state, comment_id, user_id, emoji).await?;
+    service::check_only_one_row_changed(reactions_deleted)?;
+    get_reactions_by_comment_id(state, comment_id, user_id).await
+}
+
+/// Service function for counting the comments that mention a user.
+pub async fn count_mentions_by_user_id(state: &TiraState, user_id: i64) -> Result<i64> {
+    dao::comments::count_mentions_by_user_id(state, user_id).await
+}
+
+/// Service function for retrieving the comments that mention a user, newest first.
+pub async fn get_mentions_by_user_id(
+    state: &TiraState,
+    user_id: i64,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Vec<Mention>> {
+    let limit = limit.unwrap_or(10);
+    let offset = offset.unwrap_or(0);
+    if limit < 0 || offset < 0 {
+        return Err(
+            ServiceError::BadRequest("Limit and offset cannot be negative".to_string()).into(),
+        );
+    }
+    dao::comments::get_mentions_by_user_id(state, user_id, limit, offset).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mentions_skip_email_addresses() {
+        assert!(get_mentioned_usernames("mail a@b.com about it").is_empty());
+        assert!(get_mentioned_usernames("a@b.com").is_empty());
+    }
+
+    #[test]
+    fn mentions_drop_trailing_punctuation() {
+        assert_eq!(get_mentioned_usernames("ask @bob."), ["bob"]);
+        assert_eq!(get_mentioned_usernames("ask @bob-"), ["bob"]);
+        assert!(get_mentioned_usernames("@.").is_empty());
+    }
+
+    #[test]
+    fn mentions_skip_double_at() {
+        assert!(get_mentioned_usernames("@@x").is_empty());
+        assert!(get_mentioned_usernames("see @@x too").is_empty());
+    }
+
+    #[test]
+    fn mentions_keep_dots_and_dashes_inside_usernames() {
+        assert_eq!(
+            get_mentioned_usernames("@jane.doe and @john-smith"),
+            ["jane.doe", "john-smith"]
+        );
+    }
+
+    #[test]
+    fn mentions_are_found_after_punctuation_and_deduplicated() {
+        assert_eq!(
+            get_mentioned_usernames("(@carol) @alice, @carol\n@alice"),
+            ["alice", "carol"]
+        );
+    }
 }
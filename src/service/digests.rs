@@ -0,0 +1,192 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{error, info};
+use sqlx::{Acquire, Postgres, Transaction};
+
+use crate::{
+    dao,
+    service::emails::{self, Email},
+    TiraState,
+};
+
+/// Digest frequency of users that get every notification as its own email.
+pub const OFF_FREQUENCY: &str = "off";
+
+pub const DAILY_FREQUENCY: &str = "daily";
+
+pub const WEEKLY_FREQUENCY: &str = "weekly";
+
+pub const FREQUENCIES: [&str; 3] = [OFF_FREQUENCY, DAILY_FREQUENCY, WEEKLY_FREQUENCY];
+
+/// How many digests the digest worker sends at once.
+const DIGEST_BATCH_SIZE: i64 = 10;
+
+/// Puts the digests that are due in the email outbox until the program ends.
+///
+/// Several workers can run at once since claimed digests are skipped by the others.
+pub async fn run_digest_worker(state: TiraState, check_interval: Duration) {
+    info!("Starting digest worker");
+    loop {
+        match send_due_digests(&state).await {
+            Ok(0) => tokio::time::sleep(check_interval).await,
+            Ok(_) => (),
+            Err(e) => {
+                error!("Could not send digests: {:?}", e);
+                tokio::time::sleep(check_interval).await;
+            }
+        }
+    }
+}
+
+/// Puts a batch of due digests in the email outbox.
+///
+/// Every digest is put together in a savepoint of its own, so a digest that fails is rolled back,
+/// logged and moved to the back of the queue without holding up the others.
+///
+/// Returns how many digests were claimed.
+async fn send_due_digests(state: &TiraState) -> Result<usize> {
+    let mut tx = state.pool.begin().await?;
+    let digests = dao::digests::claim_due_digests(
+        &mut tx,
+        DAILY_FREQUENCY,
+        WEEKLY_FREQUENCY,
+        DIGEST_BATCH_SIZE,
+    )
+    .await?;
+
+    for (user_id, frequency) in &digests {
+        let mut savepoint = tx.begin().await?;
+        match send_digest(state, &mut savepoint, *user_id, frequency).await {
+            Ok(()) => savepoint.commit().await?,
+            Err(e) => {
+                savepoint.rollback().await?;
+                error!("Could not send the digest of user {}: {:#}", user_id, e);
+                dao::digests::postpone_digest(&mut tx, *user_id).await?;
+            }
+        }
+    }
+
+    tx.commit().await?;
+    Ok(digests.len())
+}
+
+/// Puts the digest of a user in the email outbox.
+async fn send_digest(
+    state: &TiraState,
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: i64,
+    frequency: &str,
+) -> Result<()> {
+    let items = dao::digests::get_digest_items_by_user_id(tx, user_id).await?;
+    let item_ids: Vec<_> = items.iter().map(|item| item.id).collect();
+
+    let user = dao::users::get_user_by_id(state, user_id).await?;
+    match user.email_address {
+        Some(email_address) if !user.archived => {
+            let email = Email::new(
+                email_address,
+                emails::create_digest_email_subject(frequency),
+                emails::create_digest_email_body(&state.email_templates, &items)?,
+            );
+            dao::emails::create_email_in_transaction(tx, &email).await?;
+            info!(
+                "Queued {} digest with {} notifications for user {}",
+                frequency,
+                items.len(),
+                user_id
+            );
+        }
+        // Nobody to send it to so the notifications are dropped
+        _ => (),
+    }
+
+    dao::digests::mark_digest_sent(tx, user_id, &item_ids).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::email_templates::EmailTemplates;
+    use sqlx::PgPool;
+    use std::{fs, sync::Arc};
+
+    /// Creates a user with a daily digest that is due, about a ticket with `subject`.
+    async fn create_due_digest(pool: &PgPool, username: &str, subject: &str) -> i64 {
+        let user_id: i64 = sqlx::query_scalar(
+            "INSERT INTO users (username, password, email_address) VALUES ($1, 'x', $1 || '@example.com') RETURNING id",
+        )
+        .bind(username)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        let ticket_id: i64 = sqlx::query_scalar(
+            "INSERT INTO tickets (subject, priority, status, reporter_id) VALUES ($1, 'Low', 'Backlog', $2) RETURNING id",
+        )
+        .bind(subject)
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO digest_settings (user_id, frequency, last_sent) VALUES ($1, $2, NOW() - INTERVAL '2 days')",
+        )
+        .bind(user_id)
+        .bind(DAILY_FREQUENCY)
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO digest_items (user_id, ticket_id, body, text_body) VALUES ($1, $2, '<p>Hi</p>', 'Hi')",
+        )
+        .bind(user_id)
+        .bind(ticket_id)
+        .execute(pool)
+        .await
+        .unwrap();
+        user_id
+    }
+
+    /// Loads templates whose digest cannot be rendered for tickets called "Broken".
+    fn templates_failing_on_broken_tickets() -> EmailTemplates {
+        let dir = std::env::temp_dir().join(format!("tira-digests-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("digest.html"),
+            r#"{% for ticket in tickets %}{% if ticket.subject == "Broken" %}{{ throw(message="broken") }}{% endif %}{{ ticket.subject }}{% endfor %}"#,
+        )
+        .unwrap();
+        let templates = EmailTemplates::load("http://localhost/tickets", Some(&dir)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        templates
+    }
+
+    #[sqlx::test]
+    async fn failing_digest_does_not_block_the_others(pool: PgPool) {
+        let broken_id = create_due_digest(&pool, "broken", "Broken").await;
+        let working_id = create_due_digest(&pool, "working", "Working").await;
+        let state = TiraState {
+            email_templates: Arc::new(templates_failing_on_broken_tickets()),
+            ..TiraState::for_tests(pool.clone())
+        };
+
+        assert_eq!(send_due_digests(&state).await.unwrap(), 2);
+
+        let recipients: Vec<String> = sqlx::query_scalar("SELECT recipient FROM email_outbox")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(recipients, ["working@example.com"]);
+
+        let count_items = |user_id: i64| {
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM digest_items WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_one(&pool)
+        };
+        assert_eq!(count_items(working_id).await.unwrap(), 0);
+        assert_eq!(count_items(broken_id).await.unwrap(), 1);
+
+        // The broken digest waits for its next period instead of being claimed again right away
+        assert_eq!(send_due_digests(&state).await.unwrap(), 0);
+    }
+}
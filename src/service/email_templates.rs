@@ -0,0 +1,168 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context as _, Result};
+use log::info;
+use tera::{Context, Tera};
+
+use crate::service::tickets::strip_html_tags;
+
+/// Templates every email is built from, as `(name, default content)`.
+///
+/// Each email has an HTML template and a plain-text template. Variables are HTML-escaped in the
+/// `.html` templates.
+const DEFAULT_TEMPLATES: [(&str, &str); 12] = [
+    (
+        "assignment.html",
+        include_str!("../../templates/emails/assignment.html"),
+    ),
+    (
+        "assignment.txt",
+        include_str!("../../templates/emails/assignment.txt"),
+    ),
+    (
+        "comment.html",
+        include_str!("../../templates/emails/comment.html"),
+    ),
+    (
+        "comment.txt",
+        include_str!("../../templates/emails/comment.txt"),
+    ),
+    (
+        "digest.html",
+        include_str!("../../templates/emails/digest.html"),
+    ),
+    (
+        "digest.txt",
+        include_str!("../../templates/emails/digest.txt"),
+    ),
+    (
+        "mention.html",
+        include_str!("../../templates/emails/mention.html"),
+    ),
+    (
+        "mention.txt",
+        include_str!("../../templates/emails/mention.txt"),
+    ),
+    (
+        "status_change.html",
+        include_str!("../../templates/emails/status_change.html"),
+    ),
+    (
+        "status_change.txt",
+        include_str!("../../templates/emails/status_change.txt"),
+    ),
+    (
+        "ticket_created.html",
+        include_str!("../../templates/emails/ticket_created.html"),
+    ),
+    (
+        "ticket_created.txt",
+        include_str!("../../templates/emails/ticket_created.txt"),
+    ),
+];
+
+/// The two alternative bodies of an email.
+#[derive(Clone)]
+pub struct EmailBody {
+    pub html: String,
+    pub text: String,
+}
+
+/// Renders email bodies from the built-in templates or the ones that override them.
+pub struct EmailTemplates {
+    tera: Tera,
+    ticket_link: String,
+}
+
+impl EmailTemplates {
+    /// Loads the templates, preferring files with the same name in `dir` over the built-in ones.
+    ///
+    /// `ticket_link` is the base of links to tickets and available to every template.
+    pub fn load(ticket_link: &str, dir: Option<&Path>) -> Result<Self> {
+        if let Some(dir) = dir {
+            if !dir.is_dir() {
+                return Err(anyhow!(
+                    "Email template directory {} does not exist",
+                    dir.display()
+                ));
+            }
+        }
+
+        let mut templates = Vec::new();
+        for (name, default) in DEFAULT_TEMPLATES {
+            let path = dir.map(|dir| dir.join(name));
+            let content = match path {
+                Some(path) if path.exists() => {
+                    info!("Using email template {}", path.display());
+                    fs::read_to_string(&path).with_context(|| {
+                        format!("Could not read email template {}", path.display())
+                    })?
+                }
+                _ => default.to_string(),
+            };
+            templates.push((name, content));
+        }
+
+        let mut tera = Tera::default();
+        tera.add_raw_templates(templates)
+            .context("Invalid email template")?;
+
+        Ok(Self {
+            tera,
+            ticket_link: ticket_link.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// Renders the HTML and plain-text templates of the email called `name`.
+    pub fn render(&self, name: &str, mut context: Context) -> Result<EmailBody> {
+        context.insert("ticket_link", &self.ticket_link);
+
+        let render = |file: String| {
+            self.tera
+                .render(&file, &context)
+                .with_context(|| format!("Could not render email template {}", file))
+        };
+        Ok(EmailBody {
+            html: render(format!("{}.html", name))?,
+            text: render(format!("{}.txt", name))?,
+        })
+    }
+}
+
+/// Removes everything from user-written HTML that could harm the recipient, like scripts, styles
+/// and event handlers.
+pub fn sanitize_html(html: &str) -> String {
+    ammonia::clean(html)
+}
+
+/// Turns user-written HTML into plain text for the plain-text part of an email.
+pub fn html_to_text(html: &str) -> String {
+    // Sanitizing first drops the content of scripts and styles along with their tags
+    let text = strip_html_tags(&sanitize_html(html));
+    html_escape::decode_html_entities(&text)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_to_text_decodes_every_entity() {
+        assert_eq!(
+            html_to_text("<p>Fish&nbsp;&amp;&nbsp;chips &lt;3 &euro;5 &#x2014; &quot;ok&quot;</p>"),
+            "Fish & chips <3 €5 — \"ok\""
+        );
+    }
+
+    #[test]
+    fn html_to_text_drops_scripts_and_extra_whitespace() {
+        assert_eq!(
+            html_to_text("<p>one</p>\n\n<script>alert(1)</script><p>  two</p>"),
+            "one two"
+        );
+    }
+}
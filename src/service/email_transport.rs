@@ -0,0 +1,253 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use lettre::{
+    message::{Mailbox, MultiPart, SinglePart},
+    transport::smtp::authentication::Credentials,
+    Message, SmtpTransport, Transport,
+};
+use log::info;
+
+use crate::service::emails::Email;
+
+/// Something that can deliver emails.
+///
+/// Sending may block so it should happen off of the async runtime.
+pub trait EmailTransport: Send + Sync {
+    fn send(&self, message: &Message) -> Result<()>;
+}
+
+/// Sends emails to an SMTP server.
+pub struct SmtpEmailTransport {
+    mailer: SmtpTransport,
+}
+
+impl SmtpEmailTransport {
+    /// Creates a transport that upgrades to TLS with STARTTLS and logs in.
+    pub fn starttls(host: &str, port: u16, credentials: Credentials) -> Result<Self> {
+        let mailer = SmtpTransport::starttls_relay(host)?
+            .port(port)
+            .credentials(credentials)
+            .build();
+        Ok(Self { mailer })
+    }
+
+    /// Creates a transport without TLS or authentication, for local mail sinks like MailHog.
+    pub fn plain(host: &str, port: u16) -> Self {
+        let mailer = SmtpTransport::builder_dangerous(host).port(port).build();
+        Self { mailer }
+    }
+}
+
+impl EmailTransport for SmtpEmailTransport {
+    fn send(&self, message: &Message) -> Result<()> {
+        self.mailer.send(message).context("Could not send email")?;
+        Ok(())
+    }
+}
+
+/// Writes every email to its own .eml file in a directory instead of sending it.
+pub struct FileEmailTransport {
+    dir: PathBuf,
+}
+
+impl FileEmailTransport {
+    /// Creates the transport, creating `dir` if it does not exist yet.
+    pub fn new(dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Could not create email directory {}", dir.display()))?;
+        Ok(Self { dir })
+    }
+}
+
+impl EmailTransport for FileEmailTransport {
+    fn send(&self, message: &Message) -> Result<()> {
+        let path = self.dir.join(format!("{}.eml", uuid::Uuid::new_v4()));
+        fs::write(&path, message.formatted())
+            .with_context(|| format!("Could not write email to {}", path.display()))?;
+        info!("Wrote email to {}", path.display());
+        Ok(())
+    }
+}
+
+/// Keeps every email in memory instead of sending it.
+#[derive(Default)]
+pub struct MemoryEmailTransport {
+    sent: Mutex<Vec<Message>>,
+}
+
+impl MemoryEmailTransport {
+    /// Returns the emails sent so far, oldest first.
+    #[cfg(test)]
+    pub fn sent(&self) -> Vec<Message> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+impl EmailTransport for MemoryEmailTransport {
+    fn send(&self, message: &Message) -> Result<()> {
+        info!("Keeping email to {:?} in memory", message.envelope().to());
+        self.sent.lock().unwrap().push(message.clone());
+        Ok(())
+    }
+}
+
+/// Which [`EmailTransport`] to send emails with.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum EmailTransportKind {
+    /// SMTP with STARTTLS and a login
+    Smtp,
+    /// SMTP without TLS or a login
+    PlainSmtp,
+    /// .eml files in a directory
+    File,
+    /// Kept in memory
+    Memory,
+}
+
+/// Settings for sending emails.
+#[derive(Debug, Clone)]
+pub struct EmailConfig {
+    pub transport: EmailTransportKind,
+    /// Sender address, defaults to `<username>@<domain>`.
+    pub from: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub domain: Option<String>,
+    /// Subdomain of `domain` the SMTP server runs on, used if no host is given.
+    pub subdomain: Option<String>,
+    pub smtp_host: Option<String>,
+    pub smtp_port: Option<u16>,
+    /// Directory the file transport writes to.
+    pub dir: Option<PathBuf>,
+}
+
+fn require<'a, T>(value: &'a Option<T>, name: &str) -> Result<&'a T> {
+    value
+        .as_ref()
+        .ok_or_else(|| anyhow!("{} has to be set to send emails this way", name))
+}
+
+/// Builds emails from the outbox and sends them with the configured transport.
+pub struct EmailSender {
+    from: Mailbox,
+    transport: Arc<dyn EmailTransport>,
+}
+
+impl EmailSender {
+    /// Creates a sender that sends from `from` with `transport`.
+    ///
+    /// Callers can keep a handle on the transport, to look at what a [`MemoryEmailTransport`]
+    /// kept for example.
+    pub fn new(from: Mailbox, transport: Arc<dyn EmailTransport>) -> Self {
+        Self { from, transport }
+    }
+
+    /// Checks the email settings and sets up the transport they select.
+    pub fn from_config(config: &EmailConfig) -> Result<Self> {
+        let from = match (&config.from, &config.username, &config.domain) {
+            (Some(from), _, _) => from.clone(),
+            (None, Some(username), Some(domain)) => format!("{}@{}", username, domain),
+            _ => {
+                return Err(anyhow!(
+                    "TIRA_EMAIL_FROM or TIRA_EMAIL_USERNAME and TIRA_EMAIL_DOMAIN have to be set"
+                ))
+            }
+        };
+        let from = from
+            .parse()
+            .with_context(|| format!("Invalid sender address '{}'", from))?;
+
+        let smtp_host = || -> Result<String> {
+            match &config.smtp_host {
+                Some(host) => Ok(host.clone()),
+                None => Ok(format!(
+                    "{}.{}",
+                    require(&config.subdomain, "TIRA_EMAIL_SUBDOMAIN")?,
+                    require(&config.domain, "TIRA_EMAIL_DOMAIN")?
+                )),
+            }
+        };
+
+        let transport: Arc<dyn EmailTransport> = match config.transport {
+            EmailTransportKind::Smtp => {
+                let credentials = Credentials::new(
+                    require(&config.username, "TIRA_EMAIL_USERNAME")?.clone(),
+                    require(&config.password, "TIRA_EMAIL_PASSWORD")?.clone(),
+                );
+                let port = *require(&config.smtp_port, "TIRA_EMAIL_SMTP_PORT")?;
+                Arc::new(SmtpEmailTransport::starttls(
+                    &smtp_host()?,
+                    port,
+                    credentials,
+                )?)
+            }
+            EmailTransportKind::PlainSmtp => {
+                let port = *require(&config.smtp_port, "TIRA_EMAIL_SMTP_PORT")?;
+                Arc::new(SmtpEmailTransport::plain(&smtp_host()?, port))
+            }
+            EmailTransportKind::File => Arc::new(FileEmailTransport::new(
+                require(&config.dir, "TIRA_EMAIL_DIR")?.clone(),
+            )?),
+            EmailTransportKind::Memory => Arc::new(MemoryEmailTransport::default()),
+        };
+
+        Ok(Self::new(from, transport))
+    }
+
+    /// Sends an email.
+    pub fn send(&self, email: &Email) -> Result<()> {
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .to(email.to.parse().context("Invalid recipient address")?)
+            .subject(email.subject.clone());
+        if let Some(reply_to) = &email.reply_to {
+            builder = builder.reply_to(reply_to.parse().context("Invalid reply address")?);
+        }
+        let message = match &email.text_body {
+            Some(text_body) => builder.multipart(MultiPart::alternative_plain_html(
+                text_body.clone(),
+                email.body.clone(),
+            )),
+            None => builder.singlepart(SinglePart::html(email.body.clone())),
+        }
+        .context("Could not build email")?;
+
+        self.transport.send(&message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::email_templates::EmailBody;
+
+    #[test]
+    fn memory_transport_keeps_sent_emails() {
+        let transport = Arc::new(MemoryEmailTransport::default());
+        let sender = EmailSender::new("tira@example.com".parse().unwrap(), transport.clone());
+        let mut email = Email::new(
+            "user@example.com".to_string(),
+            "Ticket 1".to_string(),
+            EmailBody {
+                html: "<p>Hello</p>".to_string(),
+                text: "Hello".to_string(),
+            },
+        );
+        email.reply_to = Some("tira+reply@example.com".to_string());
+
+        sender.send(&email).unwrap();
+
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 1);
+        let formatted = String::from_utf8(sent[0].formatted()).unwrap();
+        assert!(formatted.contains("From: tira@example.com"));
+        assert!(formatted.contains("To: user@example.com"));
+        assert!(formatted.contains("Reply-To: tira+reply@example.com"));
+        assert!(formatted.contains("Subject: Ticket 1"));
+        assert!(formatted.contains("multipart/alternative"));
+    }
+}
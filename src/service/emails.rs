@@ -1,18 +1,59 @@
-use std::env;
-use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::Duration;
 
-use lettre::{
-    message::SinglePart, transport::smtp::authentication::Credentials, Message, SmtpTransport,
-    Transport,
+use anyhow::{anyhow, Result};
+use log::{error, info, warn};
+use serde::Serialize;
+use sqlx::{Postgres, Transaction};
+use tera::Context;
+
+use crate::{
+    dao,
+    models::{DigestItem, OutboxEmail, Ticket, User},
+    service::{
+        self,
+        email_templates::{html_to_text, sanitize_html, EmailBody, EmailTemplates},
+        email_transport::EmailSender,
+        RetryConfig, ServiceError,
+    },
+    TiraState,
 };
-use log::info;
 
-use crate::models::User;
+/// Status of outbox emails that still have to be sent.
+pub const PENDING_STATUS: &str = "pending";
+
+/// Status of outbox emails that were sent.
+pub const SENT_STATUS: &str = "sent";
+
+/// Status of outbox emails that failed too many times and are no longer tried.
+pub const DEAD_STATUS: &str = "dead";
+
+const OUTBOX_STATUSES: [&str; 3] = [PENDING_STATUS, SENT_STATUS, DEAD_STATUS];
+
+/// How many emails the outbox worker claims at once.
+const OUTBOX_BATCH_SIZE: i64 = 10;
 
 pub struct Email {
     pub to: String,
     pub subject: String,
+    /// HTML body.
     pub body: String,
+    /// Plain-text alternative of the HTML body.
+    pub text_body: Option<String>,
+    /// Address that replies go to instead of the sender.
+    pub reply_to: Option<String>,
+}
+
+impl Email {
+    pub fn new(to: String, subject: String, body: EmailBody) -> Self {
+        Self {
+            to,
+            subject,
+            body: body.html,
+            text_body: Some(body.text),
+            reply_to: None,
+        }
+    }
 }
 
 fn get_real_name_display(user: &User) -> String {
@@ -43,108 +84,410 @@ fn get_display_name(user: &User) -> String {
     display_name
 }
 
+/// Builds the context shared by the emails about something `actor` did to a ticket.
+fn create_ticket_context(actor: &User, ticket: &Ticket) -> Context {
+    let mut context = Context::new();
+    context.insert("actor", &get_display_name(actor));
+    context.insert("ticket", ticket);
+    context
+}
+
 pub fn create_assignment_email_body(
+    templates: &EmailTemplates,
     assigner: &User,
-    ticket_subject: &str,
-    ticket_id: i64,
-) -> String {
-    let assigner_name = get_display_name(assigner);
-
-    format!(
-        "<p>{} assigned you to ticket '{}'.</p><p><a href=\"{}/{}\">Link to ticket</a></p>",
-        assigner_name,
-        ticket_subject,
-        env::var("TIRA_EMAIL_TICKET_LINK").unwrap(),
-        ticket_id
-    )
+    ticket: &Ticket,
+) -> Result<EmailBody> {
+    templates.render("assignment", create_ticket_context(assigner, ticket))
 }
 
 pub fn create_comment_email_body(
+    templates: &EmailTemplates,
     commenter: &User,
     comment_content: &str,
-    ticket_subject: &str,
-    ticket_id: i64,
-) -> String {
-    let commenter_name = get_display_name(commenter);
-
-    format!(
-        "<p>{} added a comment to ticket '{}'.</p><p>{}</p><p><a href=\"{}/{}\">Link to ticket</a></p>",
-        commenter_name,
-        ticket_subject,
-        comment_content,
-        env::var("TIRA_EMAIL_TICKET_LINK").unwrap(),
-        ticket_id
-    )
+    ticket: &Ticket,
+) -> Result<EmailBody> {
+    let mut context = create_ticket_context(commenter, ticket);
+    context.insert("comment_html", &sanitize_html(comment_content));
+    context.insert("comment_text", &html_to_text(comment_content));
+    templates.render("comment", context)
+}
+
+pub fn create_mention_email_body(
+    templates: &EmailTemplates,
+    commenter: &User,
+    comment_content: &str,
+    ticket: &Ticket,
+) -> Result<EmailBody> {
+    let mut context = create_ticket_context(commenter, ticket);
+    context.insert("comment_html", &sanitize_html(comment_content));
+    context.insert("comment_text", &html_to_text(comment_content));
+    templates.render("mention", context)
+}
+
+pub fn create_status_change_email_body(
+    templates: &EmailTemplates,
+    changer: &User,
+    ticket: &Ticket,
+    old_status: &str,
+) -> Result<EmailBody> {
+    let mut context = create_ticket_context(changer, ticket);
+    context.insert("old_status", old_status);
+    context.insert("new_status", &ticket.status);
+    templates.render("status_change", context)
 }
 
 pub fn create_ticket_creation_email_body(
+    templates: &EmailTemplates,
     creator: &User,
-    ticket_subject: &str,
-    ticket_description: &str,
-    created_ticket_id: i64,
-) -> String {
-    let creator_name = get_display_name(creator);
-
-    format!(
-        "<p>{} created ticket '{}'.</p>{}<p><a href=\"{}/{}\">Link to ticket</a></p>",
-        creator_name,
-        ticket_subject,
-        ticket_description,
-        env::var("TIRA_EMAIL_TICKET_LINK").unwrap(),
-        created_ticket_id
-    )
+    ticket: &Ticket,
+) -> Result<EmailBody> {
+    let description = ticket.description.as_deref().unwrap_or_default();
+
+    let mut context = create_ticket_context(creator, ticket);
+    context.insert("description_html", &sanitize_html(description));
+    context.insert("description_text", &html_to_text(description));
+    templates.render("ticket_created", context)
 }
 
-pub fn handle_emails(rx: Receiver<Email>) {
+pub fn create_digest_email_subject(frequency: &str) -> String {
+    format!("Your {} Tira digest", frequency)
+}
+
+/// A ticket in a digest with the notifications about it.
+#[derive(Serialize)]
+struct DigestTicket<'a> {
+    id: i64,
+    subject: &'a str,
+    items: Vec<DigestTicketItem<'a>>,
+}
+
+#[derive(Serialize)]
+struct DigestTicketItem<'a> {
+    created: String,
+    html: &'a str,
+    text: String,
+}
+
+/// Puts the notifications of a digest together, with a section for every ticket.
+///
+/// `items` have to be ordered by ticket.
+pub fn create_digest_email_body(
+    templates: &EmailTemplates,
+    items: &[DigestItem],
+) -> Result<EmailBody> {
+    let mut tickets: Vec<DigestTicket> = Vec::new();
+    for item in items {
+        let digest_item = DigestTicketItem {
+            created: item.created.format("%Y-%m-%d %H:%M").to_string(),
+            html: &item.body,
+            // Notifications saved before there were plain-text bodies only have HTML
+            text: item
+                .text_body
+                .clone()
+                .unwrap_or_else(|| html_to_text(&item.body)),
+        };
+
+        match tickets.last_mut() {
+            Some(ticket) if ticket.id == item.ticket_id => ticket.items.push(digest_item),
+            _ => tickets.push(DigestTicket {
+                id: item.ticket_id,
+                subject: &item.ticket_subject,
+                items: vec![digest_item],
+            }),
+        }
+    }
+
+    let mut context = Context::new();
+    context.insert("tickets", &tickets);
+    templates.render("digest", context)
+}
+
+/// Service function for putting an email in the outbox as part of a transaction, from where it
+/// gets sent in the background once the transaction is committed.
+pub async fn queue_email(tx: &mut Transaction<'_, Postgres>, email: Email) -> Result<()> {
+    dao::emails::create_email_in_transaction(tx, &email).await?;
+    Ok(())
+}
+
+/// Sends the emails in the outbox until the program ends.
+///
+/// Failed emails are retried with exponential backoff and dead-lettered after
+/// `config.max_attempts` attempts. Several workers can run at once since claimed emails are
+/// skipped by the others.
+pub async fn run_outbox_worker(state: TiraState, sender: Arc<EmailSender>, config: RetryConfig) {
+    info!("Starting email outbox worker");
     loop {
-        match rx.recv() {
-            Ok(email) => {
-                send_email(email);
+        match send_due_emails(&state, &sender, &config).await {
+            Ok(0) => tokio::time::sleep(config.poll_interval).await,
+            Ok(_) => (),
+            Err(e) => {
+                error!("Could not process email outbox: {:?}", e);
+                tokio::time::sleep(config.poll_interval).await;
+            }
+        }
+    }
+}
+
+/// Sends a batch of due emails from the outbox.
+///
+/// The emails are claimed before sending and every one is marked on its own right after, so that
+/// a failure to mark one never causes the others to be sent again. Returns how many emails were
+/// claimed.
+async fn send_due_emails(
+    state: &TiraState,
+    sender: &Arc<EmailSender>,
+    config: &RetryConfig,
+) -> Result<usize> {
+    let emails = dao::emails::claim_due_emails(
+        state,
+        PENDING_STATUS,
+        OUTBOX_BATCH_SIZE,
+        config.lease.as_secs() as i64,
+    )
+    .await?;
+
+    for outbox_email in &emails {
+        let email = Email {
+            to: outbox_email.recipient.clone(),
+            subject: outbox_email.subject.clone(),
+            body: outbox_email.body.clone(),
+            text_body: outbox_email.text_body.clone(),
+            reply_to: outbox_email.reply_to.clone(),
+        };
+
+        // Attempts are counted when claiming, so an email with more than the allowed attempts never
+        // finished its earlier ones, like when sending it crashes or hangs the worker
+        let sent = if outbox_email.attempts > config.max_attempts {
+            Err(anyhow!("Earlier attempts did not finish"))
+        } else {
+            // Sending can block so keep it off of the async runtime
+            let sender = sender.clone();
+            tokio::task::spawn_blocking(move || sender.send(&email)).await?
+        };
+        let marked = match sent {
+            Ok(()) => {
+                info!(
+                    "Sent email {} to {}",
+                    outbox_email.id, outbox_email.recipient
+                );
+                dao::emails::mark_email_sent(state, outbox_email.id, SENT_STATUS).await
             }
             Err(e) => {
-                info!("Ending email queue: {}", e);
-                return;
+                let attempts = outbox_email.attempts;
+                let (status, retry_in) = if attempts >= config.max_attempts {
+                    error!(
+                        "Giving up on email {} after {} attempts: {:#}",
+                        outbox_email.id, attempts, e
+                    );
+                    (DEAD_STATUS, Duration::ZERO)
+                } else {
+                    let retry_in = config.retry_delay(attempts);
+                    warn!(
+                        "Could not send email {}, retrying in {:?}: {:#}",
+                        outbox_email.id, retry_in, e
+                    );
+                    (PENDING_STATUS, retry_in)
+                };
+
+                dao::emails::mark_email_failed(
+                    state,
+                    outbox_email.id,
+                    status,
+                    &format!("{:#}", e),
+                    retry_in.as_secs() as i64,
+                )
+                .await
             }
+        };
+        if let Err(e) = marked {
+            error!("Could not mark email {}: {:?}", outbox_email.id, e);
         }
     }
+
+    Ok(emails.len())
 }
 
-pub fn send_email(e: Email) {
-    let email = Message::builder()
-        .from(
-            format!(
-                "{}@{}",
-                env::var("TIRA_EMAIL_USERNAME").unwrap(),
-                env::var("TIRA_EMAIL_DOMAIN").unwrap()
-            )
-            .parse()
-            .unwrap(),
+/// Checks that a status is one of the outbox statuses.
+fn check_outbox_status(status: &Option<String>) -> Result<()> {
+    match status {
+        Some(status) if !OUTBOX_STATUSES.contains(&status.as_str()) => {
+            let message = format!(
+                "Status must be '{}', '{}', or '{}'",
+                PENDING_STATUS, SENT_STATUS, DEAD_STATUS
+            );
+            Err(ServiceError::BadRequest(message).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Service function for counting the emails in the outbox.
+pub async fn count_outbox_emails(state: &TiraState, status: Option<String>) -> Result<i64> {
+    check_outbox_status(&status)?;
+    dao::emails::count_emails(state, status).await
+}
+
+/// Service function for retrieving the emails in the outbox.
+pub async fn get_outbox_emails(
+    state: &TiraState,
+    status: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Vec<OutboxEmail>> {
+    check_outbox_status(&status)?;
+
+    let limit = limit.unwrap_or(10);
+    let offset = offset.unwrap_or(0);
+    if limit < 0 || offset < 0 {
+        return Err(
+            ServiceError::BadRequest("Limit and offset cannot be negative".to_string()).into(),
+        );
+    }
+
+    dao::emails::get_emails(state, status, limit, offset).await
+}
+
+/// Service function for giving a dead-lettered email another round of attempts.
+pub async fn retry_outbox_email_by_id(state: &TiraState, id: i64) -> Result<()> {
+    let emails_retried =
+        dao::emails::retry_email_by_id(state, id, DEAD_STATUS, PENDING_STATUS).await?;
+    service::check_only_one_row_changed(emails_retried)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::email_transport::MemoryEmailTransport;
+    use sqlx::PgPool;
+
+    fn templates() -> EmailTemplates {
+        EmailTemplates::load("http://localhost:3000/tickets", None).unwrap()
+    }
+
+    fn user() -> User {
+        User {
+            id: 1,
+            username: "user1".to_string(),
+            password: String::new(),
+            email_address: None,
+            first_name: Some("Tom <b>&</b> Jerry".to_string()),
+            last_name: None,
+            profile_picture_url: None,
+            created: Default::default(),
+            archived: false,
+            password_scheme: String::new(),
+            role: String::new(),
+        }
+    }
+
+    fn ticket() -> Ticket {
+        Ticket {
+            id: 7,
+            subject: "<img src=x onerror=alert(1)> & more".to_string(),
+            description: Some("<p>Fish &amp; chips<script>alert(1)</script></p>".to_string()),
+            description_source: None,
+            content_format: "html".to_string(),
+            category_id: None,
+            priority: "Low".to_string(),
+            status: "Backlog".to_string(),
+            created: Default::default(),
+            reporter_id: 1,
+        }
+    }
+
+    #[test]
+    fn markup_in_subjects_and_names_is_escaped_in_html_only() {
+        let body = create_ticket_creation_email_body(&templates(), &user(), &ticket()).unwrap();
+
+        assert!(body
+            .html
+            .contains("Tom &lt;b&gt;&amp;&lt;&#x2F;b&gt; Jerry"));
+        assert!(body
+            .html
+            .contains("&lt;img src=x onerror=alert(1)&gt; &amp; more"));
+        assert!(!body.html.contains("<img"));
+        assert!(body.html.contains("<p>Fish &amp; chips</p>"));
+        assert!(!body.html.contains("script"));
+
+        assert!(body.text.contains("Tom <b>&</b> Jerry"));
+        assert!(body.text.contains("<img src=x onerror=alert(1)> & more"));
+        assert!(body.text.contains("Fish & chips"));
+        assert!(!body.text.contains("&amp;"));
+        assert!(!body.text.contains("script"));
+    }
+
+    #[test]
+    fn comments_are_sanitized_in_html_and_decoded_in_text() {
+        let comment =
+            "<p onclick=\"alert(1)\">1 &lt; 2 &amp;&amp; <b>bold</b></p><script>x</script>";
+        let body = create_comment_email_body(&templates(), &user(), comment, &ticket()).unwrap();
+
+        assert!(body.html.contains("<p>1 &lt; 2 &amp;&amp; <b>bold</b></p>"));
+        assert!(!body.html.contains("onclick"));
+        assert!(!body.html.contains("<script>"));
+
+        assert!(body.text.contains("1 < 2 && bold"));
+        assert!(!body.text.contains("&lt;"));
+    }
+
+    fn retry_config() -> RetryConfig {
+        RetryConfig {
+            max_attempts: 3,
+            retry_base: Duration::from_secs(1),
+            retry_max: Duration::from_secs(60),
+            poll_interval: Duration::from_secs(1),
+            lease: Duration::from_secs(60),
+        }
+    }
+
+    /// Adds an email to the outbox that is due and was claimed `attempts` times before.
+    async fn create_due_email(pool: &PgPool, recipient: &str, attempts: i32) -> i64 {
+        sqlx::query_scalar(
+            "INSERT INTO email_outbox (recipient, subject, body, attempts) VALUES ($1, 'Subject', '<p>Body</p>', $2) RETURNING id",
         )
-        .to(e.to.parse().unwrap())
-        .subject(e.subject)
-        .singlepart(SinglePart::html(e.body))
-        .unwrap();
-
-    let creds = Credentials::new(
-        env::var("TIRA_EMAIL_USERNAME").unwrap(),
-        env::var("TIRA_EMAIL_PASSWORD").unwrap(),
-    );
-
-    // Open a remote connection to gmail
-    let mail_domain = format!(
-        "{}.{}",
-        env::var("TIRA_EMAIL_SUBDOMAIN").unwrap(),
-        env::var("TIRA_EMAIL_DOMAIN").unwrap()
-    );
-    let mailer = SmtpTransport::starttls_relay(&mail_domain)
+        .bind(recipient)
+        .bind(attempts)
+        .fetch_one(pool)
+        .await
         .unwrap()
-        .port(env::var("TIRA_EMAIL_SMTP_PORT").unwrap().parse().unwrap())
-        .credentials(creds)
-        .build();
-
-    // Send the email
-    match mailer.send(&email) {
-        Ok(_) => info!("Email sent successfully!"),
-        Err(e) => panic!("Could not send email: {:?}", e),
+    }
+
+    #[sqlx::test]
+    async fn emails_whose_attempts_never_finish_are_dead_lettered(pool: PgPool) {
+        let stuck_id = create_due_email(&pool, "stuck@example.com", 3).await;
+        let fresh_id = create_due_email(&pool, "fresh@example.com", 0).await;
+        let state = TiraState::for_tests(pool.clone());
+        let transport = Arc::new(MemoryEmailTransport::default());
+        let sender = Arc::new(EmailSender::new(
+            "tira@example.com".parse().unwrap(),
+            transport.clone(),
+        ));
+
+        assert_eq!(
+            send_due_emails(&state, &sender, &retry_config())
+                .await
+                .unwrap(),
+            2
+        );
+
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].envelope().to()[0].to_string(), "fresh@example.com");
+
+        let outbox_email = |id: i64| {
+            sqlx::query_as::<_, (String, i32)>(
+                "SELECT status, attempts FROM email_outbox WHERE id = $1",
+            )
+            .bind(id)
+            .fetch_one(&pool)
+        };
+        assert_eq!(
+            outbox_email(stuck_id).await.unwrap(),
+            (DEAD_STATUS.to_string(), 4)
+        );
+        assert_eq!(
+            outbox_email(fresh_id).await.unwrap(),
+            (SENT_STATUS.to_string(), 1)
+        );
     }
 }
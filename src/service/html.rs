@@ -0,0 +1,288 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use serde::Deserialize;
+
+use crate::{
+    dao::{self, html::StoredHtml},
+    service::{tickets::strip_html_tags, ServiceError},
+    TiraState,
+};
+
+/// Format of descriptions and comments written in the editor's HTML.
+pub const HTML_FORMAT: &str = "html";
+
+/// Format of descriptions and comments written in Markdown.
+pub const MARKDOWN_FORMAT: &str = "markdown";
+
+pub const CONTENT_FORMATS: [&str; 2] = [HTML_FORMAT, MARKDOWN_FORMAT];
+
+/// What links in user-written HTML get as `rel`, so that the linked pages can neither reach back
+/// into Tira nor gain from being linked.
+const LINK_REL: &str = "noopener nofollow";
+
+/// How many rows are re-sanitized per query.
+const RESANITIZE_BATCH_SIZE: i64 = 500;
+
+/// Which HTML descriptions and comments may contain, everything else is removed.
+///
+/// Every field left out of a policy file keeps its default, which is what ammonia allows by
+/// default.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HtmlPolicy {
+    /// Tags that are kept, the content of other tags is kept without the tag.
+    pub tags: HashSet<String>,
+    /// Attributes that are kept on every tag.
+    pub generic_attributes: HashSet<String>,
+    /// Attributes that are kept on a tag, by tag.
+    pub tag_attributes: HashMap<String, HashSet<String>>,
+    /// Schemes that links and images may use.
+    pub url_schemes: HashSet<String>,
+}
+
+impl Default for HtmlPolicy {
+    fn default() -> Self {
+        let builder = ammonia::Builder::default();
+        let to_strings = |set: HashSet<&str>| set.into_iter().map(str::to_string).collect();
+        Self {
+            tags: to_strings(builder.clone_tags()),
+            generic_attributes: to_strings(builder.clone_generic_attributes()),
+            tag_attributes: builder
+                .clone_tag_attributes()
+                .into_iter()
+                .map(|(tag, attributes)| (tag.to_string(), to_strings(attributes)))
+                .collect(),
+            url_schemes: to_strings(builder.clone_url_schemes()),
+        }
+    }
+}
+
+/// Checks that a content format is one of the known formats.
+pub fn check_content_format(format: &str) -> Result<()> {
+    if CONTENT_FORMATS.contains(&format) {
+        Ok(())
+    } else {
+        let message = format!(
+            "Content format must be '{}' or '{}'",
+            HTML_FORMAT, MARKDOWN_FORMAT
+        );
+        Err(ServiceError::invalid_field("content_format", &message).into())
+    }
+}
+
+fn markdown_to_html(markdown: &str) -> String {
+    let options = pulldown_cmark::Options::ENABLE_TABLES
+        | pulldown_cmark::Options::ENABLE_STRIKETHROUGH
+        | pulldown_cmark::Options::ENABLE_TASKLISTS;
+    let parser = pulldown_cmark::Parser::new_ext(markdown, options);
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+    html
+}
+
+fn borrow_all(set: &HashSet<String>) -> HashSet<&str> {
+    set.iter().map(String::as_str).collect()
+}
+
+/// Cleans user-written HTML before it is stored, so that it is safe to show in browsers and
+/// emails.
+pub struct HtmlSanitizer {
+    policy: HtmlPolicy,
+}
+
+impl HtmlSanitizer {
+    /// Sets up the sanitizer with the policy in the JSON file at `policy_file`, or the default
+    /// policy.
+    pub fn load(policy_file: Option<&Path>) -> Result<Self> {
+        let policy = match policy_file {
+            Some(policy_file) => {
+                info!("loading HTML policy from {}", policy_file.display());
+                let json = fs::read_to_string(policy_file).with_context(|| {
+                    format!("Could not read HTML policy {}", policy_file.display())
+                })?;
+                serde_json::from_str(&json)
+                    .with_context(|| format!("Invalid HTML policy {}", policy_file.display()))?
+            }
+            None => HtmlPolicy::default(),
+        };
+
+        // Ammonia panics when cleaning with a builder that breaks one of its rules, so the rules the
+        // policy could break are checked up front. The rel of links is always replaced, ammonia
+        // refuses to keep it as well
+        let allows_rel = policy.generic_attributes.contains("rel")
+            || policy
+                .tag_attributes
+                .values()
+                .any(|attributes| attributes.contains("rel"));
+        if allows_rel {
+            return Err(anyhow!(
+                "The HTML policy cannot allow the rel attribute, it is set on every link"
+            ));
+        }
+
+        // Tags like script and style are removed with their content, ammonia refuses to keep them
+        // as well
+        let removed_tags = ammonia::Builder::default().clone_clean_content_tags();
+        if let Some(tag) = removed_tags
+            .iter()
+            .find(|tag| policy.tags.contains(**tag) || policy.tag_attributes.contains_key(**tag))
+        {
+            return Err(anyhow!(
+                "The HTML policy cannot allow the {} tag, it is always removed with its content",
+                tag
+            ));
+        }
+
+        Ok(Self { policy })
+    }
+
+    /// Returns an ammonia builder for the policy, which only borrows it.
+    fn builder(&self) -> ammonia::Builder<'_> {
+        let mut builder = ammonia::Builder::default();
+        builder
+            .tags(borrow_all(&self.policy.tags))
+            .generic_attributes(borrow_all(&self.policy.generic_attributes))
+            .tag_attributes(
+                self.policy
+                    .tag_attributes
+                    .iter()
+                    .map(|(tag, attributes)| (tag.as_str(), borrow_all(attributes)))
+                    .collect(),
+            )
+            .url_schemes(borrow_all(&self.policy.url_schemes))
+            .link_rel(Some(LINK_REL));
+        builder
+    }
+
+    /// Turns a description or comment into the sanitized HTML it is stored and shown as.
+    ///
+    /// For HTML the source is sanitized as well, so that it never holds what was removed.
+    /// Returns the source to store and the HTML.
+    pub fn render(&self, format: &str, source: &str) -> (String, String) {
+        if format == MARKDOWN_FORMAT {
+            (source.to_string(), self.sanitize(&markdown_to_html(source)))
+        } else {
+            let html = self.sanitize(source);
+            (html.clone(), html)
+        }
+    }
+
+    /// Removes everything from user-written HTML that the policy does not allow.
+    pub fn sanitize(&self, html: &str) -> String {
+        self.builder().clean(html).to_string()
+    }
+}
+
+/// Renders every ticket description, comment and comment revision stored so far again with the
+/// current policy.
+///
+/// Descriptions and comments that change are reindexed for searching. Returns how many rows
+/// changed.
+pub async fn resanitize_stored_html(state: &TiraState) -> Result<u64> {
+    let mut changed = 0;
+    for column in StoredHtml::ALL {
+        let mut after_id = 0;
+        loop {
+            let rows = dao::html::get_html_after_id(state, column, after_id, RESANITIZE_BATCH_SIZE)
+                .await?;
+            let Some(last) = rows.last() else { break };
+            after_id = last.id;
+
+            for row in rows {
+                let (source, html) = state.html_sanitizer.render(&row.format, &row.source);
+                if source != row.source || html != row.html {
+                    let text = strip_html_tags(&html);
+                    dao::html::update_html(state, column, row.id, &source, &html, &text).await?;
+                    changed += 1;
+                }
+            }
+        }
+        info!("re-sanitized {:?}", column);
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_policy(json: &str) -> Result<HtmlSanitizer> {
+        let policy_file =
+            std::env::temp_dir().join(format!("tira-policy-{}.json", uuid::Uuid::new_v4()));
+        fs::write(&policy_file, json).unwrap();
+        let sanitizer = HtmlSanitizer::load(Some(&policy_file));
+        fs::remove_file(&policy_file).unwrap();
+        sanitizer
+    }
+
+    #[test]
+    fn scripts_and_styles_are_removed_with_their_content() {
+        let sanitizer = HtmlSanitizer::load(None).unwrap();
+        assert_eq!(
+            sanitizer.sanitize(
+                "<p onclick=\"alert(1)\">hi</p><script>alert(1)</script><style>p { }</style>"
+            ),
+            "<p>hi</p>"
+        );
+    }
+
+    #[test]
+    fn links_get_rel() {
+        let sanitizer = HtmlSanitizer::load(None).unwrap();
+        assert_eq!(
+            sanitizer.sanitize(r#"<a href="https://example.com" rel="opener">site</a>"#),
+            r#"<a href="https://example.com" rel="noopener nofollow">site</a>"#
+        );
+        assert_eq!(
+            sanitizer.sanitize(r#"<a href="javascript:alert(1)">site</a>"#),
+            "<a rel=\"noopener nofollow\">site</a>"
+        );
+    }
+
+    #[test]
+    fn policies_cannot_allow_rel_or_removed_tags() {
+        for json in [
+            r#"{"generic_attributes": ["rel"]}"#,
+            r#"{"tag_attributes": {"a": ["href", "rel"]}}"#,
+            r#"{"tags": ["p", "script"]}"#,
+            r#"{"tag_attributes": {"style": ["media"]}}"#,
+        ] {
+            assert!(load_policy(json).is_err(), "{} should be rejected", json);
+        }
+    }
+
+    #[test]
+    fn policies_limit_what_is_kept() {
+        let sanitizer = load_policy(r#"{"tags": ["p"], "tag_attributes": {}}"#).unwrap();
+        assert_eq!(
+            sanitizer.sanitize("<p><b>bold</b> <img src=\"x.png\"></p>"),
+            "<p>bold </p>"
+        );
+    }
+
+    #[test]
+    fn markdown_is_rendered_through_the_sanitizer() {
+        let sanitizer = HtmlSanitizer::load(None).unwrap();
+        let source = "**hi** <script>alert(1)</script> [site](https://example.com)";
+        let (stored_source, html) = sanitizer.render(MARKDOWN_FORMAT, source);
+        assert_eq!(stored_source, source);
+        assert_eq!(
+            html,
+            "<p><strong>hi</strong>  \
+             <a href=\"https://example.com\" rel=\"noopener nofollow\">site</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn html_source_is_sanitized_too() {
+        let sanitizer = HtmlSanitizer::load(None).unwrap();
+        let (source, html) = sanitizer.render(HTML_FORMAT, "<p>hi<script>alert(1)</script></p>");
+        assert_eq!(source, "<p>hi</p>");
+        assert_eq!(html, "<p>hi</p>");
+    }
+}
@@ -0,0 +1,435 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use mail_parser::{HeaderName, HeaderValue, Message, MessageParser};
+use regex::Regex;
+use sha2::Sha256;
+
+use crate::{
+    dao,
+    service::{permissions, tickets, ServiceError},
+    TiraState,
+};
+
+/// How many bytes of the HMAC end up in a reply address.
+const SIGNATURE_BYTES: usize = 12;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Matches the line mail clients put above the quoted email, like
+/// `On Mon, 3 Mar 2025 at 10:00, Tira <reply@example.com> wrote:`, which can be wrapped.
+static QUOTE_HEADER_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^On\s.*\swrote:$").unwrap());
+
+/// Matches the separators Outlook puts above the quoted email.
+static OUTLOOK_SEPARATOR_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(-{3,}\s*Original Message\s*-{3,}|_{10,})$").unwrap());
+
+/// Creates and checks the signed reply-to addresses of notification emails.
+///
+/// A reply address looks like `<local part>+<ticket id>-<user id>-<day>-<signature>@<domain>`,
+/// so that a reply can be added as a comment by the right user on the right ticket. The day it
+/// was created on, counted from the Unix epoch, lets addresses expire.
+pub struct ReplyAddresses {
+    local_part: String,
+    domain: String,
+    secret: Vec<u8>,
+    max_age_days: i64,
+}
+
+/// Returns the current day, counted from the Unix epoch.
+fn today() -> i64 {
+    Utc::now().timestamp().div_euclid(SECONDS_PER_DAY)
+}
+
+impl ReplyAddresses {
+    /// Sets up reply addresses based on `address`, signed with `secret`, that can be replied to
+    /// for `max_age_days` days.
+    pub fn new(address: &str, secret: &str, max_age_days: i64) -> Result<Self> {
+        let (local_part, domain) = address
+            .rsplit_once('@')
+            .filter(|(local_part, domain)| {
+                !local_part.is_empty() && !local_part.contains('+') && !domain.is_empty()
+            })
+            .ok_or_else(|| anyhow!("Invalid reply address '{}'", address))?;
+
+        if secret.len() < 16 {
+            return Err(anyhow!(
+                "The reply address secret has to be at least 16 characters long"
+            ));
+        }
+
+        Ok(Self {
+            local_part: local_part.to_string(),
+            domain: domain.to_string(),
+            secret: secret.as_bytes().to_vec(),
+            max_age_days,
+        })
+    }
+
+    fn mac(&self, ticket_id: i64, user_id: i64, day: i64) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC takes keys of any length");
+        mac.update(format!("{}-{}-{}", ticket_id, user_id, day).as_bytes());
+        mac
+    }
+
+    /// Returns the address a user replies to for commenting on a ticket.
+    pub fn create(&self, ticket_id: i64, user_id: i64) -> String {
+        self.create_on(ticket_id, user_id, today())
+    }
+
+    fn create_on(&self, ticket_id: i64, user_id: i64, day: i64) -> String {
+        let signature = self.mac(ticket_id, user_id, day).finalize().into_bytes();
+        format!(
+            "{}+{}-{}-{}-{}@{}",
+            self.local_part,
+            ticket_id,
+            user_id,
+            day,
+            hex::encode(&signature[..SIGNATURE_BYTES]),
+            self.domain
+        )
+    }
+
+    /// Returns the ticket id and user id of a reply address, if it has a valid signature and has
+    /// not expired.
+    pub fn verify(&self, address: &str) -> Option<(i64, i64)> {
+        self.verify_on(address, today())
+    }
+
+    fn verify_on(&self, address: &str, today: i64) -> Option<(i64, i64)> {
+        let (local_part, domain) = address.rsplit_once('@')?;
+        let (base, token) = local_part.split_once('+')?;
+        if !base.eq_ignore_ascii_case(&self.local_part)
+            || !domain.eq_ignore_ascii_case(&self.domain)
+        {
+            return None;
+        }
+
+        let mut parts = token.splitn(4, '-');
+        let ticket_id = parts.next()?.parse().ok()?;
+        let user_id = parts.next()?.parse().ok()?;
+        let day: i64 = parts.next()?.parse().ok()?;
+        let signature = hex::decode(parts.next()?).ok()?;
+        if signature.len() != SIGNATURE_BYTES {
+            return None;
+        }
+
+        self.mac(ticket_id, user_id, day)
+            .verify_truncated_left(&signature)
+            .ok()?;
+        if !(0..=self.max_age_days).contains(&(today - day)) {
+            return None;
+        }
+        Some((ticket_id, user_id))
+    }
+}
+
+/// Returns every address a message was sent to, including the envelope recipient the MTA
+/// records in `Delivered-To`.
+fn get_recipient_addresses(message: &Message) -> Vec<String> {
+    let mut addresses: Vec<String> = [message.to(), message.cc()]
+        .into_iter()
+        .flatten()
+        .flat_map(|address| address.iter())
+        .filter_map(|addr| addr.address())
+        .map(str::to_string)
+        .collect();
+
+    for value in message.header_values(HeaderName::DeliveredTo) {
+        match value {
+            HeaderValue::Text(text) => addresses.push(text.trim().to_string()),
+            HeaderValue::Address(address) => addresses.extend(
+                address
+                    .iter()
+                    .filter_map(|addr| addr.address())
+                    .map(str::to_string),
+            ),
+            _ => (),
+        }
+    }
+
+    addresses
+}
+
+/// Cuts a reply down to what the sender wrote, leaving out the quoted email and their signature.
+fn strip_reply(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+
+    let mut end = lines.len();
+    for (i, line) in lines.iter().enumerate() {
+        let next_line = lines.get(i + 1).copied().unwrap_or_default();
+        let is_quote_header = QUOTE_HEADER_REGEX.is_match(line)
+            || (line.starts_with("On ")
+                && QUOTE_HEADER_REGEX.is_match(&format!("{} {}", line, next_line)));
+
+        if *line == "--"
+            || line.starts_with('>')
+            || is_quote_header
+            || OUTLOOK_SEPARATOR_REGEX.is_match(line.trim())
+        {
+            end = i;
+            break;
+        }
+    }
+
+    lines[..end].join("\n").trim().to_string()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Turns a plain-text reply into the HTML comments are stored as, with a paragraph for every
+/// block of lines.
+fn text_to_html(text: &str) -> String {
+    text.split("\n\n")
+        .map(str::trim)
+        .filter(|paragraph| !paragraph.is_empty())
+        .map(|paragraph| {
+            let lines: Vec<_> = paragraph.lines().map(escape_html).collect();
+            format!("<p>{}</p>", lines.join("<br>"))
+        })
+        .collect()
+}
+
+/// Service function for adding a reply to a notification email as a comment.
+///
+/// `raw` is the whole RFC 822 message. The reply has to be sent to a validly signed reply
+/// address, from the email address of the user that address belongs to. Returns the id of the
+/// new comment.
+pub async fn receive_email(state: &TiraState, raw: &[u8]) -> Result<i64> {
+    let reply_addresses = state
+        .reply_addresses
+        .as_ref()
+        .ok_or_else(|| ServiceError::NotFound("Reply-by-email is not enabled".to_string()))?;
+
+    let message = MessageParser::default()
+        .parse(raw)
+        .ok_or_else(|| ServiceError::BadRequest("Could not parse email".to_string()))?;
+
+    let (ticket_id, user_id) = get_recipient_addresses(&message)
+        .iter()
+        .find_map(|address| reply_addresses.verify(address))
+        .ok_or_else(|| {
+            ServiceError::Forbidden("Email was not sent to a valid reply address".to_string())
+        })?;
+
+    let user = dao::users::get_user_by_id(state, user_id).await?;
+    let sender = message
+        .from()
+        .and_then(|from| from.first())
+        .and_then(|from| from.address());
+    match (&user.email_address, sender) {
+        (Some(email_address), Some(sender)) if email_address.eq_ignore_ascii_case(sender) => (),
+        _ => {
+            return Err(ServiceError::Forbidden(
+                "Sender does not match the reply address".to_string(),
+            )
+            .into())
+        }
+    }
+    if user.archived {
+        return Err(ServiceError::Forbidden("User is archived".to_string()).into());
+    }
+    permissions::require_write(&user)?;
+
+    let text = message.body_text(0).unwrap_or_default();
+    let content = text_to_html(&strip_reply(&text));
+    tickets::create_comment_by_ticket_id_and_commenter_id(
+        state, &content, None, ticket_id, user_id, None,
+    )
+    .await
+}
+
+/// Moves a message from `new` to `cur` in a maildir, marking it as seen.
+fn mark_seen(maildir: &Path, path: &Path) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid maildir entry {}", path.display()))?;
+    let mut seen = file_name.to_os_string();
+    seen.push(":2,S");
+    fs::rename(path, maildir.join("cur").join(seen))?;
+    Ok(())
+}
+
+/// Whether an error means that a message can never be added as a comment, unlike problems with
+/// the database that might go away.
+fn is_permanent(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<ServiceError>().is_some()
+        || matches!(
+            error.downcast_ref::<sqlx::Error>(),
+            Some(sqlx::Error::RowNotFound)
+        )
+}
+
+/// Adds the replies delivered to a maildir as comments until the program ends.
+pub async fn run_maildir_poller(state: TiraState, maildir: PathBuf, poll_interval: Duration) {
+    info!("Starting maildir poller for {}", maildir.display());
+    loop {
+        if let Err(e) = receive_maildir_emails(&state, &maildir).await {
+            error!("Could not read maildir {}: {:#}", maildir.display(), e);
+        }
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+/// Adds the new messages in a maildir as comments.
+///
+/// Messages that can never become comments are marked as seen along with the ones that did.
+/// The others stay new and are tried again next time.
+async fn receive_maildir_emails(state: &TiraState, maildir: &Path) -> Result<()> {
+    for entry in fs::read_dir(maildir.join("new"))? {
+        let path = entry?.path();
+        let raw = fs::read(&path)?;
+
+        match receive_email(state, &raw).await {
+            Ok(comment_id) => {
+                info!("Added {} as comment {}", path.display(), comment_id);
+                mark_seen(maildir, &path)?;
+            }
+            Err(e) if is_permanent(&e) => {
+                warn!("Ignoring {}: {:#}", path.display(), e);
+                mark_seen(maildir, &path)?;
+            }
+            Err(e) => warn!("Could not add {} as comment: {:#}", path.display(), e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 20_000;
+
+    fn reply_addresses() -> ReplyAddresses {
+        ReplyAddresses::new("tira@example.com", "0123456789abcdef", 30).unwrap()
+    }
+
+    #[test]
+    fn verify_accepts_created_addresses() {
+        let reply_addresses = reply_addresses();
+        let address = reply_addresses.create_on(12, 34, DAY);
+        assert!(address.starts_with("tira+12-34-20000-"));
+        assert!(address.ends_with("@example.com"));
+        assert_eq!(reply_addresses.verify_on(&address, DAY), Some((12, 34)));
+        assert_eq!(
+            reply_addresses.verify_on(&address, DAY + 30),
+            Some((12, 34))
+        );
+    }
+
+    #[test]
+    fn verify_ignores_case() {
+        let reply_addresses = reply_addresses();
+        let address = reply_addresses.create_on(12, 34, DAY).to_uppercase();
+        assert_eq!(reply_addresses.verify_on(&address, DAY), Some((12, 34)));
+    }
+
+    #[test]
+    fn verify_rejects_tampered_addresses() {
+        let reply_addresses = reply_addresses();
+        let address = reply_addresses.create_on(12, 34, DAY);
+
+        let other_ticket = address.replacen("+12-", "+13-", 1);
+        assert_eq!(reply_addresses.verify_on(&other_ticket, DAY), None);
+        let other_user = address.replacen("-34-", "-35-", 1);
+        assert_eq!(reply_addresses.verify_on(&other_user, DAY), None);
+        let other_day = address.replacen("-20000-", "-20001-", 1);
+        assert_eq!(reply_addresses.verify_on(&other_day, DAY + 1), None);
+
+        let (local_part, domain) = address.rsplit_once('@').unwrap();
+        let last = local_part.chars().last().unwrap();
+        let flipped = if last == '0' { '1' } else { '0' };
+        let tampered = format!(
+            "{}{}@{}",
+            &local_part[..local_part.len() - 1],
+            flipped,
+            domain
+        );
+        assert_eq!(reply_addresses.verify_on(&tampered, DAY), None);
+
+        let other_secret = ReplyAddresses::new("tira@example.com", "fedcba9876543210", 30).unwrap();
+        assert_eq!(other_secret.verify_on(&address, DAY), None);
+    }
+
+    #[test]
+    fn verify_rejects_truncated_signatures() {
+        let reply_addresses = reply_addresses();
+        let address = reply_addresses.create_on(12, 34, DAY);
+        let (local_part, domain) = address.rsplit_once('@').unwrap();
+
+        for cut in [1, 2, 8] {
+            let truncated = format!("{}@{}", &local_part[..local_part.len() - cut], domain);
+            assert_eq!(reply_addresses.verify_on(&truncated, DAY), None);
+        }
+        let unsigned = format!("{}@{}", local_part.rsplit_once('-').unwrap().0, domain);
+        assert_eq!(reply_addresses.verify_on(&unsigned, DAY), None);
+    }
+
+    #[test]
+    fn verify_rejects_other_addresses() {
+        let reply_addresses = reply_addresses();
+        let address = reply_addresses.create_on(12, 34, DAY);
+        let other_base = address.replacen("tira+", "other+", 1);
+        assert_eq!(reply_addresses.verify_on(&other_base, DAY), None);
+        let other_domain = address.replacen("@example.com", "@example.org", 1);
+        assert_eq!(reply_addresses.verify_on(&other_domain, DAY), None);
+        assert_eq!(reply_addresses.verify_on("tira@example.com", DAY), None);
+    }
+
+    #[test]
+    fn verify_rejects_expired_addresses() {
+        let reply_addresses = reply_addresses();
+        let address = reply_addresses.create_on(12, 34, DAY);
+        assert_eq!(reply_addresses.verify_on(&address, DAY + 31), None);
+        assert_eq!(reply_addresses.verify_on(&address, DAY - 1), None);
+    }
+
+    #[test]
+    fn strip_reply_cuts_quoted_email() {
+        let reply =
+            "Sounds good.\n\nOn Mon, 3 Mar 2025 at 10:00, Tira <reply@example.com> wrote:\n> Hello";
+        assert_eq!(strip_reply(reply), "Sounds good.");
+    }
+
+    #[test]
+    fn strip_reply_cuts_wrapped_quote_header() {
+        let reply =
+            "Sounds good.\nOn Mon, 3 Mar 2025 at 10:00, Tira\n<reply@example.com> wrote:\n> Hello";
+        assert_eq!(strip_reply(reply), "Sounds good.");
+    }
+
+    #[test]
+    fn strip_reply_keeps_lines_starting_with_on() {
+        let reply = "On second thought,\nlet's wait.";
+        assert_eq!(strip_reply(reply), reply);
+    }
+
+    #[test]
+    fn strip_reply_cuts_outlook_separators() {
+        let original_message = "Done.\r\n\r\n-----Original Message-----\r\nFrom: Tira";
+        assert_eq!(strip_reply(original_message), "Done.");
+        let underscores = "Done.\n\n________________________________\nFrom: Tira";
+        assert_eq!(strip_reply(underscores), "Done.");
+    }
+
+    #[test]
+    fn strip_reply_cuts_signature_and_quoted_lines() {
+        assert_eq!(strip_reply("Done.\n-- \nJane"), "Done.");
+        assert_eq!(strip_reply("Done.\n> Hello\n> there"), "Done.");
+    }
+}
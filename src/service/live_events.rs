@@ -0,0 +1,148 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use log::{error, info, warn};
+use sqlx::{postgres::PgListener, PgPool};
+use tokio::sync::broadcast;
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    Stream, StreamExt,
+};
+
+use crate::{
+    dao,
+    models::{LiveEvent, LiveEventFilter, Ticket},
+    TiraState,
+};
+
+pub const TICKET_CREATED_EVENT: &str = "ticket.created";
+
+pub const TICKET_UPDATED_EVENT: &str = "ticket.updated";
+
+pub const COMMENT_ADDED_EVENT: &str = "comment.added";
+
+/// Event for a user being assigned to or unassigned from a ticket.
+pub const ASSIGNMENT_CHANGED_EVENT: &str = "assignment.changed";
+
+/// Postgres channel that carries the events between the replicas of the backend.
+const CHANNEL: &str = "tira_events";
+
+/// How many events a slow client can fall behind before it misses some.
+const HUB_CAPACITY: usize = 256;
+
+/// Fans live events out to every client following `/events` on this replica.
+#[derive(Clone)]
+pub struct EventHub {
+    sender: broadcast::Sender<LiveEvent>,
+    /// Whether events go through Postgres, so that the clients of other replicas get them too.
+    distributed: Arc<AtomicBool>,
+}
+
+impl EventHub {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(HUB_CAPACITY);
+        Self {
+            sender,
+            distributed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn send(&self, event: LiveEvent) {
+        // Nobody listening is not an error
+        let _ = self.sender.send(event);
+    }
+}
+
+/// Starts listening for the events of every replica and feeds them to `hub`.
+///
+/// Databases without LISTEN/NOTIFY, like CockroachDB, leave the hub with only the events of this
+/// replica.
+pub async fn start_event_listener(pool: &PgPool, hub: &EventHub) {
+    let listener = async {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen(CHANNEL).await?;
+        Ok::<_, sqlx::Error>(listener)
+    };
+
+    match listener.await {
+        Ok(listener) => {
+            hub.distributed.store(true, Ordering::SeqCst);
+            tokio::spawn(run_event_listener(listener, hub.clone()));
+        }
+        Err(e) => warn!(
+            "Could not listen for events from other replicas, only sending events of this one: {}",
+            e
+        ),
+    }
+}
+
+/// Passes the events from Postgres on to the hub until the program ends.
+async fn run_event_listener(mut listener: PgListener, hub: EventHub) {
+    info!("Starting event listener");
+    loop {
+        // The listener reconnects by itself, missing the events sent meanwhile
+        match listener.recv().await {
+            Ok(notification) => match serde_json::from_str(notification.payload()) {
+                Ok(event) => hub.send(event),
+                Err(e) => error!("Invalid event '{}': {}", notification.payload(), e),
+            },
+            Err(e) => {
+                error!("Could not receive events: {}", e);
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// Returns an event about a ticket, without a comment or assignee.
+pub fn ticket_event(event: &str, actor_id: i64, ticket: &Ticket) -> LiveEvent {
+    LiveEvent {
+        event: event.to_string(),
+        ticket_id: ticket.id,
+        category_id: ticket.category_id,
+        actor_id,
+        comment_id: None,
+        assignee_id: None,
+    }
+}
+
+/// Service function for sending an event to the clients following `/events` on every replica.
+///
+/// Live events are best effort, so an event that cannot be sent is logged and dropped instead of
+/// failing the change it is about.
+pub async fn publish(state: &TiraState, event: LiveEvent) {
+    if state.events.distributed.load(Ordering::SeqCst) {
+        let result = match serde_json::to_string(&event) {
+            Ok(payload) => dao::live_events::notify(state, CHANNEL, &payload).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            error!("Could not publish the {} event: {:#}", event.event, e);
+        }
+    } else {
+        state.events.send(event);
+    }
+}
+
+fn matches(filter: &LiveEventFilter, event: &LiveEvent) -> bool {
+    filter
+        .ticket_id
+        .is_none_or(|ticket_id| ticket_id == event.ticket_id)
+        && filter
+            .category_id
+            .is_none_or(|category_id| Some(category_id) == event.category_id)
+}
+
+/// Service function for following the live events that match a filter.
+pub fn subscribe(state: &TiraState, filter: LiveEventFilter) -> impl Stream<Item = LiveEvent> {
+    BroadcastStream::new(state.events.sender.subscribe()).filter_map(move |event| match event {
+        Ok(event) if matches(&filter, &event) => Some(event),
+        Ok(_) => None,
+        Err(BroadcastStreamRecvError::Lagged(missed)) => {
+            warn!("Client fell behind and missed {} events", missed);
+            None
+        }
+    })
+}
@@ -1,26 +1,113 @@
-use anyhow::{anyhow, Result};
-use std::cmp::Ordering;
+use crate::models::error::FieldError;
+use anyhow::Result;
+use std::{cmp::Ordering, fmt, time::Duration};
 pub mod assignments;
+pub mod attachments;
+pub mod blob_store;
 pub mod categories;
 pub mod comments;
+pub mod digests;
+pub mod email_templates;
+pub mod email_transport;
 pub mod emails;
+pub mod html;
 pub mod images;
+pub mod inbound_emails;
+pub mod live_events;
+pub mod notifications;
+pub mod permissions;
+pub mod search;
 pub mod security;
 pub mod sessions;
 pub mod tickets;
 pub mod users;
+pub mod webhooks;
+pub mod workflows;
+
+/// Settings for a background worker that retries what it failed to send.
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// Attempts after which something is dead-lettered.
+    pub max_attempts: i32,
+    /// Delay before the first retry, which doubles with every failed attempt.
+    pub retry_base: Duration,
+    /// Longest delay between two attempts.
+    pub retry_max: Duration,
+    /// How long to wait before looking for more work when there is none.
+    pub poll_interval: Duration,
+    /// How long claimed work is hidden from the other workers. Work that was never marked done
+    /// or failed by then, because a worker stopped, is picked up again.
+    pub lease: Duration,
+}
+
+impl RetryConfig {
+    /// Returns how long to wait before retrying something that failed `attempts` times.
+    pub fn retry_delay(&self, attempts: i32) -> Duration {
+        let exponent = attempts.saturating_sub(1).clamp(0, 31) as u32;
+        self.retry_base
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.retry_max)
+    }
+}
+
+/// Errors caused by the request rather than by the server.
+///
+/// Anything else that goes wrong in the service layer is an internal error.
+#[derive(Debug)]
+pub enum ServiceError {
+    /// The request is malformed, like sorting on an unknown column.
+    BadRequest(String),
+    /// The user could not be authenticated.
+    Unauthorized(String),
+    /// The user is authenticated but not allowed to do this.
+    Forbidden(String),
+    /// The resource doesn't exist.
+    NotFound(String),
+    /// The request conflicts with the current state of a resource.
+    Conflict(String),
+    /// One or more fields of the request are invalid.
+    Validation(Vec<FieldError>),
+}
+
+impl ServiceError {
+    /// Shorthand for a validation error on a single field.
+    pub fn invalid_field(field: &str, message: &str) -> Self {
+        Self::Validation(vec![FieldError::new(field, message)])
+    }
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadRequest(message)
+            | Self::Unauthorized(message)
+            | Self::Forbidden(message)
+            | Self::NotFound(message)
+            | Self::Conflict(message) => write!(f, "{}", message),
+            Self::Validation(field_errors) => {
+                let fields: Vec<_> = field_errors
+                    .iter()
+                    .map(|error| format!("{}: {}", error.field, error.message))
+                    .collect();
+                write!(f, "{}", fields.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ServiceError {}
 
 pub fn check_only_one_row_changed(rows_changed: u64) -> Result<()> {
     match rows_changed.cmp(&1) {
         Ordering::Equal => Ok(()),
-        Ordering::Less => Err(anyhow!("No rows affected")),
-        Ordering::Greater => Err(anyhow!("More than one row affected")),
+        Ordering::Less => Err(ServiceError::NotFound("No rows affected".to_string()).into()),
+        Ordering::Greater => Err(anyhow::anyhow!("More than one row affected")),
     }
 }
 
 pub fn _check_at_least_one_row_changed(rows_changed: usize) -> Result<()> {
     if let Ordering::Less = rows_changed.cmp(&1) {
-        Err(anyhow!("No rows affected"))
+        Err(ServiceError::NotFound("No rows affected".to_string()).into())
     } else {
         Ok(())
     }
@@ -0,0 +1,229 @@
+use crate::{
+    dao,
+    models::{patch::UpdateNotificationPreferences, NotificationPreferences, Ticket, User},
+    service::{
+        digests,
+        emails::{self, Email},
+        ServiceError,
+    },
+    TiraState,
+};
+use anyhow::Result;
+use sqlx::{Postgres, Transaction};
+use std::collections::HashSet;
+
+/// Event for a new ticket, sent to the ticket's watchers.
+pub const CREATED_EVENT: &str = "created";
+
+/// Event for a new comment, sent to the ticket's watchers.
+pub const COMMENTED_EVENT: &str = "commented";
+
+/// Event for a user being assigned to a ticket, sent to that user.
+pub const ASSIGNED_EVENT: &str = "assigned";
+
+/// Event for a ticket moving to another status, sent to the ticket's watchers.
+pub const STATUS_CHANGED_EVENT: &str = "status_changed";
+
+/// Event for a user being mentioned in a comment, sent to that user.
+pub const MENTIONED_EVENT: &str = "mentioned";
+
+/// Something that happened to a ticket that users can be emailed about.
+pub enum Notification<'a> {
+    Created,
+    /// Sent to the watchers other than `mentioned_ids`, who get told about the mention instead.
+    Commented {
+        content: &'a str,
+        mentioned_ids: &'a [i64],
+    },
+    Assigned {
+        assignee_id: i64,
+    },
+    StatusChanged {
+        old_status: &'a str,
+    },
+    Mentioned {
+        content: &'a str,
+        mentioned_ids: &'a [i64],
+    },
+}
+
+impl Notification<'_> {
+    fn event(&self) -> &'static str {
+        match self {
+            Notification::Created => CREATED_EVENT,
+            Notification::Commented { .. } => COMMENTED_EVENT,
+            Notification::Assigned { .. } => ASSIGNED_EVENT,
+            Notification::StatusChanged { .. } => STATUS_CHANGED_EVENT,
+            Notification::Mentioned { .. } => MENTIONED_EVENT,
+        }
+    }
+}
+
+/// Service function for emailing the users that want to hear about something that `actor_id`
+/// did to a ticket, as part of the transaction that did it.
+///
+/// Assignments and mentions go to the users concerned and everything else to the ticket's
+/// watchers. The actor is never emailed about their own actions and users can turn off every
+/// event in their notification preferences. Users that chose digests get the notification in
+/// their next digest.
+pub async fn notify(
+    state: &TiraState,
+    tx: &mut Transaction<'_, Postgres>,
+    actor_id: i64,
+    ticket: &Ticket,
+    notification: Notification<'_>,
+) -> Result<()> {
+    let mut user_ids = match notification {
+        Notification::Assigned { assignee_id } => vec![assignee_id],
+        Notification::Mentioned { mentioned_ids, .. } => mentioned_ids.to_vec(),
+        Notification::Commented { mentioned_ids, .. } => {
+            let mut watcher_ids =
+                dao::watchers::get_watcher_ids_by_ticket_id(tx, ticket.id).await?;
+            watcher_ids.retain(|user_id| !mentioned_ids.contains(user_id));
+            watcher_ids
+        }
+        _ => dao::watchers::get_watcher_ids_by_ticket_id(tx, ticket.id).await?,
+    };
+    user_ids.retain(|user_id| *user_id != actor_id);
+    if user_ids.is_empty() {
+        return Ok(());
+    }
+
+    let recipients =
+        dao::notifications::get_email_recipients(state, user_ids, notification.event()).await?;
+    if recipients.is_empty() {
+        return Ok(());
+    }
+
+    let actor = dao::users::get_user_by_id(state, actor_id).await?;
+    let templates = &state.email_templates;
+    let body = match notification {
+        Notification::Created => {
+            emails::create_ticket_creation_email_body(templates, &actor, ticket)?
+        }
+        Notification::Commented { content, .. } => {
+            emails::create_comment_email_body(templates, &actor, content, ticket)?
+        }
+        Notification::Assigned { .. } => {
+            emails::create_assignment_email_body(templates, &actor, ticket)?
+        }
+        Notification::StatusChanged { old_status } => {
+            emails::create_status_change_email_body(templates, &actor, ticket, old_status)?
+        }
+        Notification::Mentioned { content, .. } => {
+            emails::create_mention_email_body(templates, &actor, content, ticket)?
+        }
+    };
+
+    let recipient_ids = recipients.iter().map(|recipient| recipient.id).collect();
+    let digest_user_ids: HashSet<_> =
+        dao::digests::get_digest_user_ids(state, recipient_ids, digests::OFF_FREQUENCY)
+            .await?
+            .into_iter()
+            .collect();
+
+    for recipient in recipients {
+        if digest_user_ids.contains(&recipient.id) {
+            dao::digests::create_digest_item(tx, recipient.id, ticket.id, &body).await?;
+        } else if let Some(email_address) = recipient.email_address {
+            let mut email = Email::new(email_address, ticket.subject.clone(), body.clone());
+            email.reply_to = state
+                .reply_addresses
+                .as_ref()
+                .map(|reply_addresses| reply_addresses.create(ticket.id, recipient.id));
+            emails::queue_email(tx, email).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Service function for retrieving which events a user gets emailed about.
+pub async fn get_notification_preferences_by_user_id(
+    state: &TiraState,
+    user_id: i64,
+) -> Result<NotificationPreferences> {
+    let mut preferences = NotificationPreferences {
+        created: true,
+        commented: true,
+        assigned: true,
+        status_changed: true,
+        mentioned: true,
+        digest: dao::digests::get_digest_frequency_by_user_id(state, user_id)
+            .await?
+            .unwrap_or_else(|| digests::OFF_FREQUENCY.to_string()),
+    };
+
+    let stored =
+        dao::notifications::get_notification_preferences_by_user_id(state, user_id).await?;
+    for (event, email) in stored {
+        match event.as_str() {
+            CREATED_EVENT => preferences.created = email,
+            COMMENTED_EVENT => preferences.commented = email,
+            ASSIGNED_EVENT => preferences.assigned = email,
+            STATUS_CHANGED_EVENT => preferences.status_changed = email,
+            MENTIONED_EVENT => preferences.mentioned = email,
+            _ => (),
+        }
+    }
+
+    Ok(preferences)
+}
+
+/// Service function for changing which events a user gets emailed about.
+pub async fn update_notification_preferences_by_user_id(
+    state: &TiraState,
+    user_id: i64,
+    preferences: &UpdateNotificationPreferences,
+) -> Result<()> {
+    if let Some(digest) = &preferences.digest {
+        if !digests::FREQUENCIES.contains(&digest.as_str()) {
+            let message = format!(
+                "Digest must be '{}', '{}', or '{}'",
+                digests::OFF_FREQUENCY,
+                digests::DAILY_FREQUENCY,
+                digests::WEEKLY_FREQUENCY
+            );
+            return Err(ServiceError::invalid_field("digest", &message).into());
+        }
+        dao::digests::upsert_digest_frequency(state, user_id, digest).await?;
+    }
+
+    let changes = [
+        (CREATED_EVENT, preferences.created),
+        (COMMENTED_EVENT, preferences.commented),
+        (ASSIGNED_EVENT, preferences.assigned),
+        (STATUS_CHANGED_EVENT, preferences.status_changed),
+        (MENTIONED_EVENT, preferences.mentioned),
+    ];
+    for (event, email) in changes {
+        if let Some(email) = email {
+            dao::notifications::upsert_notification_preference(state, user_id, event, email)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Service function for making a user watch a ticket.
+pub async fn watch_ticket(state: &TiraState, ticket_id: i64, user_id: i64) -> Result<()> {
+    dao::tickets::get_ticket_by_id(state, ticket_id).await?;
+
+    let mut tx = state.pool.begin().await?;
+    dao::watchers::create_watchers(&mut tx, ticket_id, &[user_id]).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Service function for making a user stop watching a ticket.
+pub async fn unwatch_ticket(state: &TiraState, ticket_id: i64, user_id: i64) -> Result<()> {
+    dao::tickets::get_ticket_by_id(state, ticket_id).await?;
+    dao::watchers::delete_watcher(state, ticket_id, user_id).await?;
+    Ok(())
+}
+
+/// Service function for retrieving the users watching a ticket.
+pub async fn get_watchers_by_ticket_id(state: &TiraState, ticket_id: i64) -> Result<Vec<User>> {
+    dao::tickets::get_ticket_by_id(state, ticket_id).await?;
+    dao::watchers::get_watchers_by_ticket_id(state, ticket_id).await
+}
@@ -0,0 +1,115 @@
+use crate::{dao, models::User, service::ServiceError, TiraState};
+use anyhow::Result;
+
+/// Role that can do everything, including managing users and categories.
+pub const ADMIN_ROLE: &str = "admin";
+
+/// Role that can create and work on tickets.
+pub const MEMBER_ROLE: &str = "member";
+
+/// Role that can only read.
+pub const VIEWER_ROLE: &str = "viewer";
+
+pub const ROLES: [&str; 3] = [ADMIN_ROLE, MEMBER_ROLE, VIEWER_ROLE];
+
+fn deny(message: &str) -> Result<()> {
+    Err(ServiceError::Forbidden(message.to_string()).into())
+}
+
+pub fn is_admin(user: &User) -> bool {
+    user.role == ADMIN_ROLE
+}
+
+/// Checks that a role is one of the known roles.
+pub fn check_role(role: &str) -> Result<()> {
+    if ROLES.contains(&role) {
+        Ok(())
+    } else {
+        let message = format!(
+            "Role must be '{}', '{}', or '{}'",
+            ADMIN_ROLE, MEMBER_ROLE, VIEWER_ROLE
+        );
+        Err(ServiceError::invalid_field("role", &message).into())
+    }
+}
+
+/// Permission check for actions only admins can do.
+pub fn require_admin(user: &User) -> Result<()> {
+    if is_admin(user) {
+        Ok(())
+    } else {
+        deny("Only admins can do this!")
+    }
+}
+
+/// Permission check for anything that changes data.
+pub fn require_write(user: &User) -> Result<()> {
+    if user.role == VIEWER_ROLE {
+        deny("Viewers cannot make changes!")
+    } else {
+        Ok(())
+    }
+}
+
+/// Permission check for editing a user.
+///
+/// Users can edit themselves and admins can edit anyone.
+pub fn require_user_editor(user: &User, user_id: i64) -> Result<()> {
+    if user.id == user_id || is_admin(user) {
+        Ok(())
+    } else {
+        deny("Cannot edit another person's user!")
+    }
+}
+
+/// Permission check for editing a ticket.
+///
+/// Tickets can be edited by their reporter, their assignees and admins.
+pub async fn require_ticket_editor(state: &TiraState, user: &User, ticket_id: i64) -> Result<()> {
+    if is_admin(user) {
+        return Ok(());
+    }
+
+    let ticket = dao::tickets::get_ticket_by_id(state, ticket_id).await?;
+    if ticket.reporter_id == user.id {
+        return Ok(());
+    }
+
+    let assignments = dao::tickets::get_assignments_by_ticket_id(state, ticket_id).await?;
+    if assignments
+        .iter()
+        .any(|assignment| assignment.assignee_id == user.id)
+    {
+        return Ok(());
+    }
+
+    deny("Only the reporter or an assignee can edit this ticket!")
+}
+
+/// Permission check for deleting an attachment.
+///
+/// Attachments can be deleted by whoever uploaded them and admins.
+pub async fn require_attachment_owner(
+    state: &TiraState,
+    user: &User,
+    attachment_id: i64,
+) -> Result<()> {
+    let attachment = dao::attachments::get_attachment_by_id(state, attachment_id).await?;
+    if attachment.owner_id == user.id || is_admin(user) {
+        Ok(())
+    } else {
+        deny("Only the uploader can delete this attachment!")
+    }
+}
+
+/// Permission check for editing or deleting a comment.
+///
+/// Comments can be edited and deleted by their author and admins.
+pub async fn require_comment_editor(state: &TiraState, user: &User, comment_id: i64) -> Result<()> {
+    let comment = dao::comments::get_comment_by_id(state, comment_id).await?;
+    if comment.commenter_id == user.id || is_admin(user) {
+        Ok(())
+    } else {
+        deny("Only the author can edit this comment!")
+    }
+}
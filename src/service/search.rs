@@ -0,0 +1,99 @@
+use crate::{
+    dao,
+    models::{SearchHit, TicketFilter},
+    service::ServiceError,
+    TiraState,
+};
+use anyhow::Result;
+
+/// Put before every match in a snippet by the database, a private use character that does not
+/// show up in tickets.
+pub const MATCH_START: &str = "\u{E000}";
+
+/// Put after every match in a snippet by the database.
+pub const MATCH_END: &str = "\u{E001}";
+
+/// Turns a snippet from the database into HTML with its matches in `<mark>` tags.
+///
+/// The snippet mixes the plain text subject with the text of HTML descriptions and comments, so
+/// it is decoded before all of it is escaped.
+fn highlight_snippet(snippet: &str) -> String {
+    let text = html_escape::decode_html_entities(snippet);
+    html_escape::encode_text(&text)
+        .replace(MATCH_START, "<mark>")
+        .replace(MATCH_END, "</mark>")
+}
+
+/// Service function for counting the tickets that match a search.
+pub async fn count_search_hits(
+    state: &TiraState,
+    text: &str,
+    filter: &TicketFilter,
+) -> Result<i64> {
+    dao::search::count_search_hits(state, text, filter).await
+}
+
+/// Service function for searching tickets and their comments.
+pub async fn search_tickets(
+    state: &TiraState,
+    text: &str,
+    filter: &TicketFilter,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Vec<SearchHit>> {
+    let limit = limit.unwrap_or(10);
+    let offset = offset.unwrap_or(0);
+
+    if text.trim().is_empty() {
+        return Err(ServiceError::BadRequest("Search text cannot be empty".to_string()).into());
+    }
+    if limit < 0 || offset < 0 {
+        return Err(
+            ServiceError::BadRequest("Limit and offset cannot be negative".to_string()).into(),
+        );
+    }
+
+    let mut hits = dao::search::search_tickets(state, text, filter, limit, offset).await?;
+    for hit in &mut hits {
+        hit.snippet = highlight_snippet(&hit.snippet);
+    }
+    Ok(hits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snippet_matches_are_marked() {
+        let snippet = format!("the {}printer{} is broken", MATCH_START, MATCH_END);
+        assert_eq!(
+            highlight_snippet(&snippet),
+            "the <mark>printer</mark> is broken"
+        );
+    }
+
+    #[test]
+    fn snippet_markup_in_subject_is_escaped() {
+        let snippet = format!(
+            "<img src=x onerror=alert(1)> {}printer{} & <b>co</b>",
+            MATCH_START, MATCH_END
+        );
+        assert_eq!(
+            highlight_snippet(&snippet),
+            "&lt;img src=x onerror=alert(1)&gt; <mark>printer</mark> &amp; &lt;b&gt;co&lt;/b&gt;"
+        );
+    }
+
+    #[test]
+    fn snippet_entities_from_html_are_not_escaped_twice() {
+        let snippet = format!(
+            "{}tom{} &amp; jerry &lt;3 &#39;x&#39;",
+            MATCH_START, MATCH_END
+        );
+        assert_eq!(
+            highlight_snippet(&snippet),
+            "<mark>tom</mark> &amp; jerry &lt;3 'x'"
+        );
+    }
+}
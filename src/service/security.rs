@@ -1,8 +1,225 @@
-use crypto::{digest::Digest, sha2::Sha256};
+use anyhow::{anyhow, Result};
+use argon2::{
+    password_hash::{rand_core::OsRng, SaltString},
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+};
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
 
-/// Service function for putting a string through sha256
-pub fn sha256(password: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.input_str(password);
-    hasher.result_str()
+/// Password scheme for PHC-format Argon2id hashes.
+pub const ARGON2ID_SCHEME: &str = "argon2id";
+
+/// Password scheme for the legacy unsalted SHA-256 hex digests.
+///
+/// These are only ever verified, never written, and get upgraded to Argon2id on the next login.
+pub const SHA256_SCHEME: &str = "sha256";
+
+/// Result of checking a password against a stored hash.
+pub enum PasswordCheck {
+    /// The password is wrong.
+    Invalid,
+    /// The password is right and the stored hash is up to date.
+    Valid,
+    /// The password is right but the stored hash uses an old scheme or old parameters.
+    ///
+    /// Holds a fresh Argon2id hash that should replace the stored one.
+    ValidNeedsRehash(String),
+}
+
+fn argon2(params: &Params) -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone())
+}
+
+/// Service function for hashing a password with Argon2id.
+///
+/// Returns the hash in PHC string format.
+pub async fn hash_password(params: &Params, password: &str) -> Result<String> {
+    let params = params.clone();
+    let password = password.to_string();
+
+    // Hashing is deliberately slow so keep it off of the async runtime
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        argon2(&params)
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| anyhow!("Could not hash password: {}", e))
+    })
+    .await?
+}
+
+/// Service function for checking a password against a stored hash of the given scheme.
+pub async fn verify_password(
+    params: &Params,
+    password: &str,
+    stored_hash: &str,
+    scheme: &str,
+) -> Result<PasswordCheck> {
+    let valid = match scheme {
+        ARGON2ID_SCHEME => {
+            let (valid, outdated) = tokio::task::spawn_blocking({
+                let params = params.clone();
+                let password = password.to_string();
+                let stored_hash = stored_hash.to_string();
+
+                move || -> Result<_> {
+                    let hash = PasswordHash::new(&stored_hash)
+                        .map_err(|e| anyhow!("Stored password hash is invalid: {}", e))?;
+                    let valid = argon2(&params)
+                        .verify_password(password.as_bytes(), &hash)
+                        .is_ok();
+                    let outdated = hash.algorithm != Algorithm::Argon2id.ident()
+                        || Params::try_from(&hash).map_or(true, |stored| {
+                            stored.m_cost() != params.m_cost()
+                                || stored.t_cost() != params.t_cost()
+                                || stored.p_cost() != params.p_cost()
+                        });
+                    Ok((valid, outdated))
+                }
+            })
+            .await??;
+
+            if valid && outdated {
+                return rehash(params, password).await;
+            }
+            valid
+        }
+        SHA256_SCHEME => {
+            if constant_time_eq(sha256(password).as_bytes(), stored_hash.as_bytes()) {
+                return rehash(params, password).await;
+            }
+            false
+        }
+        _ => return Err(anyhow!("Unknown password scheme '{}'", scheme)),
+    };
+
+    Ok(if valid {
+        PasswordCheck::Valid
+    } else {
+        PasswordCheck::Invalid
+    })
+}
+
+/// Service function for taking as long as checking a password does, for logins with a username
+/// that does not exist, so that response times don't tell which usernames do.
+///
+/// The password is checked against a hash of a made up password, with the same parameters as
+/// real hashes.
+pub async fn verify_dummy_password(params: &Params, password: &str) -> Result<()> {
+    static DUMMY_HASH: OnceCell<String> = OnceCell::const_new();
+    let dummy_hash = DUMMY_HASH
+        .get_or_try_init(|| hash_password(params, "not a real password"))
+        .await?;
+    verify_password(params, password, dummy_hash, ARGON2ID_SCHEME).await?;
+    Ok(())
+}
+
+async fn rehash(params: &Params, password: &str) -> Result<PasswordCheck> {
+    let hash = hash_password(params, password).await?;
+    Ok(PasswordCheck::ValidNeedsRehash(hash))
+}
+
+fn sha256(password: &str) -> String {
+    format!("{:x}", Sha256::digest(password.as_bytes()))
+}
+
+/// Compares secrets in a time that does not depend on where they differ.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The smallest parameters Argon2 allows, so that the tests don't take long
+    fn params() -> Params {
+        Params::new(8, 1, 1, None).unwrap()
+    }
+
+    async fn check(password: &str, stored_hash: &str, scheme: &str) -> PasswordCheck {
+        verify_password(&params(), password, stored_hash, scheme)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn passwords_are_hashed_with_argon2id() {
+        let hash = hash_password(&params(), "hunter2").await.unwrap();
+        assert!(hash.starts_with("$argon2id$v=19$m=8,t=1,p=1$"));
+        assert_ne!(hash, hash_password(&params(), "hunter2").await.unwrap());
+
+        assert!(matches!(
+            check("hunter2", &hash, ARGON2ID_SCHEME).await,
+            PasswordCheck::Valid
+        ));
+        assert!(matches!(
+            check("hunter3", &hash, ARGON2ID_SCHEME).await,
+            PasswordCheck::Invalid
+        ));
+    }
+
+    #[tokio::test]
+    async fn legacy_sha256_hashes_are_verified_and_rehashed() {
+        let hash = sha256("hunter2");
+        assert_eq!(
+            hash,
+            "f52fbd32b2b3b86ff88ef6c490628285f482af15ddcb29541f94bcf526a3f6c7"
+        );
+
+        match check("hunter2", &hash, SHA256_SCHEME).await {
+            PasswordCheck::ValidNeedsRehash(new_hash) => {
+                assert!(matches!(
+                    check("hunter2", &new_hash, ARGON2ID_SCHEME).await,
+                    PasswordCheck::Valid
+                ));
+            }
+            _ => panic!("a right sha256 password should be rehashed"),
+        }
+        assert!(matches!(
+            check("hunter3", &hash, SHA256_SCHEME).await,
+            PasswordCheck::Invalid
+        ));
+    }
+
+    #[tokio::test]
+    async fn hashes_with_outdated_params_are_rehashed() {
+        let old_params = Params::new(16, 2, 1, None).unwrap();
+        let hash = hash_password(&old_params, "hunter2").await.unwrap();
+
+        match check("hunter2", &hash, ARGON2ID_SCHEME).await {
+            PasswordCheck::ValidNeedsRehash(new_hash) => {
+                assert!(new_hash.starts_with("$argon2id$v=19$m=8,t=1,p=1$"));
+            }
+            _ => panic!("a right password with outdated params should be rehashed"),
+        }
+        assert!(matches!(
+            check("hunter3", &hash, ARGON2ID_SCHEME).await,
+            PasswordCheck::Invalid
+        ));
+    }
+
+    #[tokio::test]
+    async fn unknown_schemes_are_rejected() {
+        assert!(verify_password(&params(), "hunter2", "hunter2", "plain")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn dummy_password_is_checked_against_a_real_hash() {
+        verify_dummy_password(&params(), "hunter2").await.unwrap();
+        verify_dummy_password(&params(), "not a real password")
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn constant_time_eq_compares_whole_secrets() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"Secret"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+        assert!(constant_time_eq(b"", b""));
+    }
 }
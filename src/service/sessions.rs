@@ -1,23 +1,59 @@
 use crate::{
     dao,
     models::{Login, User},
-    service, TiraState,
+    service::{
+        self,
+        security::{self, PasswordCheck},
+        ServiceError,
+    },
+    TiraState,
 };
 use anyhow::Result;
 use uuid::Uuid;
 
-/// Service function for retrieving user_id by session_uuid.
+// Service function for retrieving user_id by session_uuid.
 // pub async fn get_user_id_from_session_uuid(conn: &TiraDbConn, session_uuid: String) -> QueryResult<i64> {
 //     let session = dao::sessions::get_session_from_session_uuid(conn, session_uuid).await?;
 //     Ok(session.user_id)
 // }
 
+fn invalid_login() -> ServiceError {
+    ServiceError::Unauthorized("Invalid username or password".to_string())
+}
+
 /// Service function for performing a login.
 ///
+/// Unknown usernames, wrong passwords and archived users all get the same error, and take about
+/// as long to get it.
+///
 /// Returns the UUID for the newly created session and user.
 pub async fn login(state: &TiraState, login_info: Login) -> Result<(String, User)> {
     let remember_me = login_info.remember_me;
-    let user = dao::users::get_user_by_username_and_password(state, login_info).await?;
+    let user = match dao::users::get_user_by_username(state, &login_info.username).await {
+        Err(e) if matches!(e.downcast_ref(), Some(sqlx::Error::RowNotFound)) => {
+            security::verify_dummy_password(&state.argon2_params, &login_info.password).await?;
+            return Err(invalid_login().into());
+        }
+        user => user?,
+    };
+
+    let password_check = security::verify_password(
+        &state.argon2_params,
+        &login_info.password,
+        &user.password,
+        &user.password_scheme,
+    )
+    .await?;
+
+    if matches!(password_check, PasswordCheck::Invalid) || user.archived {
+        return Err(invalid_login().into());
+    }
+    if let PasswordCheck::ValidNeedsRehash(password) = password_check {
+        let users_updated =
+            dao::users::update_password_by_id(state, user.id, &password, security::ARGON2ID_SCHEME)
+                .await?;
+        service::check_only_one_row_changed(users_updated)?;
+    }
 
     let my_uuid = Uuid::new_v4();
     dao::sessions::create_session_by_session_uuid_and_user_id(
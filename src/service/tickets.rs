@@ -1,80 +1,262 @@
 use crate::{
-    dao::{self, tickets},
+    dao::{
+        self,
+        tickets::{self, BatchTicket, TicketBatch},
+    },
     models::{
-        patch::UpdateTicket, Assignment, Comment, CreateTicket, Ticket, TicketWithoutDescription,
+        error::FieldError, patch::UpdateTicket, Assignment, Comment, CreateTicket, LiveEvent,
+        Ticket, TicketEvent, TicketFieldChange, TicketFilter, TicketWithoutDescription,
+    },
+    service::{
+        self, comments, html, live_events,
+        notifications::{self, Notification},
+        webhooks::{self, WebhookEvent},
+        workflows, ServiceError,
     },
-    service, TiraState,
+    TiraState,
 };
-use anyhow::anyhow;
 use anyhow::Result;
 use regex::Regex;
+use sqlx::{Postgres, Transaction};
+use std::{collections::HashSet, sync::LazyLock};
+
+/// Matches the HTML tags and non-breaking spaces that the editor puts in descriptions and comments.
+pub const HTML_TAG_PATTERN: &str = r"</?[^>]+(>|$)|&nbsp;";
+
+static HTML_TAG_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(HTML_TAG_PATTERN).unwrap());
+
+/// Replaces every HTML tag in `html` with a space, leaving only its text.
+pub fn strip_html_tags(html: &str) -> String {
+    HTML_TAG_REGEX.replace_all(html, " ").into()
+}
 
 /// Service function for creating an assignment by ticket id and assigner id.
+///
+/// The assignee starts watching the ticket and gets notified, and so do the webhooks.
 pub async fn create_assignment_by_ticket_id_and_assigner_id(
     state: &TiraState,
     assignee_id: i64,
     ticket_id: i64,
     assigner_id: i64,
 ) -> Result<i64> {
-    dao::tickets::create_assignment_by_ticket_id_and_assigner_id(
-        state,
+    let ticket = dao::tickets::get_ticket_by_id(state, ticket_id).await?;
+
+    let mut tx = state.pool.begin().await?;
+    let id = dao::tickets::create_assignment_by_ticket_id_and_assigner_id(
+        &mut tx,
         assignee_id,
         ticket_id,
         assigner_id,
     )
-    .await
+    .await?;
+    dao::watchers::create_watchers(&mut tx, ticket_id, &[assignee_id]).await?;
+    notifications::notify(
+        state,
+        &mut tx,
+        assigner_id,
+        &ticket,
+        Notification::Assigned { assignee_id },
+    )
+    .await?;
+    webhooks::trigger(
+        state,
+        &mut tx,
+        assigner_id,
+        ticket_id,
+        WebhookEvent::AssignmentCreated { assignee_id },
+    )
+    .await?;
+    tx.commit().await?;
+
+    live_events::publish(
+        state,
+        LiveEvent {
+            assignee_id: Some(assignee_id),
+            ..live_events::ticket_event(live_events::ASSIGNMENT_CHANGED_EVENT, assigner_id, &ticket)
+        },
+    )
+    .await;
+    Ok(id)
 }
 
 /// Service function for creating a comment by ticket id.
+///
+/// The comment is rendered to sanitized HTML from `content_format`, HTML unless given, and a
+/// reply names its parent comment, which has to be on the same ticket and not deleted.
+/// The commenter and the users mentioned as @username start watching the ticket. The mentioned
+/// users get told about the mention, the other watchers and the webhooks about the comment.
 pub async fn create_comment_by_ticket_id_and_commenter_id(
     state: &TiraState,
     comment: &str,
+    content_format: Option<&str>,
     ticket_id: i64,
     commenter_id: i64,
+    parent_comment_id: Option<i64>,
 ) -> Result<i64> {
-    let regex = Regex::new(r"(</?[^>]+(>|$)|&nbsp;|\s)").unwrap();
-    let content_without_tags: String = regex.replace_all(comment, "").into();
+    let content_format = content_format.unwrap_or(html::HTML_FORMAT);
+    html::check_content_format(content_format)?;
+    let (content_source, comment) = state.html_sanitizer.render(content_format, comment);
+    let content_without_tags = strip_html_tags(&comment);
 
-    if content_without_tags.is_empty() {
-        return Err(anyhow!("Comment cannot be blank!"));
+    if content_without_tags.trim().is_empty() {
+        return Err(ServiceError::invalid_field("content", "Comment cannot be blank!").into());
     }
 
-    dao::tickets::create_comment_by_ticket_id_and_commenter_id(
-        state,
-        comment,
+    let ticket = dao::tickets::get_ticket_by_id(state, ticket_id).await?;
+
+    if let Some(parent_comment_id) = parent_comment_id {
+        let parent = match dao::comments::get_comment_by_id(state, parent_comment_id).await {
+            Err(e) if matches!(e.downcast_ref(), Some(sqlx::Error::RowNotFound)) => {
+                return Err(
+                    ServiceError::invalid_field("parent_comment_id", "Comment not found").into(),
+                )
+            }
+            parent => parent?,
+        };
+        if parent.ticket_id != ticket_id {
+            return Err(ServiceError::invalid_field(
+                "parent_comment_id",
+                "Can only reply to comments on the same ticket",
+            )
+            .into());
+        }
+        if parent.deleted.is_some() {
+            return Err(ServiceError::invalid_field(
+                "parent_comment_id",
+                "Cannot reply to a deleted comment",
+            )
+            .into());
+        }
+    }
+
+    let mentioned_ids = comments::get_mentioned_user_ids(state, &content_without_tags).await?;
+
+    let mut tx = state.pool.begin().await?;
+    let id = dao::tickets::create_comment_by_ticket_id_and_commenter_id(
+        &mut tx,
+        &comment,
+        &content_source,
+        content_format,
+        &content_without_tags,
         ticket_id,
         commenter_id,
+        parent_comment_id,
+    )
+    .await?;
+    dao::comments::replace_mentions(&mut tx, id, &mentioned_ids).await?;
+    dao::watchers::create_watchers(&mut tx, ticket_id, &[commenter_id]).await?;
+    dao::watchers::create_watchers(&mut tx, ticket_id, &mentioned_ids).await?;
+    notifications::notify(
+        state,
+        &mut tx,
+        commenter_id,
+        &ticket,
+        Notification::Commented {
+            content: &comment,
+            mentioned_ids: &mentioned_ids,
+        },
     )
-    .await
+    .await?;
+    notifications::notify(
+        state,
+        &mut tx,
+        commenter_id,
+        &ticket,
+        Notification::Mentioned {
+            content: &comment,
+            mentioned_ids: &mentioned_ids,
+        },
+    )
+    .await?;
+    webhooks::trigger(
+        state,
+        &mut tx,
+        commenter_id,
+        ticket_id,
+        WebhookEvent::CommentCreated { comment_id: id },
+    )
+    .await?;
+    tx.commit().await?;
+
+    live_events::publish(
+        state,
+        LiveEvent {
+            comment_id: Some(id),
+            ..live_events::ticket_event(live_events::COMMENT_ADDED_EVENT, commenter_id, &ticket)
+        },
+    )
+    .await;
+    Ok(id)
 }
 
 /// Service function for creating a ticket by reporter id.
+///
+/// The description is rendered to sanitized HTML. The assignees and the webhooks get notified
+/// about the new ticket.
 pub async fn create_ticket_by_reporter_id(
     state: &TiraState,
-    ticket: &CreateTicket,
+    mut ticket: CreateTicket,
     reporter_id: i64,
 ) -> Result<i64> {
+    let content_format = ticket
+        .content_format
+        .get_or_insert_with(|| html::HTML_FORMAT.to_string());
+    html::check_content_format(content_format)?;
+    let (description_source, description) = ticket
+        .description
+        .as_deref()
+        .map(|description| state.html_sanitizer.render(content_format, description))
+        .unzip();
+    ticket.description = description;
+
+    let mut field_errors = Vec::new();
+
     if ticket.subject.is_empty() {
-        return Err(anyhow!("Subject can not be empty"));
+        field_errors.push(FieldError::new("subject", "Subject can not be empty"));
     }
 
-    match ticket.status.as_str() {
-        "Backlog" | "In Progress" | "Not Deployed Yet" | "Done" | "Closed" => (),
-        _ => {
-            return Err(anyhow!(
-                "Status must be 'Backlog', 'In Progress', 'Not Deployed Yet', 'Done', or 'Closed'"
-            ));
-        }
-    }
+    let mut tx = state.pool.begin().await?;
+    field_errors.extend(
+        workflows::check_status_transition(&mut tx, ticket.category_id, None, &ticket.status)
+            .await?,
+    );
+    field_errors.extend(workflows::check_priority(&mut tx, &ticket.priority).await?);
 
-    match ticket.priority.as_str() {
-        "Low" | "Medium" | "High" => (),
-        _ => {
-            return Err(anyhow!("Priority must be 'Low', 'Medium', or 'High'"));
-        }
+    if !field_errors.is_empty() {
+        return Err(ServiceError::Validation(field_errors).into());
     }
 
-    let id = dao::tickets::create_ticket_by_reporter_id(state, ticket, reporter_id).await?;
+    let description_text = strip_html_tags(ticket.description.as_deref().unwrap_or_default());
+    let id = dao::tickets::create_ticket_by_reporter_id(
+        &mut tx,
+        &ticket,
+        description_source.as_deref(),
+        &description_text,
+        reporter_id,
+    )
+    .await?;
+
+    let created_ticket = dao::tickets::get_ticket_by_id_in_transaction(&mut tx, id).await?;
+    notifications::notify(
+        state,
+        &mut tx,
+        reporter_id,
+        &created_ticket,
+        Notification::Created,
+    )
+    .await?;
+    webhooks::trigger(state, &mut tx, reporter_id, id, WebhookEvent::TicketCreated).await?;
+    tx.commit().await?;
+
+    live_events::publish(
+        state,
+        live_events::ticket_event(
+            live_events::TICKET_CREATED_EVENT,
+            reporter_id,
+            &created_ticket,
+        ),
+    )
+    .await;
     Ok(id)
 }
 
@@ -87,10 +269,15 @@ pub async fn get_assignments_by_ticket_id(
     Ok(assignments)
 }
 
-/// Service function for retrieving comments by ticket id.
+/// Service function for retrieving comments by ticket id, oldest first.
+///
+/// Deleted comments show a placeholder instead of their content.
 pub async fn get_comments_by_ticket_id(state: &TiraState, ticket_id: i64) -> Result<Vec<Comment>> {
     let comments = dao::tickets::get_comments_by_ticket_id(state, ticket_id).await?;
-    Ok(comments)
+    Ok(comments
+        .into_iter()
+        .map(comments::hide_deleted_content)
+        .collect())
 }
 
 /// Service function for retrieving a ticket by id.
@@ -99,53 +286,387 @@ pub async fn get_ticket_by_id(state: &TiraState, ticket_id: i64) -> Result<Ticke
     Ok(ticket)
 }
 
+/// Service function for loading the reporters, categories, assignees and attachments of a batch
+/// of tickets.
+pub async fn load_ticket_batch<T: BatchTicket>(
+    state: &TiraState,
+    tickets: &[T],
+    extra_user_ids: Vec<i64>,
+) -> Result<TicketBatch> {
+    TicketBatch::load(state, tickets, extra_user_ids).await
+}
+
 /// Service function for retrieving tickets by ids.
 pub async fn get_tickets_by_ids(state: &TiraState, ticket_ids: Vec<i64>) -> Result<Vec<Ticket>> {
     let tickets = dao::tickets::get_tickets_by_ids(state, ticket_ids).await?;
     Ok(tickets)
 }
 
+/// Service function for counting all tickets that match a filter.
+pub async fn count_tickets(state: &TiraState, filter: &TicketFilter) -> Result<i64> {
+    dao::tickets::count_tickets(state, filter).await
+}
+
 /// Service function for retrieving all tickets.
 pub async fn get_tickets(
     state: &TiraState,
-    // limit: Option<i64>,
-    // offset: Option<i64>,
-    // filter_reporter_id: Option<i64>,
-    // filter_open: Option<bool>,
-    // sort_by: Option<String>,
-    // order_by: Option<String>,
+    filter: &TicketFilter,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    sort_by: Option<String>,
+    order_by: Option<String>,
 ) -> Result<Vec<TicketWithoutDescription>> {
+    let limit = limit.unwrap_or(10);
+    let offset = offset.unwrap_or(0);
+
+    if limit < 0 || offset < 0 {
+        return Err(
+            ServiceError::BadRequest("Limit and offset cannot be negative".to_string()).into(),
+        );
+    }
+
     let tickets = dao::tickets::get_tickets(
         state,
-        // limit,
-        // offset,
-        // filter_reporter_id,
-        // filter_open,
-        // sort_by,
-        // order_by,
+        filter,
+        limit,
+        offset,
+        sort_by.as_deref().unwrap_or("created"),
+        order_by.as_deref().unwrap_or("desc"),
     )
     .await?;
 
     Ok(tickets)
 }
 
+/// Service function for retrieving the recorded changes of a ticket by ticket id.
+pub async fn get_ticket_events_by_ticket_id(
+    state: &TiraState,
+    ticket_id: i64,
+) -> Result<Vec<TicketEvent>> {
+    dao::ticket_events::get_ticket_events_by_ticket_id(state, ticket_id).await
+}
+
+/// Adds a change for `field` to `changes` if `new_value` is set and differs from `old_value`.
+fn push_change<T: PartialEq + ToString + ?Sized>(
+    changes: &mut Vec<TicketFieldChange>,
+    field: &'static str,
+    old_value: Option<&T>,
+    new_value: Option<&T>,
+) {
+    if let Some(new_value) = new_value {
+        if old_value != Some(new_value) {
+            changes.push(TicketFieldChange {
+                field,
+                old_value: old_value.map(ToString::to_string),
+                new_value: Some(new_value.to_string()),
+            });
+        }
+    }
+}
+
 /// Service function for updating a ticket by id.
+///
+/// Status changes have to be allowed by the workflow of the ticket's category. The description
+/// is rendered to sanitized HTML, again from the stored source if only the format changes.
+/// Every field that changes is recorded in the ticket's history as changed by `actor_id`.
+/// The ticket, its assignees and its history are updated in one transaction.
+/// Newly assigned users and, if the status changed, the ticket's watchers get notified.
+/// The webhooks get told about the update and every assignment that started or ended.
 pub async fn update_ticket_by_id(
     state: &TiraState,
-    ticket: &UpdateTicket,
+    mut ticket: UpdateTicket,
     ticket_id: i64,
+    actor_id: i64,
 ) -> Result<()> {
-    let tickets_updated = tickets::update_ticket_by_id(state, ticket, ticket_id).await?;
-    service::check_only_one_row_changed(tickets_updated)
+    let mut tx = state.pool.begin().await?;
+    let old_ticket = tickets::get_ticket_by_id_for_update(&mut tx, ticket_id).await?;
+
+    let content_format = ticket
+        .content_format
+        .take()
+        .unwrap_or_else(|| old_ticket.content_format.clone());
+    html::check_content_format(&content_format)?;
+    let format_changed = content_format != old_ticket.content_format;
+    let source = match ticket.description.take() {
+        Some(description) => Some(description),
+        None if format_changed => old_ticket.description_source.clone(),
+        None => None,
+    };
+    let (description_source, description) = source
+        .map(|source| state.html_sanitizer.render(&content_format, &source))
+        .unzip();
+    ticket.description = description;
+    ticket.content_format = Some(content_format);
+
+    let mut field_errors = Vec::new();
+    if let Some(status) = ticket.status.as_deref() {
+        if status != old_ticket.status {
+            let category_id = ticket.category_id.or(old_ticket.category_id);
+            field_errors.extend(
+                workflows::check_status_transition(
+                    &mut tx,
+                    category_id,
+                    Some(&old_ticket.status),
+                    status,
+                )
+                .await?,
+            );
+        }
+    }
+    if let Some(priority) = ticket.priority.as_deref() {
+        if priority != old_ticket.priority {
+            field_errors.extend(workflows::check_priority(&mut tx, priority).await?);
+        }
+    }
+    if !field_errors.is_empty() {
+        return Err(ServiceError::Validation(field_errors).into());
+    }
+
+    let mut changes = Vec::new();
+    push_change(
+        &mut changes,
+        "category",
+        old_ticket.category_id.as_ref(),
+        ticket.category_id.as_ref(),
+    );
+    push_change(
+        &mut changes,
+        "subject",
+        Some(old_ticket.subject.as_str()),
+        ticket.subject.as_deref(),
+    );
+    push_change(
+        &mut changes,
+        "description",
+        old_ticket.description_source.as_deref(),
+        description_source.as_deref(),
+    );
+    push_change(
+        &mut changes,
+        "content_format",
+        Some(old_ticket.content_format.as_str()),
+        ticket.content_format.as_deref(),
+    );
+    push_change(
+        &mut changes,
+        "status",
+        Some(old_ticket.status.as_str()),
+        ticket.status.as_deref(),
+    );
+    push_change(
+        &mut changes,
+        "priority",
+        Some(old_ticket.priority.as_str()),
+        ticket.priority.as_deref(),
+    );
+
+    if !changes.is_empty() {
+        let tickets_updated = tickets::update_ticket_by_id(
+            &mut tx,
+            &ticket,
+            description_source.as_deref(),
+            ticket_id,
+        )
+        .await?;
+        service::check_only_one_row_changed(tickets_updated)?;
+    }
+
+    if ticket.subject.is_some() || ticket.description.is_some() {
+        let subject = ticket.subject.as_ref().unwrap_or(&old_ticket.subject);
+        let description = ticket
+            .description
+            .as_ref()
+            .or(old_ticket.description.as_ref());
+        let description_text = strip_html_tags(description.map_or("", String::as_str));
+        tickets::update_ticket_search_vector(&mut tx, ticket_id, subject, &description_text)
+            .await?;
+    }
+
+    let (added_assignee_ids, removed_assignee_ids) = match &ticket.assignee_ids {
+        Some(assignee_ids) => replace_assignees(&mut tx, ticket_id, assignee_ids, actor_id).await?,
+        None => (Vec::new(), Vec::new()),
+    };
+
+    dao::ticket_events::create_ticket_events(&mut tx, ticket_id, actor_id, &changes).await?;
+
+    if changes.is_empty() && added_assignee_ids.is_empty() && removed_assignee_ids.is_empty() {
+        tx.commit().await?;
+        return Ok(());
+    }
+    let updated_ticket = dao::tickets::get_ticket_by_id_in_transaction(&mut tx, ticket_id).await?;
+    webhooks::trigger(
+        state,
+        &mut tx,
+        actor_id,
+        ticket_id,
+        WebhookEvent::TicketUpdated,
+    )
+    .await?;
+    for assignee_id in &removed_assignee_ids {
+        webhooks::trigger(
+            state,
+            &mut tx,
+            actor_id,
+            ticket_id,
+            WebhookEvent::AssignmentDeleted {
+                assignee_id: *assignee_id,
+            },
+        )
+        .await?;
+    }
+    for assignee_id in &added_assignee_ids {
+        notifications::notify(
+            state,
+            &mut tx,
+            actor_id,
+            &updated_ticket,
+            Notification::Assigned {
+                assignee_id: *assignee_id,
+            },
+        )
+        .await?;
+        webhooks::trigger(
+            state,
+            &mut tx,
+            actor_id,
+            ticket_id,
+            WebhookEvent::AssignmentCreated {
+                assignee_id: *assignee_id,
+            },
+        )
+        .await?;
+    }
+    let status_changed = changes.iter().any(|change| change.field == "status");
+    if status_changed {
+        notifications::notify(
+            state,
+            &mut tx,
+            actor_id,
+            &updated_ticket,
+            Notification::StatusChanged {
+                old_status: &old_ticket.status,
+            },
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
+    live_events::publish(
+        state,
+        live_events::ticket_event(live_events::TICKET_UPDATED_EVENT, actor_id, &updated_ticket),
+    )
+    .await;
+    for assignee_id in removed_assignee_ids.into_iter().chain(added_assignee_ids) {
+        live_events::publish(
+            state,
+            LiveEvent {
+                assignee_id: Some(assignee_id),
+                ..live_events::ticket_event(
+                    live_events::ASSIGNMENT_CHANGED_EVENT,
+                    actor_id,
+                    &updated_ticket,
+                )
+            },
+        )
+        .await;
+    }
+    Ok(())
 }
 
-/// Service function for updating assignments by ticket id.
-pub async fn update_assignments_by_ticket_id(
-    state: &TiraState,
+/// Makes `assignee_ids` the assignees of a ticket.
+///
+/// Only users that are not assigned yet get a new assignment and start watching the ticket.
+/// The assignments of users missing from `assignee_ids` are ended, so both show up in the
+/// ticket's history.
+///
+/// Returns the ids of the newly assigned users and of the users that are no longer assigned.
+async fn replace_assignees(
+    tx: &mut Transaction<'_, Postgres>,
     ticket_id: i64,
-    assignee_ids: Vec<i64>,
+    assignee_ids: &[i64],
     assigner_id: i64,
+) -> Result<(Vec<i64>, Vec<i64>)> {
+    let old_assignee_ids: HashSet<_> =
+        dao::assignments::get_assignments_by_ticket_id_in_transaction(tx, ticket_id)
+            .await?
+            .iter()
+            .map(|assignment| assignment.assignee_id)
+            .collect();
+    let new_assignee_ids: HashSet<_> = assignee_ids.iter().copied().collect();
+
+    let added: Vec<_> = new_assignee_ids
+        .difference(&old_assignee_ids)
+        .copied()
+        .collect();
+    let removed: Vec<_> = old_assignee_ids
+        .difference(&new_assignee_ids)
+        .copied()
+        .collect();
+
+    if !removed.is_empty() {
+        dao::assignments::unassign_by_ticket_id_and_assignee_ids(
+            tx,
+            ticket_id,
+            &removed,
+            assigner_id,
+        )
+        .await?;
+    }
+    if !added.is_empty() {
+        dao::assignments::create_assignments_by_ticket_id(tx, ticket_id, &added, assigner_id)
+            .await?;
+        dao::watchers::create_watchers(tx, ticket_id, &added).await?;
+    }
+
+    Ok((added, removed))
+}
+
+/// Service function for unassigning a user from a ticket.
+pub async fn unassign_by_ticket_id_and_assignee_id(
+    state: &TiraState,
+    ticket_id: i64,
+    assignee_id: i64,
+    unassigner_id: i64,
 ) -> Result<()> {
-    dao::assignments::update_assignments_by_ticket_id(state, ticket_id, assignee_ids, assigner_id)
-        .await
+    let mut tx = state.pool.begin().await?;
+    let assignments_ended = dao::assignments::unassign_by_ticket_id_and_assignee_ids(
+        &mut tx,
+        ticket_id,
+        &[assignee_id],
+        unassigner_id,
+    )
+    .await?;
+    service::check_only_one_row_changed(assignments_ended)?;
+    webhooks::trigger(
+        state,
+        &mut tx,
+        unassigner_id,
+        ticket_id,
+        WebhookEvent::AssignmentDeleted { assignee_id },
+    )
+    .await?;
+    let ticket = dao::tickets::get_ticket_by_id_in_transaction(&mut tx, ticket_id).await?;
+    tx.commit().await?;
+
+    live_events::publish(
+        state,
+        LiveEvent {
+            assignee_id: Some(assignee_id),
+            ..live_events::ticket_event(
+                live_events::ASSIGNMENT_CHANGED_EVENT,
+                unassigner_id,
+                &ticket,
+            )
+        },
+    )
+    .await;
+    Ok(())
+}
+
+/// Service function for retrieving every assignment a ticket ever had, including ended ones.
+pub async fn get_all_assignments_by_ticket_id(
+    state: &TiraState,
+    ticket_id: i64,
+) -> Result<Vec<Assignment>> {
+    dao::tickets::get_all_assignments_by_ticket_id(state, ticket_id).await
 }
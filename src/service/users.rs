@@ -1,7 +1,8 @@
 use crate::{
     dao,
     models::{patch::UpdateUser, Assignment, User},
-    service, TiraState,
+    service::{self, security, ServiceError},
+    TiraState,
 };
 use anyhow::Result;
 
@@ -11,8 +12,29 @@ pub async fn archive_user_by_id(state: &TiraState, user_id: i64) -> Result<()> {
     service::check_only_one_row_changed(users_archived)
 }
 
+/// Checks that no user other than `user_id` already has a username.
+async fn check_username_available(
+    state: &TiraState,
+    username: &str,
+    user_id: Option<i64>,
+) -> Result<()> {
+    match dao::users::get_user_by_username(state, username).await {
+        Ok(existing) if Some(existing.id) == user_id => Ok(()),
+        Ok(_) => {
+            Err(ServiceError::Conflict(format!("Username '{}' is already taken", username)).into())
+        }
+        Err(e) if matches!(e.downcast_ref(), Some(sqlx::Error::RowNotFound)) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
 /// Service function for creating a user.
-pub async fn create_user(state: &TiraState, user: User) -> Result<i64> {
+///
+/// The password is hashed before the user is stored.
+pub async fn create_user(state: &TiraState, mut user: User) -> Result<i64> {
+    check_username_available(state, &user.username, None).await?;
+    user.password = security::hash_password(&state.argon2_params, &user.password).await?;
+    user.password_scheme = security::ARGON2ID_SCHEME.to_string();
     dao::users::create_user(state, user).await
 }
 
@@ -24,22 +46,35 @@ pub async fn get_assignments_by_user_id(
     dao::users::get_assignments_by_user_id(state, user_id).await
 }
 
-/// Service function for retrieving a user by id.
-pub async fn get_user_by_id(state: &TiraState, user_id: i64) -> Result<User> {
-    dao::users::get_user_by_id(state, user_id).await
-}
-
 /// Service function for retrieving users by ids.
 pub async fn get_users_by_ids(state: &TiraState, user_ids: Vec<i64>) -> Result<Vec<User>> {
     dao::users::get_users_by_ids(state, user_ids).await
 }
 
+/// Service function for retrieving a user by id.
+pub async fn get_user_by_id(state: &TiraState, user_id: i64) -> Result<User> {
+    dao::users::get_user_by_id(state, user_id).await
+}
+
 pub async fn get_users(state: &TiraState, filter_archived: Option<bool>) -> Result<Vec<User>> {
     dao::users::get_users(state, filter_archived).await
 }
 
 /// Service function for updating a user by id.
-pub async fn update_user_by_id(state: &TiraState, user: UpdateUser, user_id: i64) -> Result<()> {
+///
+/// A new password is hashed before it is stored.
+pub async fn update_user_by_id(
+    state: &TiraState,
+    mut user: UpdateUser,
+    user_id: i64,
+) -> Result<()> {
+    if let Some(username) = &user.username {
+        check_username_available(state, username, Some(user_id)).await?;
+    }
+    if let Some(password) = &user.password {
+        user.password = Some(security::hash_password(&state.argon2_params, password).await?);
+    }
+
     let users_updated = dao::users::update_user_by_id(state, user, user_id).await?;
     service::check_only_one_row_changed(users_updated)
 }
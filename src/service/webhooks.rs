@@ -0,0 +1,502 @@
+use std::slice;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::{Postgres, Transaction};
+
+use crate::{
+    dao::{self, tickets::TicketBatch, webhooks::DueDelivery},
+    models::{
+        error::FieldError,
+        patch::UpdateWebhook,
+        success::{CommentResponse, TicketResponse},
+        CreateWebhook, User, Webhook, WebhookDelivery,
+    },
+    service::{self, RetryConfig, ServiceError},
+    TiraState,
+};
+
+pub const TICKET_CREATED_EVENT: &str = "ticket.created";
+
+pub const TICKET_UPDATED_EVENT: &str = "ticket.updated";
+
+pub const COMMENT_CREATED_EVENT: &str = "comment.created";
+
+pub const COMMENT_EDITED_EVENT: &str = "comment.edited";
+
+pub const ASSIGNMENT_CREATED_EVENT: &str = "assignment.created";
+
+pub const ASSIGNMENT_DELETED_EVENT: &str = "assignment.deleted";
+
+pub const EVENTS: [&str; 6] = [
+    TICKET_CREATED_EVENT,
+    TICKET_UPDATED_EVENT,
+    COMMENT_CREATED_EVENT,
+    COMMENT_EDITED_EVENT,
+    ASSIGNMENT_CREATED_EVENT,
+    ASSIGNMENT_DELETED_EVENT,
+];
+
+/// Status of deliveries that still have to be made.
+pub const PENDING_STATUS: &str = "pending";
+
+/// Status of deliveries the webhook accepted.
+pub const DELIVERED_STATUS: &str = "delivered";
+
+/// Status of deliveries that failed too many times and are no longer tried.
+pub const DEAD_STATUS: &str = "dead";
+
+const DELIVERY_STATUSES: [&str; 3] = [PENDING_STATUS, DELIVERED_STATUS, DEAD_STATUS];
+
+/// How many deliveries the webhook worker claims at once.
+const DELIVERY_BATCH_SIZE: i64 = 10;
+
+/// Header with the HMAC-SHA256 of the body, keyed with the webhook's secret.
+const SIGNATURE_HEADER: &str = "X-Tira-Signature";
+
+const EVENT_HEADER: &str = "X-Tira-Event";
+
+const DELIVERY_HEADER: &str = "X-Tira-Delivery";
+
+/// Something that happened to a ticket that webhooks can subscribe to.
+pub enum WebhookEvent {
+    TicketCreated,
+    TicketUpdated,
+    CommentCreated { comment_id: i64 },
+    CommentEdited { comment_id: i64 },
+    AssignmentCreated { assignee_id: i64 },
+    AssignmentDeleted { assignee_id: i64 },
+}
+
+impl WebhookEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::TicketCreated => TICKET_CREATED_EVENT,
+            WebhookEvent::TicketUpdated => TICKET_UPDATED_EVENT,
+            WebhookEvent::CommentCreated { .. } => COMMENT_CREATED_EVENT,
+            WebhookEvent::CommentEdited { .. } => COMMENT_EDITED_EVENT,
+            WebhookEvent::AssignmentCreated { .. } => ASSIGNMENT_CREATED_EVENT,
+            WebhookEvent::AssignmentDeleted { .. } => ASSIGNMENT_DELETED_EVENT,
+        }
+    }
+}
+
+/// Body of the requests made to webhooks.
+#[derive(Serialize)]
+struct WebhookPayload {
+    event: &'static str,
+    actor: User,
+    ticket: TicketResponse,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<CommentResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    assignee: Option<User>,
+}
+
+/// Service function for queueing the delivery of an event to the webhooks subscribed to it.
+///
+/// `actor_id` is the user that caused the event. The deliveries are part of the transaction that
+/// made the change, so the payload is built from the ticket as that transaction sees it.
+pub async fn trigger(
+    state: &TiraState,
+    tx: &mut Transaction<'_, Postgres>,
+    actor_id: i64,
+    ticket_id: i64,
+    event: WebhookEvent,
+) -> Result<()> {
+    let webhook_ids = dao::webhooks::get_webhook_ids_by_event(state, event.name()).await?;
+    if webhook_ids.is_empty() {
+        return Ok(());
+    }
+
+    let ticket = dao::tickets::get_ticket_by_id_in_transaction(tx, ticket_id).await?;
+    let comment = match event {
+        WebhookEvent::CommentCreated { comment_id }
+        | WebhookEvent::CommentEdited { comment_id } => {
+            Some(dao::comments::get_comment_by_id_in_transaction(tx, comment_id).await?)
+        }
+        _ => None,
+    };
+    let assignee_id = match event {
+        WebhookEvent::AssignmentCreated { assignee_id }
+        | WebhookEvent::AssignmentDeleted { assignee_id } => Some(assignee_id),
+        _ => None,
+    };
+
+    let reactions = match &comment {
+        Some(comment) => {
+            service::comments::get_reactions_by_comment_ids(state, &[comment.id], actor_id)
+                .await?
+                .remove(&comment.id)
+                .unwrap_or_default()
+        }
+        None => Vec::new(),
+    };
+
+    let mut extra_user_ids = vec![actor_id];
+    extra_user_ids.extend(comment.as_ref().map(|comment| comment.commenter_id));
+    extra_user_ids.extend(assignee_id);
+    let batch =
+        TicketBatch::load_in_transaction(state, tx, slice::from_ref(&ticket), extra_user_ids)
+            .await?;
+
+    let payload = WebhookPayload {
+        event: event.name(),
+        actor: batch.user(actor_id)?,
+        comment: comment
+            .map(|comment| batch.comment_response(comment, reactions))
+            .transpose()?,
+        assignee: assignee_id
+            .map(|assignee_id| batch.user(assignee_id))
+            .transpose()?,
+        ticket: batch.ticket_response(ticket)?,
+    };
+    let payload = serde_json::to_string(&payload)?;
+
+    dao::webhooks::create_deliveries(tx, &webhook_ids, event.name(), &payload).await
+}
+
+/// Returns the value of the signature header for a body.
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Makes the deliveries that are due until the program ends.
+///
+/// Failed deliveries are retried with exponential backoff and dead-lettered after
+/// `config.max_attempts` attempts. Several workers can run at once since claimed deliveries are
+/// skipped by the others.
+pub async fn run_webhook_worker(state: TiraState, client: reqwest::Client, config: RetryConfig) {
+    info!("Starting webhook worker");
+    loop {
+        match send_due_deliveries(&state, &client, &config).await {
+            Ok(0) => tokio::time::sleep(config.poll_interval).await,
+            Ok(_) => (),
+            Err(e) => {
+                error!("Could not process webhook deliveries: {:#}", e);
+                tokio::time::sleep(config.poll_interval).await;
+            }
+        }
+    }
+}
+
+/// Makes a request to a webhook.
+///
+/// Returns the status code of the response, if there was one, and whether it was a success.
+async fn deliver(client: &reqwest::Client, delivery: &DueDelivery) -> (Option<i32>, Result<()>) {
+    let response = client
+        .post(&delivery.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, &delivery.event)
+        .header(DELIVERY_HEADER, delivery.id)
+        .header(SIGNATURE_HEADER, sign(&delivery.secret, &delivery.payload))
+        .body(delivery.payload.clone())
+        .send()
+        .await;
+
+    match response {
+        Ok(response) => {
+            let status = response.status();
+            let result = if status.is_success() {
+                Ok(())
+            } else {
+                Err(anyhow!("Webhook responded with {}", status))
+            };
+            (Some(status.as_u16().into()), result)
+        }
+        Err(e) => (None, Err(e).context("Could not reach webhook")),
+    }
+}
+
+/// Returns how long the webhook worker keeps deliveries claimed, which is longer than a batch
+/// of deliveries can take when every webhook times out.
+pub fn delivery_lease(timeout: Duration) -> Duration {
+    timeout * DELIVERY_BATCH_SIZE as u32 + Duration::from_secs(60)
+}
+
+/// Makes a batch of due deliveries.
+///
+/// The deliveries are claimed before they are made and every one is marked on its own right
+/// after, so that a failure to mark one never causes the others to be made again. Returns how
+/// many deliveries were claimed.
+async fn send_due_deliveries(
+    state: &TiraState,
+    client: &reqwest::Client,
+    config: &RetryConfig,
+) -> Result<usize> {
+    let deliveries = dao::webhooks::claim_due_deliveries(
+        state,
+        PENDING_STATUS,
+        DELIVERY_BATCH_SIZE,
+        config.lease.as_secs() as i64,
+    )
+    .await?;
+
+    for delivery in &deliveries {
+        let (response_status, result) = deliver(client, delivery).await;
+        let marked = match result {
+            Ok(()) => {
+                info!("Delivered {} to {}", delivery.event, delivery.url);
+                dao::webhooks::mark_delivery_delivered(
+                    state,
+                    delivery.id,
+                    DELIVERED_STATUS,
+                    response_status,
+                )
+                .await
+            }
+            Err(e) => {
+                let attempts = delivery.attempts + 1;
+                let (status, retry_in) = if attempts >= config.max_attempts {
+                    error!(
+                        "Giving up on webhook delivery {} after {} attempts: {:#}",
+                        delivery.id, attempts, e
+                    );
+                    (DEAD_STATUS, Duration::ZERO)
+                } else {
+                    let retry_in = config.retry_delay(attempts);
+                    warn!(
+                        "Could not make webhook delivery {}, retrying in {:?}: {:#}",
+                        delivery.id, retry_in, e
+                    );
+                    (PENDING_STATUS, retry_in)
+                };
+
+                dao::webhooks::mark_delivery_failed(
+                    state,
+                    delivery.id,
+                    status,
+                    response_status,
+                    &format!("{:#}", e),
+                    retry_in.as_secs() as i64,
+                )
+                .await
+            }
+        };
+        if let Err(e) = marked {
+            error!("Could not mark webhook delivery {}: {:?}", delivery.id, e);
+        }
+    }
+
+    Ok(deliveries.len())
+}
+
+/// Checks the fields of a webhook that are set.
+fn check_webhook(url: Option<&str>, secret: Option<&str>, events: Option<&[String]>) -> Result<()> {
+    let mut field_errors = Vec::new();
+
+    if let Some(url) = url {
+        match reqwest::Url::parse(url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => (),
+            _ => field_errors.push(FieldError::new("url", "URL must be an http(s) URL")),
+        }
+    }
+
+    if secret == Some("") {
+        field_errors.push(FieldError::new("secret", "Secret cannot be empty"));
+    }
+
+    if let Some(events) = events {
+        if events.is_empty() {
+            field_errors.push(FieldError::new("events", "Events cannot be empty"));
+        }
+        if let Some(event) = events
+            .iter()
+            .find(|event| !EVENTS.contains(&event.as_str()))
+        {
+            let message = format!("Unknown event '{}'", event);
+            field_errors.push(FieldError::new("events", &message));
+        }
+    }
+
+    if field_errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ServiceError::Validation(field_errors).into())
+    }
+}
+
+/// Service function for creating a webhook.
+pub async fn create_webhook(
+    state: &TiraState,
+    webhook: &CreateWebhook,
+    creator_id: i64,
+) -> Result<i64> {
+    check_webhook(
+        Some(&webhook.url),
+        Some(&webhook.secret),
+        Some(&webhook.events),
+    )?;
+    dao::webhooks::create_webhook(state, webhook, creator_id).await
+}
+
+/// Service function for retrieving every webhook.
+pub async fn get_webhooks(state: &TiraState) -> Result<Vec<Webhook>> {
+    dao::webhooks::get_webhooks(state).await
+}
+
+/// Service function for retrieving a webhook by id.
+pub async fn get_webhook_by_id(state: &TiraState, id: i64) -> Result<Webhook> {
+    dao::webhooks::get_webhook_by_id(state, id).await
+}
+
+/// Service function for updating a webhook by id.
+pub async fn update_webhook_by_id(
+    state: &TiraState,
+    webhook: &UpdateWebhook,
+    id: i64,
+) -> Result<()> {
+    check_webhook(
+        webhook.url.as_deref(),
+        webhook.secret.as_deref(),
+        webhook.events.as_deref(),
+    )?;
+    let webhooks_updated = dao::webhooks::update_webhook_by_id(state, webhook, id).await?;
+    service::check_only_one_row_changed(webhooks_updated)
+}
+
+/// Service function for deleting a webhook by id.
+pub async fn delete_webhook_by_id(state: &TiraState, id: i64) -> Result<()> {
+    let webhooks_deleted = dao::webhooks::delete_webhook_by_id(state, id).await?;
+    service::check_only_one_row_changed(webhooks_deleted)
+}
+
+/// Checks that a status is one of the delivery statuses.
+fn check_delivery_status(status: &Option<String>) -> Result<()> {
+    match status {
+        Some(status) if !DELIVERY_STATUSES.contains(&status.as_str()) => {
+            let message = format!(
+                "Status must be '{}', '{}', or '{}'",
+                PENDING_STATUS, DELIVERED_STATUS, DEAD_STATUS
+            );
+            Err(ServiceError::BadRequest(message).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Service function for counting the deliveries of a webhook.
+pub async fn count_deliveries_by_webhook_id(
+    state: &TiraState,
+    webhook_id: i64,
+    status: Option<String>,
+) -> Result<i64> {
+    check_delivery_status(&status)?;
+    dao::webhooks::count_deliveries_by_webhook_id(state, webhook_id, status).await
+}
+
+/// Service function for retrieving the deliveries of a webhook.
+pub async fn get_deliveries_by_webhook_id(
+    state: &TiraState,
+    webhook_id: i64,
+    status: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Vec<WebhookDelivery>> {
+    check_delivery_status(&status)?;
+    dao::webhooks::get_webhook_by_id(state, webhook_id).await?;
+
+    let limit = limit.unwrap_or(10);
+    let offset = offset.unwrap_or(0);
+    if limit < 0 || offset < 0 {
+        return Err(
+            ServiceError::BadRequest("Limit and offset cannot be negative".to_string()).into(),
+        );
+    }
+
+    dao::webhooks::get_deliveries_by_webhook_id(state, webhook_id, status, limit, offset).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::HeaderMap, http::StatusCode, routing::post, Router};
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+
+    /// What the stand-in webhook received.
+    type Received = Arc<Mutex<Vec<(HeaderMap, String)>>>;
+
+    /// Serves a webhook that answers with `status` on a free local port and returns its URL.
+    async fn serve_webhook(status: StatusCode, received: Received) -> String {
+        let app = Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: String| async move {
+                received.lock().unwrap().push((headers, body));
+                status
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}/hook", address)
+    }
+
+    fn due_delivery(url: String) -> DueDelivery {
+        DueDelivery {
+            id: 7,
+            event: TICKET_CREATED_EVENT.to_string(),
+            payload: r#"{"event":"ticket.created"}"#.to_string(),
+            attempts: 0,
+            url,
+            secret: "0123456789abcdef".to_string(),
+        }
+    }
+
+    #[test]
+    fn sign_matches_known_hmac() {
+        assert_eq!(
+            sign("key", "The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[tokio::test]
+    async fn deliver_sends_signed_payload() {
+        let received = Received::default();
+        let url = serve_webhook(StatusCode::NO_CONTENT, received.clone()).await;
+        let delivery = due_delivery(url);
+
+        let (status, result) = deliver(&reqwest::Client::new(), &delivery).await;
+        assert_eq!(status, Some(204));
+        assert!(result.is_ok());
+
+        let received = received.lock().unwrap();
+        let (headers, body) = &received[0];
+        assert_eq!(body, &delivery.payload);
+        assert_eq!(headers["content-type"], "application/json");
+        assert_eq!(headers[EVENT_HEADER], TICKET_CREATED_EVENT);
+        assert_eq!(headers[DELIVERY_HEADER], "7");
+        assert_eq!(
+            headers[SIGNATURE_HEADER],
+            sign(&delivery.secret, &delivery.payload).as_str()
+        );
+    }
+
+    #[tokio::test]
+    async fn deliver_fails_on_error_status() {
+        let url = serve_webhook(StatusCode::INTERNAL_SERVER_ERROR, Received::default()).await;
+
+        let (status, result) = deliver(&reqwest::Client::new(), &due_delivery(url)).await;
+        assert_eq!(status, Some(500));
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn deliver_fails_without_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        drop(listener);
+
+        let (status, result) = deliver(&reqwest::Client::new(), &due_delivery(url)).await;
+        assert_eq!(status, None);
+        assert!(result.is_err());
+    }
+}
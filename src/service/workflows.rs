@@ -0,0 +1,175 @@
+use crate::{
+    dao,
+    models::{
+        error::FieldError, patch::UpdateTicketStatus, StatusTransition, TicketPriority,
+        TicketStatus, Workflow,
+    },
+    service::{self, ServiceError},
+    TiraState,
+};
+use anyhow::Result;
+use sqlx::{Postgres, Transaction};
+use std::collections::HashSet;
+
+/// Service function for creating a status.
+pub async fn create_status(state: &TiraState, status: &TicketStatus) -> Result<()> {
+    if status.name.trim().is_empty() {
+        return Err(ServiceError::invalid_field("name", "Name can not be empty").into());
+    }
+    dao::workflows::create_status(state, status).await
+}
+
+/// Service function for retrieving all statuses.
+pub async fn get_statuses(state: &TiraState) -> Result<Vec<TicketStatus>> {
+    dao::workflows::get_statuses(state).await
+}
+
+/// Service function for updating a status by name.
+pub async fn update_status_by_name(
+    state: &TiraState,
+    name: &str,
+    status: &UpdateTicketStatus,
+) -> Result<()> {
+    let statuses_updated = dao::workflows::update_status_by_name(state, name, status).await?;
+    service::check_only_one_row_changed(statuses_updated)
+}
+
+/// Service function for creating a priority.
+pub async fn create_priority(state: &TiraState, priority: &TicketPriority) -> Result<()> {
+    if priority.name.trim().is_empty() {
+        return Err(ServiceError::invalid_field("name", "Name can not be empty").into());
+    }
+    dao::workflows::create_priority(state, priority).await
+}
+
+/// Service function for retrieving all priorities.
+pub async fn get_priorities(state: &TiraState) -> Result<Vec<TicketPriority>> {
+    dao::workflows::get_priorities(state).await
+}
+
+/// Service function for retrieving the workflow that applies to a category.
+///
+/// Returns the category the workflow belongs to, which is `None` if the category uses the
+/// default workflow, and the workflow's transitions.
+pub async fn get_workflow_by_category_id(
+    state: &TiraState,
+    category_id: Option<i64>,
+) -> Result<(Option<i64>, Vec<StatusTransition>)> {
+    if let Some(category_id) = category_id {
+        dao::categories::get_category_by_id(state, category_id).await?;
+
+        let transitions =
+            dao::workflows::get_status_transitions_by_category_id(state, Some(category_id)).await?;
+        if !transitions.is_empty() {
+            return Ok((Some(category_id), transitions));
+        }
+    }
+
+    let transitions = dao::workflows::get_status_transitions_by_category_id(state, None).await?;
+    Ok((None, transitions))
+}
+
+/// Service function for replacing the workflow of a category.
+///
+/// A `category_id` of `None` replaces the default workflow.
+pub async fn replace_workflow_by_category_id(
+    state: &TiraState,
+    category_id: Option<i64>,
+    workflow: Workflow,
+) -> Result<()> {
+    if let Some(category_id) = category_id {
+        dao::categories::get_category_by_id(state, category_id).await?;
+    }
+
+    let status_names: HashSet<_> = dao::workflows::get_statuses(state)
+        .await?
+        .into_iter()
+        .map(|status| status.name)
+        .collect();
+
+    let mut field_errors = Vec::new();
+    let mut seen = HashSet::new();
+    let mut transitions = Vec::new();
+    for transition in workflow.transitions {
+        let unknown_status = transition
+            .from_status
+            .iter()
+            .chain([&transition.to_status])
+            .find(|name| !status_names.contains(*name));
+        if let Some(name) = unknown_status {
+            let message = format!("'{}' is not a status", name);
+            field_errors.push(FieldError::new("transitions", &message));
+        } else if seen.insert((transition.from_status.clone(), transition.to_status.clone())) {
+            transitions.push(transition);
+        }
+    }
+    if !transitions
+        .iter()
+        .any(|transition| transition.from_status.is_none())
+    {
+        field_errors.push(FieldError::new(
+            "transitions",
+            "A workflow needs at least one status that new tickets can start in",
+        ));
+    }
+
+    if !field_errors.is_empty() {
+        return Err(ServiceError::Validation(field_errors).into());
+    }
+
+    let mut tx = state.pool.begin().await?;
+    dao::workflows::replace_status_transitions_by_category_id(&mut tx, category_id, &transitions)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Service function for removing the own workflow of a category so it uses the default one again.
+pub async fn delete_workflow_by_category_id(state: &TiraState, category_id: i64) -> Result<()> {
+    dao::categories::get_category_by_id(state, category_id).await?;
+
+    let mut tx = state.pool.begin().await?;
+    dao::workflows::replace_status_transitions_by_category_id(&mut tx, Some(category_id), &[])
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Checks that the workflow of a category allows a ticket to move to `to_status`.
+///
+/// A `from_status` of `None` checks a new ticket.
+/// Returns the error for the `status` field if the transition is not allowed.
+pub async fn check_status_transition(
+    tx: &mut Transaction<'_, Postgres>,
+    category_id: Option<i64>,
+    from_status: Option<&str>,
+    to_status: &str,
+) -> Result<Option<FieldError>> {
+    if dao::workflows::is_transition_allowed(tx, category_id, from_status, to_status).await? {
+        return Ok(None);
+    }
+
+    let message = match from_status {
+        Some(from_status) => format!(
+            "Tickets cannot move from '{}' to '{}'",
+            from_status, to_status
+        ),
+        None => format!("New tickets cannot start in '{}'", to_status),
+    };
+    Ok(Some(FieldError::new("status", &message)))
+}
+
+/// Checks that a priority exists.
+///
+/// Returns the error for the `priority` field if it does not.
+pub async fn check_priority(
+    tx: &mut Transaction<'_, Postgres>,
+    priority: &str,
+) -> Result<Option<FieldError>> {
+    if dao::workflows::priority_exists(tx, priority).await? {
+        Ok(None)
+    } else {
+        let message = format!("'{}' is not a priority", priority);
+        Ok(Some(FieldError::new("priority", &message)))
+    }
+}